@@ -0,0 +1,479 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Cursor},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        Arc,
+        mpsc::{self, Sender},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{error, info};
+
+use crate::{
+    api::remote::RemoteError,
+    bus::AppCommand,
+    hits::{
+        processor::HitProcessResult,
+        storage::{HitOrder, HitQuery, HitStorage, SharedStorage},
+    },
+    report::export::{HitRow, collect_rows},
+    sessions::Session,
+    targets::{TargetInfo, settings::Target},
+    timer::ShotTiming,
+};
+
+mod remote;
+mod ws;
+
+/// Served at `/`, shows live targets of lanes given as `?lanes=a,b`
+const SCOREBOARD_PAGE: &str = include_str!("../../web/scoreboard.html");
+
+type Reply = Response<Cursor<Vec<u8>>>;
+
+/// Embedded HTTP server for scoreboards on other devices
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    /// Address to listen on, only this machine by default, 0.0.0.0 makes it reachable
+    /// from other devices
+    pub address: String,
+    /// Accept commands posted to `/api/commands`
+    pub remote_control: bool,
+    /// Secret that commands must carry as `Authorization: Bearer <token>`, commands are
+    /// refused while it is empty
+    pub token: String,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:8080".to_string(),
+            remote_control: false,
+            token: String::new(),
+        }
+    }
+}
+
+/// Pushed to WebSocket clients as JSON as it happens
+#[derive(Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ApiEvent {
+    NewHit {
+        lane: String,
+        timestamp: DateTime<Local>,
+    },
+    ProcessedHit {
+        timestamp: DateTime<Local>,
+        result: HitProcessResult,
+    },
+    TargetRecognized {
+        lane: String,
+        target: TargetInfo,
+    },
+    /// Lanes were loaded, added or removed
+    LanesChanged {
+        lanes: Vec<LaneEntry>,
+    },
+    /// Target selected for lane, none if cleared
+    LaneTargetChanged {
+        lane: String,
+        target_id: Option<String>,
+    },
+    SessionChanged {
+        session: Option<Session>,
+    },
+    /// Shot timer waits for its random delay before start signal
+    ShotTimerArmed,
+    ShotTimerStarted {
+        at: DateTime<Local>,
+    },
+    ShotTimerStopped,
+    ShotTimed {
+        timestamp: DateTime<Local>,
+        timing: ShotTiming,
+    },
+    DrillChanged {
+        running: bool,
+    },
+}
+
+impl ApiEvent {
+    /// Latest event of kind that describes current state, replayed to clients connecting later
+    fn state_key(&self) -> Option<&'static str> {
+        match self {
+            ApiEvent::SessionChanged { .. } => Some("session"),
+            ApiEvent::LanesChanged { .. } => Some("lanes"),
+            ApiEvent::ShotTimerArmed
+            | ApiEvent::ShotTimerStarted { .. }
+            | ApiEvent::ShotTimerStopped => Some("shot_timer"),
+            ApiEvent::DrillChanged { .. } => Some("drill"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct LaneEntry {
+    pub name: String,
+    pub target_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TargetEntry<'a> {
+    id: &'a str,
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct SessionEntry {
+    #[serde(flatten)]
+    session: Session,
+    hits: Vec<HitRow>,
+}
+
+/// Serve REST API, event stream and remote commands, none if disabled or address is taken
+pub fn start_api_server(
+    config: &ApiConfig,
+    storage: SharedStorage,
+    targets: Arc<HashMap<String, Target>>,
+    command_tx: Sender<AppCommand>,
+) -> Option<Sender<ApiEvent>> {
+    if !config.enabled {
+        return None;
+    }
+    let listener = match TcpListener::bind(&config.address) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("failed to start api server on {}: {e:?}", config.address);
+            return None;
+        }
+    };
+    // Requests other than event streams are passed on to it
+    let server = match Server::http("127.0.0.1:0") {
+        Ok(v) => v,
+        Err(e) => {
+            error!("failed to start http server: {e:?}");
+            return None;
+        }
+    };
+    let http_addr = server
+        .server_addr()
+        .to_ip()
+        .expect("http server listens on ip address");
+    info!("Serving api on http://{}", config.address);
+
+    let (tx, rx) = mpsc::channel();
+    let clients = ws::start_broadcast(rx);
+    start_listener(listener, http_addr, clients.clone());
+
+    let remote_control = config.remote_control;
+    let token = config.token.clone();
+    std::thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let response = match (request.method(), path(&request)) {
+                (Method::Post, "/api/commands") => {
+                    let (activity, lanes) = {
+                        let clients = clients.lock().unwrap();
+                        (clients.activity, clients.lanes.clone())
+                    };
+                    let result = if remote_control {
+                        remote::authorize(&request, &token)
+                            .and_then(|()| remote::parse(&mut request))
+                            .and_then(|command| {
+                                info!("Remote command {command:?}");
+                                let result = remote::execute(
+                                    command.clone(),
+                                    &lanes,
+                                    &targets,
+                                    activity,
+                                    &command_tx,
+                                )?;
+                                // Next command sees it before the app reports back
+                                clients.lock().unwrap().activity.expect(&command);
+                                Ok(result)
+                            })
+                    } else {
+                        Err(RemoteError::Disabled)
+                    };
+                    match result {
+                        Ok(result) => {
+                            json_response(serde_json::json!({ "ok": true, "result": result }), 200)
+                        }
+                        Err(e) => {
+                            error!("refused remote command: {e}");
+                            json_response(serde_json::json!({ "error": e.to_string() }), e.status())
+                        }
+                    }
+                }
+                (Method::Get, _) => {
+                    let lanes = clients.lock().unwrap().lanes.clone();
+                    let routed =
+                        route(&request, storage.lock().unwrap().as_mut(), &lanes, &targets);
+                    let response = match routed {
+                        Ok(Some(response)) => response,
+                        Ok(None) => json_response(serde_json::json!({ "error": "not found" }), 404),
+                        Err(e) => {
+                            error!("failed to serve {}: {e:?}", request.url());
+                            json_response(serde_json::json!({ "error": e.to_string() }), 500)
+                        }
+                    };
+                    // Scoreboards may be served from elsewhere, commands may not
+                    response.with_header(header("Access-Control-Allow-Origin", "*"))
+                }
+                _ => json_response(serde_json::json!({ "error": "method not allowed" }), 405),
+            };
+            if let Err(e) = request.respond(response) {
+                error!("failed to respond: {e:?}");
+            }
+        }
+    });
+
+    Some(tx)
+}
+
+/// Take event streams on connections to api address and forward all else to http server.
+/// Streams upgraded by tiny_http can't be read while they are written to, so clients closing
+/// or pinging them would go unnoticed.
+fn start_listener(listener: TcpListener, http_addr: SocketAddr, clients: ws::Clients) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("failed to accept api connection: {e:?}");
+                    continue;
+                }
+            };
+            let clients = clients.clone();
+            std::thread::spawn(move || {
+                let result = match is_event_stream(&stream) {
+                    Ok(true) => ws::serve(stream, &clients),
+                    Ok(false) => forward(stream, http_addr).map_err(anyhow::Error::from),
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = result {
+                    error!("api connection failed: {e:?}");
+                }
+            });
+        }
+    });
+}
+
+/// Whether connection starts with request of event stream, looked at without reading it
+fn is_event_stream(stream: &TcpStream) -> io::Result<bool> {
+    const REQUEST: &[u8] = b"GET /api/events";
+    let mut buf = [0; REQUEST.len() + 1];
+    loop {
+        let n = stream.peek(&mut buf)?;
+        if n == 0 || !REQUEST.starts_with(&buf[..n.min(REQUEST.len())]) {
+            return Ok(false);
+        }
+        if n == buf.len() {
+            return Ok(matches!(buf[REQUEST.len()], b' ' | b'?'));
+        }
+        // Rest of request line is on its way
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Pass connection on to http server and its responses back until either side closes
+fn forward(client: TcpStream, http_addr: SocketAddr) -> io::Result<()> {
+    let server = TcpStream::connect(http_addr)?;
+    let (mut requests, mut server_in) = (client.try_clone()?, server.try_clone()?);
+    std::thread::spawn(move || {
+        io::copy(&mut requests, &mut server_in).ok();
+        server_in.shutdown(Shutdown::Write).ok();
+    });
+    let (mut responses, mut client_out) = (server, client);
+    io::copy(&mut responses, &mut client_out)?;
+    client_out.shutdown(Shutdown::Both).ok();
+    Ok(())
+}
+
+/// Response to request, none if nothing is there
+fn route(
+    request: &Request,
+    storage: &mut dyn HitStorage,
+    lanes: &[LaneEntry],
+    targets: &HashMap<String, Target>,
+) -> Result<Option<Reply>> {
+    let path = path(request);
+    let params = query_params(request.url());
+
+    let body = match path.split('/').collect::<Vec<_>>().as_slice() {
+        ["", ""] | ["", "scoreboard"] => {
+            return Ok(Some(
+                Response::from_data(SCOREBOARD_PAGE)
+                    .with_header(header("Content-Type", "text/html; charset=utf-8")),
+            ));
+        }
+        ["", "api", "lanes"] => serde_json::to_value(lanes)?,
+        ["", "api", "sessions"] => {
+            let mut sessions = storage.get_all_sessions()?;
+            sessions.sort_by_key(|s| s.started_at);
+            serde_json::to_value(sessions)?
+        }
+        ["", "api", "sessions", id] => {
+            let Some(session) = storage.load_session(id)? else {
+                return Ok(None);
+            };
+            let query = HitQuery {
+                session: Some(id.to_string()),
+                ..Default::default()
+            };
+            let hits = collect_rows(storage, &[query])?;
+            serde_json::to_value(SessionEntry { session, hits })?
+        }
+        ["", "api", "hits"] => {
+            let query = hit_query(&params)?;
+            serde_json::to_value(collect_rows(storage, &[query])?)?
+        }
+        ["", "api", "targets"] => {
+            let mut entries: Vec<_> = targets
+                .values()
+                .map(|t| TargetEntry {
+                    id: t.id(),
+                    name: t.name(),
+                })
+                .collect();
+            entries.sort_by_key(|t| t.id);
+            serde_json::to_value(entries)?
+        }
+        ["", "api", "targets", id, "preview"] => {
+            let Some(target) = targets.get(*id) else {
+                return Ok(None);
+            };
+            let Ok(data) = fs::read(target.preview_path()) else {
+                return Ok(None);
+            };
+            return Ok(Some(
+                Response::from_data(data).with_header(header("Content-Type", "image/png")),
+            ));
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(json_response(body, 200)))
+}
+
+/// Filter of `/api/hits`, `last` keeps only that many latest hits
+fn hit_query(params: &HashMap<String, String>) -> Result<HitQuery> {
+    let time = |name: &str| {
+        params
+            .get(name)
+            .map(|v| {
+                DateTime::parse_from_rfc3339(v)
+                    .map(|t| t.with_timezone(&Local))
+                    .with_context(|| format!("invalid {name} '{v}', expected RFC 3339 time"))
+            })
+            .transpose()
+    };
+    let last = params
+        .get("last")
+        .map(|v| v.parse::<usize>().context("invalid last"))
+        .transpose()?;
+    Ok(HitQuery {
+        from: time("from")?,
+        to: time("to")?,
+        session: params.get("session").cloned(),
+        shooter: params.get("shooter").cloned(),
+        lane: params.get("lane").cloned(),
+        target_id: params.get("target").cloned(),
+        order: if last.is_some() {
+            HitOrder::NewestFirst
+        } else {
+            HitOrder::OldestFirst
+        },
+        limit: last,
+        ..Default::default()
+    })
+}
+
+fn path(request: &Request) -> &str {
+    let url = request.url();
+    url.split_once('?').map_or(url, |(path, _)| path)
+}
+
+fn query_params(url: &str) -> HashMap<String, String> {
+    let Some((_, query)) = url.split_once('?') else {
+        return HashMap::new();
+    };
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (percent_decode(k), percent_decode(v)))
+        .collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = &bytes[i + 1..i + 3];
+                // from_str_radix would take a sign such as "%+1" too
+                match std::str::from_utf8(hex)
+                    .ok()
+                    .filter(|_| hex.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(b) => {
+                        out.push(b);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn json_response(body: serde_json::Value, status: u16) -> Reply {
+    Response::from_data(body.to_string())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("invalid header")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_decodes_sequences_and_plus() {
+        assert_eq!(percent_decode("Lane%201"), "Lane 1");
+        assert_eq!(percent_decode("a+b%2Bc"), "a b+c");
+        assert_eq!(percent_decode("%C3%A9t%c3%a9"), "été");
+    }
+
+    #[test]
+    fn percent_decode_keeps_broken_sequences() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%4"), "%4");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        // Sign is no hex digit, plus stays a space
+        assert_eq!(percent_decode("%+1"), "% 1");
+    }
+
+    #[test]
+    fn percent_decode_replaces_invalid_utf8() {
+        assert_eq!(percent_decode("a%FFb"), "a\u{fffd}b");
+    }
+}
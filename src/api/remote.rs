@@ -0,0 +1,327 @@
+use std::{
+    collections::HashMap,
+    sync::mpsc::{self, Sender},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::Value;
+use tiny_http::Request;
+
+use crate::{
+    api::{ApiEvent, LaneEntry},
+    bus::{AppCommand, CommandReply},
+    drills::load_drills,
+    targets::settings::Target,
+    timer::ShotTimerConfig,
+};
+
+/// How long caller waits for app to carry out command
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Command from tablet or club scoring system, posted as JSON to `/api/commands`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum RemoteCommand {
+    StartSession {
+        shooter: String,
+    },
+    EndSession,
+    /// No target clears lane target
+    SelectTarget {
+        lane: String,
+        target: Option<String>,
+    },
+    StartDrill {
+        drill: String,
+    },
+    AbortDrill,
+    /// Delays and par in seconds
+    StartShotTimer {
+        #[serde(default = "default_min_delay")]
+        min_delay: f32,
+        #[serde(default = "default_max_delay")]
+        max_delay: f32,
+        par: Option<f32>,
+    },
+    StopShotTimer,
+    /// Look for lane target now instead of waiting for periodic recognition
+    RecognizeTarget {
+        lane: String,
+    },
+}
+
+fn default_min_delay() -> f32 {
+    2.0
+}
+
+fn default_max_delay() -> f32 {
+    4.0
+}
+
+/// What is going on in the app, commands clashing with it are refused
+#[derive(Default, Clone, Copy)]
+pub struct Activity {
+    pub session: bool,
+    pub drill: bool,
+    pub shot_timer: bool,
+}
+
+impl Activity {
+    /// Follow what the app reports
+    pub fn update(&mut self, event: &ApiEvent) {
+        match event {
+            ApiEvent::SessionChanged { session } => self.session = session.is_some(),
+            ApiEvent::DrillChanged { running } => self.drill = *running,
+            ApiEvent::ShotTimerArmed | ApiEvent::ShotTimerStarted { .. } => self.shot_timer = true,
+            ApiEvent::ShotTimerStopped => self.shot_timer = false,
+            _ => {}
+        }
+    }
+
+    /// Take effect of command the app carried out, before its event arrives
+    pub fn expect(&mut self, command: &RemoteCommand) {
+        match command {
+            RemoteCommand::StartSession { .. } => self.session = true,
+            RemoteCommand::EndSession => self.session = false,
+            RemoteCommand::StartDrill { .. } => self.drill = true,
+            RemoteCommand::AbortDrill => self.drill = false,
+            RemoteCommand::StartShotTimer { .. } => self.shot_timer = true,
+            RemoteCommand::StopShotTimer => self.shot_timer = false,
+            _ => {}
+        }
+    }
+}
+
+/// Why command was refused, shown to caller with matching HTTP status
+#[derive(Debug)]
+pub enum RemoteError {
+    Disabled,
+    Unauthorized,
+    NotJson,
+    Invalid(String),
+    NotFound(String),
+    Conflict(String),
+    /// App could not carry out accepted command
+    Failed(String),
+    /// App did not answer in time
+    Timeout,
+}
+
+impl RemoteError {
+    pub fn status(&self) -> u16 {
+        match self {
+            RemoteError::Disabled => 403,
+            RemoteError::Unauthorized => 401,
+            RemoteError::NotJson => 415,
+            RemoteError::Invalid(_) => 400,
+            RemoteError::NotFound(_) => 404,
+            RemoteError::Conflict(_) => 409,
+            RemoteError::Failed(_) => 422,
+            RemoteError::Timeout => 504,
+        }
+    }
+}
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoteError::Disabled => write!(f, "remote control is disabled in config"),
+            RemoteError::Unauthorized => write!(f, "missing or wrong token"),
+            RemoteError::NotJson => write!(f, "content type must be application/json"),
+            RemoteError::Timeout => write!(f, "app did not answer in time"),
+            RemoteError::Invalid(e)
+            | RemoteError::NotFound(e)
+            | RemoteError::Conflict(e)
+            | RemoteError::Failed(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Check bearer token of request, an empty token in config refuses everyone
+pub fn authorize(request: &Request, token: &str) -> Result<(), RemoteError> {
+    let given = header_value(request, "Authorization")
+        .and_then(|v| v.strip_prefix("Bearer ").map(str::to_string));
+    match given {
+        Some(given)
+            if !token.is_empty() && constant_time_eq(given.as_bytes(), token.as_bytes()) =>
+        {
+            Ok(())
+        }
+        _ => Err(RemoteError::Unauthorized),
+    }
+}
+
+/// Read posted JSON command, other content types are refused so plain web forms can't post them
+pub fn parse(request: &mut Request) -> Result<RemoteCommand, RemoteError> {
+    let is_json = header_value(request, "Content-Type").is_some_and(|v| {
+        v.split(';')
+            .next()
+            .is_some_and(|t| t.trim().eq_ignore_ascii_case("application/json"))
+    });
+    if !is_json {
+        return Err(RemoteError::NotJson);
+    }
+
+    let mut body = String::new();
+    request
+        .as_reader()
+        .read_to_string(&mut body)
+        .map_err(|e| RemoteError::Invalid(format!("failed to read body: {e}")))?;
+    serde_json::from_str(&body).map_err(|e| RemoteError::Invalid(format!("invalid command: {e}")))
+}
+
+/// Send command to app and wait for its outcome
+pub fn execute(
+    command: RemoteCommand,
+    lanes: &[LaneEntry],
+    targets: &HashMap<String, Target>,
+    activity: Activity,
+    command_tx: &Sender<AppCommand>,
+) -> Result<Value, RemoteError> {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    let command = to_app_command(command, lanes, targets, activity, reply_tx)?;
+    command_tx
+        .send(command)
+        .expect("failed to send remote command");
+    reply_rx
+        .recv_timeout(REPLY_TIMEOUT)
+        .map_err(|_| RemoteError::Timeout)?
+        .map_err(RemoteError::Failed)
+}
+
+/// Check command against current lanes, targets, drills and session, and map it to app command
+fn to_app_command(
+    command: RemoteCommand,
+    lanes: &[LaneEntry],
+    targets: &HashMap<String, Target>,
+    activity: Activity,
+    reply: CommandReply,
+) -> Result<AppCommand, RemoteError> {
+    let reply = Some(reply);
+    let known_lane = |lane: &str| {
+        if lanes.iter().any(|l| l.name == lane) {
+            Ok(())
+        } else {
+            Err(RemoteError::NotFound(format!("unknown lane '{lane}'")))
+        }
+    };
+
+    Ok(match command {
+        RemoteCommand::StartSession { shooter } => {
+            let shooter = shooter.trim().to_string();
+            if shooter.is_empty() {
+                return Err(RemoteError::Invalid(
+                    "shooter must not be empty".to_string(),
+                ));
+            }
+            if activity.session {
+                return Err(RemoteError::Conflict(
+                    "a session is running already, end it first".to_string(),
+                ));
+            }
+            AppCommand::StartSession { shooter, reply }
+        }
+        RemoteCommand::EndSession => {
+            if !activity.session {
+                return Err(RemoteError::Conflict("no session is running".to_string()));
+            }
+            AppCommand::EndSession { reply }
+        }
+        RemoteCommand::SelectTarget { lane, target } => {
+            known_lane(&lane)?;
+            if let Some(id) = &target
+                && !targets.contains_key(id)
+            {
+                return Err(RemoteError::NotFound(format!("unknown target '{id}'")));
+            }
+            AppCommand::SelectTarget {
+                lane,
+                id: target,
+                reply,
+            }
+        }
+        RemoteCommand::StartDrill { drill } => {
+            if !load_drills().contains_key(&drill) {
+                return Err(RemoteError::NotFound(format!("unknown drill '{drill}'")));
+            }
+            if activity.drill {
+                return Err(RemoteError::Conflict(
+                    "a drill is running already, abort it first".to_string(),
+                ));
+            }
+            if activity.shot_timer {
+                return Err(RemoteError::Conflict(
+                    "shot timer is running, stop it before starting a drill".to_string(),
+                ));
+            }
+            AppCommand::StartDrill { id: drill, reply }
+        }
+        RemoteCommand::AbortDrill => {
+            if !activity.drill {
+                return Err(RemoteError::Conflict("no drill is running".to_string()));
+            }
+            AppCommand::AbortDrill { reply }
+        }
+        RemoteCommand::StartShotTimer {
+            min_delay,
+            max_delay,
+            par,
+        } => {
+            let secs = |name: &str, v: f32| {
+                Duration::try_from_secs_f32(v)
+                    .map_err(|_| RemoteError::Invalid(format!("{name} must be positive seconds")))
+            };
+            let min_delay = secs("min_delay", min_delay)?;
+            let max_delay = secs("max_delay", max_delay)?;
+            if max_delay < min_delay {
+                return Err(RemoteError::Invalid(
+                    "max_delay must not be below min_delay".to_string(),
+                ));
+            }
+            if activity.shot_timer {
+                return Err(RemoteError::Conflict(
+                    "shot timer is running already, stop it first".to_string(),
+                ));
+            }
+            if activity.drill {
+                return Err(RemoteError::Conflict(
+                    "a drill is running, it times its own strings".to_string(),
+                ));
+            }
+            AppCommand::StartShotTimer {
+                config: ShotTimerConfig {
+                    min_delay,
+                    max_delay,
+                    par: par.map(|v| secs("par", v)).transpose()?,
+                },
+                reply,
+            }
+        }
+        RemoteCommand::StopShotTimer => {
+            if !activity.shot_timer {
+                return Err(RemoteError::Conflict(
+                    "shot timer is not running".to_string(),
+                ));
+            }
+            AppCommand::StopShotTimer { reply }
+        }
+        RemoteCommand::RecognizeTarget { lane } => {
+            known_lane(&lane)?;
+            AppCommand::RecognizeTarget { lane, reply }
+        }
+    })
+}
+
+fn header_value(request: &Request, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.to_string())
+}
+
+/// Compare without stopping at first difference, so timing tells nothing about the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::TcpStream,
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use tracing::{error, info};
+use tungstenite::Message;
+
+use crate::api::{ApiEvent, LaneEntry, remote::Activity};
+
+/// How often client is read for pings and close while no events come
+const READ_INTERVAL: Duration = Duration::from_millis(200);
+/// Reads give up this soon when client sent nothing
+const READ_TIMEOUT: Duration = Duration::from_millis(1);
+
+pub type Clients = Arc<Mutex<ClientList>>;
+
+/// Channels of connected event stream clients
+#[derive(Default)]
+pub struct ClientList {
+    senders: Vec<Sender<String>>,
+    /// Latest state events by kind
+    state: HashMap<&'static str, String>,
+    pub activity: Activity,
+    /// Lanes as the app runs them
+    pub lanes: Vec<LaneEntry>,
+}
+
+/// Send every event to all connected clients, dropping those gone
+pub fn start_broadcast(rx: Receiver<ApiEvent>) -> Clients {
+    let clients = Clients::default();
+    let shared = clients.clone();
+    std::thread::spawn(move || {
+        for event in rx {
+            let json = match serde_json::to_string(&event) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("failed to serialize api event: {e:?}");
+                    continue;
+                }
+            };
+            let mut clients = shared.lock().unwrap();
+            clients
+                .senders
+                .retain(|client| client.send(json.clone()).is_ok());
+            if let Some(key) = event.state_key() {
+                clients.state.insert(key, json);
+            }
+            clients.activity.update(&event);
+            match event {
+                ApiEvent::LanesChanged { lanes } => clients.lanes = lanes,
+                ApiEvent::LaneTargetChanged { lane, target_id } => {
+                    if let Some(entry) = clients.lanes.iter_mut().find(|l| l.name == lane) {
+                        entry.target_id = target_id;
+                    }
+                }
+                _ => {}
+            }
+        }
+    });
+    clients
+}
+
+/// Complete WebSocket handshake and stream events to client until it closes, answering its
+/// pings in between
+pub fn serve(stream: TcpStream, clients: &Clients) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut socket = tungstenite::accept(stream)?;
+    socket.get_ref().set_read_timeout(Some(READ_TIMEOUT))?;
+
+    let (tx, rx) = mpsc::channel();
+    {
+        let mut clients = clients.lock().unwrap();
+        for json in clients.state.values() {
+            tx.send(json.clone()).expect("failed to replay state");
+        }
+        clients.senders.push(tx);
+    }
+    info!("Event stream to {peer} opened");
+
+    loop {
+        match rx.recv_timeout(READ_INTERVAL) {
+            Ok(json) => socket.send(Message::text(json))?,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        // Pongs and close replies are written by tungstenite while reading
+        loop {
+            match socket.read() {
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    break;
+                }
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                    // Its sender is dropped from list with next event
+                    info!("Event stream to {peer} closed");
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
@@ -1,44 +1,72 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
     sync::{
-        Arc, RwLock,
+        Arc, Mutex,
         mpsc::{self, Receiver, Sender},
     },
 };
 
 use chrono::{DateTime, Local};
-use image::{GrayImage, Rgb, RgbImage, buffer::ConvertBuffer};
-use imageproc::{
-    drawing::draw_hollow_polygon_mut, edges::canny, filter::gaussian_blur_f32, point::Point,
-    rect::Rect,
-};
+use image::{GrayImage, RgbImage, buffer::ConvertBuffer};
+use imageproc::{filter::gaussian_blur_f32, point::Point};
+use serde_json::json;
+use tracing::{error, info};
 
 use crate::{
-    capturer::CapturedFrame,
+    api::{ApiEvent, LaneEntry, start_api_server},
+    capturer::{CapturedFrame, CapturerCommand, load_cameras, start_capturer},
+    coding::{Clip, ClipReader},
+    drills::{
+        DrillOutcome,
+        runner::{DrillProgress, DrillRunnerCommand, start_drill_runner},
+    },
     hits::{
-        detector::{HitDetectorCommand, start_hit_detector},
-        manager::HitManagerCommand,
+        manager::{HitManagerCommand, ReprocessScope},
+        player::{ClipPlayerCommand, start_clip_player},
         processor::{HitProcessResult, HitProcessorCommand},
-        storage::HitData,
+        storage::{HitData, HitQuery, RecordError, SharedStorage, open_storage},
     },
-    recorder::Recorder,
-    targets::{TargetInfo, recognizer::start_target_recognizer},
-    vision::{crop::crop_image, project::unwarp_rectangle, stencil::Stencil, zones::ZoneMap},
+    lanes::{Lane, LaneConfig, load_lanes, save_lanes},
+    recording::{Bookmark, RecordingSource, SessionRecorderCommand, start_session_recorder},
+    report::{export::ExportFormat, heatmap::HeatmapRequest, sheet::ReportFormat},
+    sessions::Session,
+    targets::TargetInfo,
+    timer::{
+        ShotTimerConfig, ShotTiming,
+        runner::{ShotTimerCommand, start_shot_timer},
+    },
+    util::geometry::polygon_contains,
+    vision::stencil::Stencil,
 };
 
 pub enum Event {
-    NewFrame(Arc<CapturedFrame>),
+    NewFrame {
+        camera: String,
+        frame: Arc<CapturedFrame>,
+    },
     NewStencil((f32, f32, f32, f32)),
     NewHit {
+        lane: String,
         timestamp: DateTime<Local>,
-        clip: (Vec<RgbImage>, u32),
+        clip: Clip,
         target_info: TargetInfo,
+        /// Laser position relative to lane target frame (0..1)
+        laser_pos: Point<f32>,
     },
     HitProcessorReady,
+    /// Lane recognizer found target frame
+    TargetRecognized {
+        lane: String,
+        target_info: TargetInfo,
+    },
+    /// Hit processor is waiting and no hits are queued
+    ProcessingIdle,
     ProcessHit {
         timestamp: DateTime<Local>,
-        clip: (Vec<RgbImage>, u32),
+        clip: Clip,
         target_info: TargetInfo,
+        target_id: Option<String>,
     },
     ProcessedHit {
         timestamp: DateTime<Local>,
@@ -47,15 +75,191 @@ pub enum Event {
     LoadedHits {
         hits: HashMap<DateTime<Local>, HitData>,
     },
-    LoadedHitClip {
+    /// Stored records that could not be loaded
+    LoadErrors {
+        errors: Vec<RecordError>,
+    },
+    /// Clip of hit ready to be played
+    HitClipOpened {
+        timestamp: DateTime<Local>,
+        reader: ClipReader,
+    },
+    ClipOpened {
+        timestamp: DateTime<Local>,
+        frame_times: Vec<DateTime<Local>>,
+        start: usize,
+        title: String,
+    },
+    ClipFrame {
+        timestamp: DateTime<Local>,
+        frame: Arc<RgbImage>,
+        /// Laser detected on frame, relative to frame size
+        laser: Option<Point<f32>>,
+    },
+    LoadedThumbnail {
+        timestamp: DateTime<Local>,
+        image: Arc<RgbImage>,
+    },
+    HeatmapReady {
+        image: Arc<RgbImage>,
+        exported: Option<PathBuf>,
+    },
+    ShotTimerStarted {
+        at: DateTime<Local>,
+    },
+    /// No more shots came, string is over
+    ShotTimerEnded,
+    ShotTimed {
+        timestamp: DateTime<Local>,
+        timing: ShotTiming,
+    },
+    DrillProgress(DrillProgress),
+    DrillFinished {
+        outcome: DrillOutcome,
+    },
+    SessionChanged {
+        session: Option<Session>,
+    },
+    RecordingReview {
         timestamp: DateTime<Local>,
-        clip: (Vec<RgbImage>, u32),
+        clip: Clip,
+    },
+    /// Hit was restored, annotated or its result changed
+    HitChanged {
+        timestamp: DateTime<Local>,
+        data: HitData,
+    },
+    HitDeleted {
+        timestamp: DateTime<Local>,
+    },
+    /// Path of written export, none if export failed
+    HitsExported {
+        path: Option<PathBuf>,
+        count: usize,
+    },
+    ReportExported {
+        path: Option<PathBuf>,
+    },
+    /// Number of hits added from archive, none if import failed
+    ArchiveImported {
+        path: PathBuf,
+        count: Option<usize>,
     },
 }
 
 pub enum AppCommand {
-    NewStencil((f32, f32, f32, f32)),
-    RequestHitClip { timestamp: DateTime<Local> },
+    NewStencil {
+        lane: String,
+        stencil: (f32, f32, f32, f32),
+    },
+    RequestHitClip {
+        timestamp: DateTime<Local>,
+    },
+    /// Frame of clip shown in hit player
+    RequestClipFrame {
+        index: usize,
+    },
+    RequestThumbnail {
+        timestamp: DateTime<Local>,
+    },
+    SelectTarget {
+        lane: String,
+        id: Option<String>,
+        reply: Option<CommandReply>,
+    },
+    /// Look for lane target now instead of waiting for periodic recognition
+    RecognizeTarget {
+        lane: String,
+        reply: Option<CommandReply>,
+    },
+    AddLane {
+        name: String,
+    },
+    RemoveLane {
+        name: String,
+    },
+    SelectLane {
+        name: String,
+    },
+    SetLaneCamera {
+        lane: String,
+        camera: String,
+    },
+    /// Show session recording around hit of lane
+    ReviewRecording {
+        timestamp: DateTime<Local>,
+        lane: String,
+    },
+    RequestHeatmap {
+        request: HeatmapRequest,
+        export: bool,
+    },
+    StartShotTimer {
+        config: ShotTimerConfig,
+        reply: Option<CommandReply>,
+    },
+    StopShotTimer {
+        reply: Option<CommandReply>,
+    },
+    StartDrill {
+        id: String,
+        reply: Option<CommandReply>,
+    },
+    AbortDrill {
+        reply: Option<CommandReply>,
+    },
+    StartSession {
+        shooter: String,
+        reply: Option<CommandReply>,
+    },
+    EndSession {
+        reply: Option<CommandReply>,
+    },
+    DeleteHit {
+        timestamp: DateTime<Local>,
+    },
+    UndoDeleteHit,
+    PurgeDeletedHits,
+    AnnotateHit {
+        timestamp: DateTime<Local>,
+        notes: String,
+        tags: Vec<String>,
+        flyer: bool,
+    },
+    /// Move hit to position relative to clip frame size (0..1) and score it again
+    CorrectHit {
+        timestamp: DateTime<Local>,
+        pos: Point<f32>,
+    },
+    Reprocess {
+        scope: ReprocessScope,
+    },
+    /// Export hits matching any query
+    ExportHits {
+        queries: Vec<HitQuery>,
+        format: ExportFormat,
+    },
+    ExportReport {
+        session: String,
+        format: ReportFormat,
+    },
+    ExportArchive {
+        queries: Vec<HitQuery>,
+    },
+    ImportArchive {
+        path: PathBuf,
+    },
+}
+
+/// Where outcome of command goes, details or why it was not carried out
+pub type CommandReply = Sender<Result<serde_json::Value, String>>;
+
+/// Send outcome back if command asked for it
+pub fn send_reply(reply: Option<CommandReply>, outcome: Result<serde_json::Value, String>) {
+    if let Some(reply) = reply {
+        // Asker may have given up waiting
+        reply.send(outcome).ok();
+    }
 }
 
 pub enum AppMessage {
@@ -66,153 +270,258 @@ pub enum AppMessage {
     LoadedHits {
         hits: HashMap<DateTime<Local>, HitData>,
     },
-    LoadedHitClip {
+    /// Stored records that could not be loaded
+    LoadErrors {
+        errors: Vec<RecordError>,
+    },
+    /// Clip player opened clip, frames follow as they are requested
+    ClipOpened {
+        timestamp: DateTime<Local>,
+        frame_times: Vec<DateTime<Local>>,
+        start: usize,
+        title: String,
+    },
+    ClipFrame {
+        timestamp: DateTime<Local>,
+        frame: Arc<RgbImage>,
+        /// Laser detected on frame, relative to frame size
+        laser: Option<Point<f32>>,
+    },
+    LoadedThumbnail {
         timestamp: DateTime<Local>,
-        clip: (Vec<RgbImage>, u32),
+        image: Arc<RgbImage>,
     },
     NewHit {
+        lane: String,
         timestamp: DateTime<Local>,
-        clip: (Vec<RgbImage>, u32),
+        clip: Clip,
         target_info: TargetInfo,
     },
+    /// Target library as (id, name) pairs
+    LoadedTargets {
+        targets: Vec<(String, String)>,
+    },
+    HeatmapReady {
+        image: Arc<RgbImage>,
+        exported: Option<PathBuf>,
+    },
+    ShotTimerStarted {
+        at: DateTime<Local>,
+    },
+    /// No more shots came, string is over
+    ShotTimerEnded,
+    ShotTimed {
+        timestamp: DateTime<Local>,
+        timing: ShotTiming,
+    },
+    /// Drill library as (id, name) pairs
+    LoadedDrills {
+        drills: Vec<(String, String)>,
+    },
+    DrillProgress(DrillProgress),
+    DrillFinished {
+        outcome: DrillOutcome,
+    },
+    SessionChanged {
+        session: Option<Session>,
+    },
+    LoadedCameras {
+        cameras: Vec<String>,
+    },
+    /// Lane configs with name of lane shown in UI
+    LanesChanged {
+        lanes: Vec<LaneConfig>,
+        current: String,
+    },
+    HitChanged {
+        timestamp: DateTime<Local>,
+        data: HitData,
+    },
+    HitDeleted {
+        timestamp: DateTime<Local>,
+    },
+    /// Path of written export, none if export failed
+    HitsExported {
+        path: Option<PathBuf>,
+        count: usize,
+    },
+    ReportExported {
+        path: Option<PathBuf>,
+    },
+    /// Number of hits added from archive, none if import failed
+    ArchiveImported {
+        path: PathBuf,
+        count: Option<usize>,
+    },
 }
 
-pub fn start() -> (Sender<AppCommand>, Receiver<AppMessage>) {
+/// Start app event loop, headless skips building frames for UI
+pub fn start(headless: bool) -> (Sender<AppCommand>, Receiver<AppMessage>) {
     let (bus_tx, ui_rx) = mpsc::channel();
     let (ui_tx, bus_rx) = mpsc::channel();
+    // Remote commands come in like those of UI
+    let command_tx = bus_tx.clone();
     std::thread::spawn(move || {
         let (bus_tx, bus_rx) = mpsc::channel::<Event>();
 
-        let target_settings = crate::targets::settings::load_targets();
+        let app_config = crate::config::load_config();
+        let target_settings = Arc::new(crate::targets::settings::load_targets());
+        {
+            let mut targets: Vec<(String, String)> = target_settings
+                .values()
+                .map(|t| (t.id().clone(), t.name().clone()))
+                .collect();
+            targets.sort();
+            ui_tx
+                .send(AppMessage::LoadedTargets { targets })
+                .expect("failed to send targets to ui");
+        }
 
-        let target_info = Arc::new(RwLock::new(None));
-        let zone_map = Arc::new(RwLock::new(None));
-        let zone_scores: Arc<RwLock<Vec<u32>>> = Arc::new(RwLock::new(Vec::new()));
-        let laser_info = Arc::new(RwLock::new(None));
-        let recorder = Arc::new(Recorder::new());
-        let mut target_stencil = Stencil::default();
+        let drills = crate::drills::load_drills();
+        {
+            let mut drills: Vec<(String, String)> = drills
+                .iter()
+                .map(|(id, drill)| (id.clone(), drill.name.clone()))
+                .collect();
+            drills.sort();
+            ui_tx
+                .send(AppMessage::LoadedDrills { drills })
+                .expect("failed to send drills to ui");
+        }
 
         // Start sub-systems
-        let capturer = crate::capturer::start_capturer(bus_tx.clone());
-        let last_camera_frame = Arc::new(RwLock::new(None));
-        let target_recognizer = start_target_recognizer(
-            target_info.clone(),
-            zone_map.clone(),
-            last_camera_frame.clone(),
-        );
-        let hit_detector = start_hit_detector(
-            bus_tx.clone(),
-            laser_info.clone(),
-            target_info.clone(),
-            recorder.clone(),
-        );
+        let cameras = load_cameras();
+        let _capturers: Vec<Sender<CapturerCommand>> = cameras
+            .iter()
+            .map(|config| start_capturer(bus_tx.clone(), config.clone()))
+            .collect();
+        let mut camera_sizes: HashMap<String, (u32, u32)> = HashMap::new();
+        ui_tx
+            .send(AppMessage::LoadedCameras {
+                cameras: cameras.iter().map(|c| c.id.clone()).collect(),
+            })
+            .expect("failed to send cameras to ui");
+
+        let mut lanes: Vec<Lane> = load_lanes()
+            .into_iter()
+            .map(|mut config| {
+                // Lanes without known camera are seen by first one
+                if !cameras.iter().any(|c| c.id == config.camera) {
+                    config.camera = cameras[0].id.clone();
+                }
+                Lane::start(config, app_config.recorder, bus_tx.clone())
+            })
+            .collect();
+        let mut ui_lane = lanes[0].name().to_string();
+        ui_tx
+            .send(AppMessage::LanesChanged {
+                lanes: lane_configs(&lanes),
+                current: ui_lane.clone(),
+            })
+            .expect("failed to send lanes to ui");
 
+        // Opened once, so only one connection or writer touches stored hits
+        let storage: SharedStorage = Arc::new(Mutex::new(open_storage(&app_config)));
         let hit_manager = crate::hits::manager::start_hit_manager(
             bus_tx.clone(),
-            Box::new(crate::hits::storage::FileHitStorage::new("data/hits")),
+            storage.clone(),
+            target_settings.clone(),
         );
 
-        let hit_processor = crate::hits::processor::start_hit_processor(bus_tx.clone());
+        let clip_player = start_clip_player(bus_tx.clone());
+
+        let api = start_api_server(
+            &app_config.api,
+            storage,
+            target_settings.clone(),
+            command_tx,
+        );
+        let send_lanes_to_api = |lanes: &[Lane]| {
+            if let Some(api) = &api {
+                api.send(ApiEvent::LanesChanged {
+                    lanes: lanes
+                        .iter()
+                        .map(|l| LaneEntry {
+                            name: l.name().to_string(),
+                            target_id: l.config.target_id.clone(),
+                        })
+                        .collect(),
+                })
+                .expect("failed to send lanes to api");
+            }
+        };
+        send_lanes_to_api(&lanes);
+
+        let hit_processor =
+            crate::hits::processor::start_hit_processor(bus_tx.clone(), target_settings.clone());
+
+        let recording = app_config.recording;
+        let session_recorder = start_session_recorder(bus_tx.clone(), recording);
+        // Frames are only forwarded while there is session to record
+        let mut recording_active = false;
+        // Latest hits by lane, so shot seen by two overlapping lanes is stored once
+        let mut recent_hits: VecDeque<(String, DateTime<Local>)> = VecDeque::new();
+        // Api only hears when drill starts or stops, not of every progress
+        let mut drill_running = false;
+
+        let shot_timer = start_shot_timer(bus_tx.clone());
+        let drill_runner = start_drill_runner(bus_tx.clone());
 
         loop {
             for event in bus_rx.try_iter() {
                 match event {
-                    Event::NewFrame(captured_frame) => {
+                    Event::NewFrame {
+                        camera,
+                        frame: captured_frame,
+                    } => {
                         let mut camera_frame = captured_frame.image.clone();
+                        camera_sizes.insert(camera.clone(), camera_frame.dimensions());
 
-                        let mut target_frame = target_stencil.crop(&camera_frame).to_image();
-                        *last_camera_frame.write().unwrap() = Some(Arc::new(CapturedFrame {
-                            image: target_frame.clone(),
-                            timestamp: captured_frame.timestamp,
-                        }));
+                        if recording_active && recording.source == RecordingSource::Camera {
+                            session_recorder
+                                .send(SessionRecorderCommand::Frame {
+                                    stream: camera.clone(),
+                                    frame: captured_frame.clone(),
+                                })
+                                .expect("failed to send frame to session recorder");
+                        }
 
-                        if let Some(target_info) = &*target_info.read().unwrap() {
-                            if let Some(frame) =
-                                unwarp_rectangle(&target_frame, &target_info.rect, 600, 800)
-                            {
-                                target_frame = frame;
+                        let mut ui_target_frame = None;
+                        for lane in lanes.iter().filter(|l| l.config.camera == camera) {
+                            let target_frame =
+                                lane.process_frame(&captured_frame, &mut camera_frame);
+                            if recording_active && recording.source == RecordingSource::Target {
+                                session_recorder
+                                    .send(SessionRecorderCommand::Frame {
+                                        stream: lane.name().to_string(),
+                                        frame: Arc::new(CapturedFrame {
+                                            image: target_frame.clone(),
+                                            timestamp: captured_frame.timestamp,
+                                        }),
+                                    })
+                                    .expect("failed to send frame to session recorder");
+                            }
+                            if lane.name() == ui_lane {
+                                ui_target_frame = Some(target_frame);
                             }
-                            let r =
-                                target_stencil.rect(camera_frame.width(), camera_frame.height());
-                            let a: Vec<Point<f32>> = target_info
-                                .rect
-                                .iter()
-                                .map(|p| Point::new(r.x as f32 + p.x, r.y as f32 + p.y))
-                                .collect();
-                            draw_hollow_polygon_mut(&mut camera_frame, &a, Rgb([0, 255, 0]));
                         }
 
-                        // *zone_map.write().unwrap() =
-                        //     Some(ZoneMap::recognize(&target_frame.convert()));
-
-                        let captured_target_frame = Arc::new(CapturedFrame {
-                            image: target_frame.clone(),
-                            timestamp: captured_frame.timestamp,
+                        if headless {
+                            continue;
+                        }
+                        // Send frame data back to UI if it shows this camera
+                        if !lanes
+                            .iter()
+                            .any(|l| l.name() == ui_lane && l.config.camera == camera)
+                        {
+                            continue;
+                        }
+                        let target_frame_arc = ui_target_frame.map(|target_frame| {
+                            let img: GrayImage = target_frame.convert();
+                            let img: RgbImage = gaussian_blur_f32(&img, 1.5).convert();
+                            // canny(&img, 10.0, 10.0).convert()
+                            Arc::new(img)
                         });
-                        recorder.push_frame(captured_target_frame.clone());
-                        // hit_detector
-                        //     .send(HitDetectorCommand::NewFrame(captured_target_frame.clone()))
-                        //     .unwrap();
-
-                        // if let Some(target_info) = &*target_info.read().unwrap() {
-                        //     if let Some(mut frame) =
-                        //         unwarp_rectangle(&captured_frame.image, &target_info.rect, 600, 800)
-                        //     {
-                        //         let captured_frame = Arc::new(CapturedFrame {
-                        //             image: frame.clone(),
-                        //             timestamp: captured_frame.timestamp,
-                        //         });
-                        //         recorder.push_frame(captured_frame.clone());
-                        //         hit_detector
-                        //             .send(HitDetectorCommand::NewFrame(captured_frame))
-                        //             .unwrap();
-                        //         if let Some(laser_info) = &*laser_info.read().unwrap() {
-                        //             imageproc::drawing::draw_cross_mut(
-                        //                 &mut frame,
-                        //                 Rgb([255, 0, 0]),
-                        //                 laser_info.pos.x as i32,
-                        //                 laser_info.pos.y as i32,
-                        //             );
-                        //             imageproc::drawing::draw_hollow_rect_mut(
-                        //                 &mut frame,
-                        //                 Rect::at(
-                        //                     laser_info.pos.x as i32 - 10,
-                        //                     laser_info.pos.y as i32 - 10,
-                        //                 )
-                        //                 .of_size(20, 20),
-                        //                 Rgb([255, 0, 0]),
-                        //             );
-                        //         }
-                        //         ui_target_frame = Some(frame);
-                        //     }
-                        //     imageproc::drawing::draw_hollow_polygon_mut(
-                        //         &mut ui_camera_frame,
-                        //         target_info
-                        //             .rect
-                        //             .iter()
-                        //             .map(Into::into)
-                        //             .collect::<Vec<_>>()
-                        //             .as_slice(),
-                        //         Rgb([0, 255, 0]),
-                        //     );
-                        // }
-
-                        // Send frame data back to UI
-
-                        let mut target_frame_arc = Some(
-                            {
-                                let img: GrayImage = target_frame.convert();
-                                let img = gaussian_blur_f32(&img, 1.5).convert();
-                                // canny(&img, 10.0, 10.0).convert()
-                                img
-                            }
-                            .clone(),
-                        )
-                        .map(|f| Arc::new(f));
-
-                        // if let Some(zone_map) = &*zone_map.read().unwrap() {
-                        //     target_frame_arc = Some(Arc::new(zone_map.map().convert()));
-                        // }
 
                         ui_tx
                             .send(AppMessage::FrameReady {
@@ -225,33 +534,119 @@ pub fn start() -> (Sender<AppCommand>, Receiver<AppMessage>) {
                     Event::HitProcessorReady => hit_manager
                         .send(HitManagerCommand::HitProcessorReady)
                         .unwrap(),
+                    Event::ProcessingIdle => {}
+                    Event::TargetRecognized { lane, target_info } => {
+                        if let Some(api) = &api {
+                            api.send(ApiEvent::TargetRecognized {
+                                lane,
+                                target: target_info,
+                            })
+                            .expect("failed to send recognized target to api");
+                        }
+                    }
                     Event::ProcessHit {
                         timestamp,
                         clip,
                         target_info,
+                        target_id,
                     } => {
                         hit_processor
                             .send(HitProcessorCommand::ProcessHit {
                                 timestamp,
                                 clip,
                                 target_info,
+                                target_id,
                             })
                             .expect("failed to request hit process");
                     }
                     Event::NewHit {
+                        lane,
                         timestamp,
                         clip,
                         target_info,
+                        laser_pos,
                     } => {
+                        let Some(seen_by) = lanes.iter().find(|l| l.name() == lane) else {
+                            error!("hit from unknown lane '{lane}'");
+                            continue;
+                        };
+
+                        // Stencils may overlap, attribute hit to lane whose target contains it
+                        let camera = &seen_by.config.camera;
+                        let camera_size = camera_sizes.get(camera).copied().unwrap_or_default();
+                        let pos = seen_by.to_camera(laser_pos, camera_size);
+                        let contains = |l: &Lane| {
+                            l.target_polygon(camera_size)
+                                .is_some_and(|polygon| polygon_contains(&polygon, pos))
+                        };
+                        let owner = if contains(seen_by) {
+                            None
+                        } else {
+                            lanes
+                                .iter()
+                                .filter(|l| l.name() != lane && &l.config.camera == camera)
+                                .filter(|l| contains(l))
+                                .find_map(|l| Some((l, l.target_info.read().unwrap().clone()?)))
+                        };
+                        let (hit_lane, clip, target_info) = match owner {
+                            Some((owner, owner_target)) => {
+                                info!(
+                                    "Hit {timestamp} seen by lane '{lane}' is on target of lane '{}'",
+                                    owner.name()
+                                );
+                                // Same moments from owner's recording, so clip shows its target
+                                let from = clip.timestamps.first().copied().unwrap_or(timestamp);
+                                let to = clip.timestamps.last().copied().unwrap_or(timestamp);
+                                (owner, owner.clip(from, to), owner_target)
+                            }
+                            None => (seen_by, clip, target_info),
+                        };
+                        let lane = hit_lane.name().to_string();
+
+                        // Owner lane may have seen the same shot on its own target
+                        if recent_hits.contains(&(lane.clone(), timestamp)) {
+                            info!("Hit {timestamp} of lane '{lane}' is already stored");
+                            continue;
+                        }
+                        recent_hits.push_back((lane.clone(), timestamp));
+                        if recent_hits.len() > 16 {
+                            recent_hits.pop_front();
+                        }
+
                         hit_manager
                             .send(HitManagerCommand::NewHit {
                                 timestamp,
                                 clip: clip.clone(),
                                 target_info: target_info.clone(),
+                                target_id: hit_lane.config.target_id.clone(),
+                                lane: lane.clone(),
                             })
                             .unwrap();
+                        if recording_active {
+                            session_recorder
+                                .send(SessionRecorderCommand::Bookmark(Bookmark {
+                                    timestamp,
+                                    lane: lane.clone(),
+                                    stream: recording.source.stream(&hit_lane.config),
+                                }))
+                                .expect("failed to send bookmark to session recorder");
+                        }
+                        shot_timer
+                            .send(ShotTimerCommand::Shot { timestamp })
+                            .expect("failed to send shot to timer");
+                        drill_runner
+                            .send(DrillRunnerCommand::Shot { timestamp })
+                            .expect("failed to send shot to drill runner");
+                        if let Some(api) = &api {
+                            api.send(ApiEvent::NewHit {
+                                lane: lane.clone(),
+                                timestamp,
+                            })
+                            .expect("failed to send hit to api");
+                        }
                         ui_tx
                             .send(AppMessage::NewHit {
+                                lane,
                                 timestamp,
                                 clip,
                                 target_info,
@@ -261,31 +656,431 @@ pub fn start() -> (Sender<AppCommand>, Receiver<AppMessage>) {
                     Event::ProcessedHit {
                         timestamp,
                         processed,
-                    } => hit_manager
-                        .send(HitManagerCommand::ProcessedHit {
-                            timestamp,
-                            processed,
-                        })
-                        .expect("failed to send hit process result to manager"),
+                    } => {
+                        drill_runner
+                            .send(DrillRunnerCommand::Processed {
+                                timestamp,
+                                result: processed.clone(),
+                            })
+                            .expect("failed to send hit process result to drill runner");
+                        if let Some(api) = &api {
+                            api.send(ApiEvent::ProcessedHit {
+                                timestamp,
+                                result: processed.clone(),
+                            })
+                            .expect("failed to send hit process result to api");
+                        }
+                        hit_manager
+                            .send(HitManagerCommand::ProcessedHit {
+                                timestamp,
+                                processed,
+                            })
+                            .expect("failed to send hit process result to manager")
+                    }
                     Event::LoadedHits { hits } => ui_tx
                         .send(AppMessage::LoadedHits { hits })
                         .expect("failed to send loaded hits to ui"),
-                    Event::LoadedHitClip { timestamp, clip } => ui_tx
-                        .send(AppMessage::LoadedHitClip { timestamp, clip })
-                        .unwrap(),
+                    Event::LoadErrors { errors } => ui_tx
+                        .send(AppMessage::LoadErrors { errors })
+                        .expect("failed to send load errors to ui"),
+                    Event::HitClipOpened { timestamp, reader } => clip_player
+                        .send(ClipPlayerCommand::Open {
+                            timestamp,
+                            reader: Box::new(reader),
+                            start: 0,
+                            title: "Hit clip".to_string(),
+                        })
+                        .expect("failed to send hit clip to player"),
+                    Event::ClipOpened {
+                        timestamp,
+                        frame_times,
+                        start,
+                        title,
+                    } => ui_tx
+                        .send(AppMessage::ClipOpened {
+                            timestamp,
+                            frame_times,
+                            start,
+                            title,
+                        })
+                        .expect("failed to send opened clip to ui"),
+                    Event::ClipFrame {
+                        timestamp,
+                        frame,
+                        laser,
+                    } => ui_tx
+                        .send(AppMessage::ClipFrame {
+                            timestamp,
+                            frame,
+                            laser,
+                        })
+                        .expect("failed to send clip frame to ui"),
+                    Event::LoadedThumbnail { timestamp, image } => ui_tx
+                        .send(AppMessage::LoadedThumbnail { timestamp, image })
+                        .expect("failed to send thumbnail to ui"),
+                    Event::HeatmapReady { image, exported } => ui_tx
+                        .send(AppMessage::HeatmapReady { image, exported })
+                        .expect("failed to send heatmap to ui"),
+                    Event::ShotTimerStarted { at } => {
+                        if let Some(api) = &api {
+                            api.send(ApiEvent::ShotTimerStarted { at })
+                                .expect("failed to send shot timer start to api");
+                        }
+                        ui_tx
+                            .send(AppMessage::ShotTimerStarted { at })
+                            .expect("failed to send shot timer start to ui");
+                    }
+                    Event::ShotTimerEnded => {
+                        if let Some(api) = &api {
+                            api.send(ApiEvent::ShotTimerStopped)
+                                .expect("failed to send shot timer state to api");
+                        }
+                        ui_tx
+                            .send(AppMessage::ShotTimerEnded)
+                            .expect("failed to send shot timer end to ui");
+                    }
+                    Event::ShotTimed { timestamp, timing } => {
+                        if let Some(api) = &api {
+                            api.send(ApiEvent::ShotTimed {
+                                timestamp,
+                                timing: timing.clone(),
+                            })
+                            .expect("failed to send shot timing to api");
+                        }
+                        hit_manager
+                            .send(HitManagerCommand::ShotTimed {
+                                timestamp,
+                                timing: timing.clone(),
+                            })
+                            .expect("failed to send shot timing to manager");
+                        ui_tx
+                            .send(AppMessage::ShotTimed { timestamp, timing })
+                            .expect("failed to send shot timing to ui");
+                    }
+                    Event::DrillProgress(progress) => {
+                        if let Some(api) = &api
+                            && progress.running != drill_running
+                        {
+                            api.send(ApiEvent::DrillChanged {
+                                running: progress.running,
+                            })
+                            .expect("failed to send drill state to api");
+                        }
+                        drill_running = progress.running;
+                        ui_tx
+                            .send(AppMessage::DrillProgress(progress))
+                            .expect("failed to send drill progress to ui");
+                    }
+                    Event::DrillFinished { outcome } => {
+                        if let Some(api) = &api {
+                            api.send(ApiEvent::DrillChanged { running: false })
+                                .expect("failed to send drill state to api");
+                        }
+                        drill_running = false;
+                        hit_manager
+                            .send(HitManagerCommand::DrillFinished {
+                                outcome: outcome.clone(),
+                            })
+                            .expect("failed to send drill outcome to manager");
+                        ui_tx
+                            .send(AppMessage::DrillFinished { outcome })
+                            .expect("failed to send drill outcome to ui");
+                    }
+                    Event::SessionChanged { session } => {
+                        recording_active = recording.enabled && session.is_some();
+                        session_recorder
+                            .send(match &session {
+                                Some(session) => SessionRecorderCommand::Start {
+                                    session_id: session.id.clone(),
+                                },
+                                None => SessionRecorderCommand::Stop,
+                            })
+                            .expect("failed to send session to recorder");
+                        if let Some(api) = &api {
+                            api.send(ApiEvent::SessionChanged {
+                                session: session.clone(),
+                            })
+                            .expect("failed to send session to api");
+                        }
+                        ui_tx
+                            .send(AppMessage::SessionChanged { session })
+                            .expect("failed to send session to ui");
+                    }
+                    Event::RecordingReview { timestamp, clip } => {
+                        // Start at the moment of the hit
+                        let start = clip
+                            .timestamps
+                            .iter()
+                            .position(|t| *t >= timestamp)
+                            .unwrap_or(0);
+                        clip_player
+                            .send(ClipPlayerCommand::Open {
+                                timestamp,
+                                reader: Box::new(ClipReader::from(clip)),
+                                start,
+                                title: "Session recording".to_string(),
+                            })
+                            .expect("failed to send recording review to player");
+                    }
+                    Event::HitChanged { timestamp, data } => ui_tx
+                        .send(AppMessage::HitChanged { timestamp, data })
+                        .expect("failed to send hit change to ui"),
+                    Event::HitDeleted { timestamp } => ui_tx
+                        .send(AppMessage::HitDeleted { timestamp })
+                        .expect("failed to send hit deletion to ui"),
+                    Event::HitsExported { path, count } => ui_tx
+                        .send(AppMessage::HitsExported { path, count })
+                        .expect("failed to send hits export to ui"),
+                    Event::ReportExported { path } => ui_tx
+                        .send(AppMessage::ReportExported { path })
+                        .expect("failed to send report export to ui"),
+                    Event::ArchiveImported { path, count } => ui_tx
+                        .send(AppMessage::ArchiveImported { path, count })
+                        .expect("failed to send archive import to ui"),
                 }
             }
 
             for cmd in ui_rx.try_iter() {
                 match cmd {
-                    AppCommand::NewStencil(stencil) => {
-                        target_stencil = stencil.into();
-                        *target_info.write().unwrap() = None;
+                    AppCommand::NewStencil { lane, stencil } => {
+                        let Some(lane) = lanes.iter_mut().find(|l| l.name() == lane) else {
+                            error!("unknown lane '{lane}'");
+                            continue;
+                        };
+                        lane.set_stencil(stencil.into());
                         bus_tx.send(Event::NewStencil(stencil)).unwrap();
+                        if let Err(e) = save_lanes(&lane_configs(&lanes)) {
+                            error!("failed to save lanes: {e:?}");
+                        }
                     }
                     AppCommand::RequestHitClip { timestamp } => hit_manager
                         .send(HitManagerCommand::RequestHitClip { timestamp })
                         .unwrap(),
+                    AppCommand::RequestClipFrame { index } => clip_player
+                        .send(ClipPlayerCommand::Frame { index })
+                        .expect("failed to send frame request to player"),
+                    AppCommand::RequestThumbnail { timestamp } => hit_manager
+                        .send(HitManagerCommand::RequestThumbnail { timestamp })
+                        .expect("failed to send thumbnail request to hit manager"),
+                    AppCommand::SelectTarget { lane, id, reply } => {
+                        let Some(lane) = lanes.iter_mut().find(|l| l.name() == lane) else {
+                            error!("unknown lane '{lane}'");
+                            send_reply(reply, Err(format!("unknown lane '{lane}'")));
+                            continue;
+                        };
+                        info!("Selected target {id:?} for lane '{}'", lane.name());
+                        if let Some(api) = &api {
+                            api.send(ApiEvent::LaneTargetChanged {
+                                lane: lane.name().to_string(),
+                                target_id: id.clone(),
+                            })
+                            .expect("failed to send lane target to api");
+                        }
+                        send_reply(reply, Ok(json!({ "lane": lane.name(), "target": id })));
+                        lane.config.target_id = id;
+                        if let Err(e) = save_lanes(&lane_configs(&lanes)) {
+                            error!("failed to save lanes: {e:?}");
+                        }
+                    }
+                    AppCommand::RecognizeTarget { lane, reply } => {
+                        let Some(lane) = lanes.iter().find(|l| l.name() == lane) else {
+                            error!("unknown lane '{lane}'");
+                            send_reply(reply, Err(format!("unknown lane '{lane}'")));
+                            continue;
+                        };
+                        lane.recognize_target(reply);
+                    }
+                    AppCommand::AddLane { name } => {
+                        let name = name.trim().to_string();
+                        if name.is_empty() || lanes.iter().any(|l| l.name() == name) {
+                            error!("lane name '{name}' is empty or already taken");
+                            continue;
+                        }
+                        info!("Adding lane '{name}'");
+                        // New lane starts on camera currently shown in UI
+                        let camera = lanes
+                            .iter()
+                            .find(|l| l.name() == ui_lane)
+                            .map(|l| l.config.camera.clone())
+                            .unwrap_or_else(|| cameras[0].id.clone());
+                        lanes.push(Lane::start(
+                            LaneConfig {
+                                name: name.clone(),
+                                stencil: Stencil::default(),
+                                camera,
+                                target_id: None,
+                            },
+                            app_config.recorder,
+                            bus_tx.clone(),
+                        ));
+                        ui_lane = name;
+                        if let Err(e) = save_lanes(&lane_configs(&lanes)) {
+                            error!("failed to save lanes: {e:?}");
+                        }
+                        ui_tx
+                            .send(AppMessage::LanesChanged {
+                                lanes: lane_configs(&lanes),
+                                current: ui_lane.clone(),
+                            })
+                            .expect("failed to send lanes to ui");
+                        send_lanes_to_api(&lanes);
+                    }
+                    AppCommand::RemoveLane { name } => {
+                        if lanes.len() <= 1 {
+                            error!("can't remove last lane");
+                            continue;
+                        }
+                        info!("Removing lane '{name}'");
+                        lanes.retain(|l| l.name() != name);
+                        if ui_lane == name {
+                            ui_lane = lanes[0].name().to_string();
+                        }
+                        if let Err(e) = save_lanes(&lane_configs(&lanes)) {
+                            error!("failed to save lanes: {e:?}");
+                        }
+                        ui_tx
+                            .send(AppMessage::LanesChanged {
+                                lanes: lane_configs(&lanes),
+                                current: ui_lane.clone(),
+                            })
+                            .expect("failed to send lanes to ui");
+                        send_lanes_to_api(&lanes);
+                    }
+                    AppCommand::SelectLane { name } => {
+                        if !lanes.iter().any(|l| l.name() == name) {
+                            error!("unknown lane '{name}'");
+                            ui_lane = lanes[0].name().to_string();
+                            ui_tx
+                                .send(AppMessage::LanesChanged {
+                                    lanes: lane_configs(&lanes),
+                                    current: ui_lane.clone(),
+                                })
+                                .expect("failed to send lanes to ui");
+                            continue;
+                        }
+                        ui_lane = name;
+                    }
+                    AppCommand::ReviewRecording { timestamp, lane } => {
+                        let Some(lane) = lanes.iter().find(|l| l.name() == lane) else {
+                            error!("unknown lane '{lane}'");
+                            continue;
+                        };
+                        session_recorder
+                            .send(SessionRecorderCommand::Review {
+                                timestamp,
+                                stream: recording.source.stream(&lane.config),
+                            })
+                            .expect("failed to send review request to session recorder");
+                    }
+                    AppCommand::SetLaneCamera { lane, camera } => {
+                        if !cameras.iter().any(|c| c.id == camera) {
+                            error!("unknown camera '{camera}'");
+                            continue;
+                        }
+                        let Some(lane) = lanes.iter_mut().find(|l| l.name() == lane) else {
+                            error!("unknown lane '{lane}'");
+                            continue;
+                        };
+                        info!("Lane '{}' moved to camera '{camera}'", lane.name());
+                        lane.set_camera(camera);
+                        if let Err(e) = save_lanes(&lane_configs(&lanes)) {
+                            error!("failed to save lanes: {e:?}");
+                        }
+                    }
+                    AppCommand::RequestHeatmap { request, export } => {
+                        let Some(target) = target_settings.get(&request.target_id) else {
+                            error!("unknown heatmap target '{}'", request.target_id);
+                            continue;
+                        };
+                        hit_manager
+                            .send(HitManagerCommand::RenderHeatmap {
+                                preview_path: target.preview_path(),
+                                request,
+                                export,
+                            })
+                            .unwrap();
+                    }
+                    AppCommand::StartShotTimer { config, reply } => {
+                        if let Some(api) = &api {
+                            api.send(ApiEvent::ShotTimerArmed)
+                                .expect("failed to send shot timer state to api");
+                        }
+                        shot_timer
+                            .send(ShotTimerCommand::Start { config, reply })
+                            .expect("failed to start shot timer");
+                    }
+                    AppCommand::StopShotTimer { reply } => {
+                        if let Some(api) = &api {
+                            api.send(ApiEvent::ShotTimerStopped)
+                                .expect("failed to send shot timer state to api");
+                        }
+                        shot_timer
+                            .send(ShotTimerCommand::Stop { reply })
+                            .expect("failed to stop shot timer");
+                    }
+                    AppCommand::StartDrill { id, reply } => {
+                        let Some(drill) = drills.get(&id) else {
+                            error!("unknown drill '{id}'");
+                            send_reply(reply, Err(format!("unknown drill '{id}'")));
+                            continue;
+                        };
+                        drill_runner
+                            .send(DrillRunnerCommand::Start {
+                                id,
+                                drill: drill.clone(),
+                                reply,
+                            })
+                            .expect("failed to start drill");
+                    }
+                    AppCommand::AbortDrill { reply } => drill_runner
+                        .send(DrillRunnerCommand::Abort { reply })
+                        .expect("failed to abort drill"),
+                    AppCommand::StartSession { shooter, reply } => hit_manager
+                        .send(HitManagerCommand::StartSession { shooter, reply })
+                        .expect("failed to start session"),
+                    AppCommand::EndSession { reply } => hit_manager
+                        .send(HitManagerCommand::EndSession { reply })
+                        .expect("failed to end session"),
+                    AppCommand::DeleteHit { timestamp } => hit_manager
+                        .send(HitManagerCommand::DeleteHit { timestamp })
+                        .expect("failed to send hit deletion to manager"),
+                    AppCommand::UndoDeleteHit => hit_manager
+                        .send(HitManagerCommand::UndoDelete)
+                        .expect("failed to send undo to manager"),
+                    AppCommand::PurgeDeletedHits => hit_manager
+                        .send(HitManagerCommand::PurgeDeleted)
+                        .expect("failed to send purge to manager"),
+                    AppCommand::AnnotateHit {
+                        timestamp,
+                        notes,
+                        tags,
+                        flyer,
+                    } => hit_manager
+                        .send(HitManagerCommand::AnnotateHit {
+                            timestamp,
+                            notes,
+                            tags,
+                            flyer,
+                        })
+                        .expect("failed to send hit notes to manager"),
+                    AppCommand::CorrectHit { timestamp, pos } => hit_manager
+                        .send(HitManagerCommand::CorrectHit { timestamp, pos })
+                        .expect("failed to send hit correction to manager"),
+                    AppCommand::Reprocess { scope } => hit_manager
+                        .send(HitManagerCommand::Reprocess { scope })
+                        .expect("failed to send reprocess request to manager"),
+                    AppCommand::ExportHits { queries, format } => hit_manager
+                        .send(HitManagerCommand::ExportHits { queries, format })
+                        .expect("failed to send export request to manager"),
+                    AppCommand::ExportReport { session, format } => hit_manager
+                        .send(HitManagerCommand::ExportReport { session, format })
+                        .expect("failed to send report request to manager"),
+                    AppCommand::ExportArchive { queries } => hit_manager
+                        .send(HitManagerCommand::ExportArchive { queries })
+                        .expect("failed to send archive request to manager"),
+                    AppCommand::ImportArchive { path } => hit_manager
+                        .send(HitManagerCommand::ImportArchive { path })
+                        .expect("failed to send archive import to manager"),
                 }
             }
 
@@ -294,3 +1089,7 @@ pub fn start() -> (Sender<AppCommand>, Receiver<AppMessage>) {
     });
     (bus_tx, bus_rx)
 }
+
+fn lane_configs(lanes: &[Lane]) -> Vec<LaneConfig> {
+    lanes.iter().map(|l| l.config.clone()).collect()
+}
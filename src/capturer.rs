@@ -1,4 +1,7 @@
 use std::{
+    fs,
+    io::BufReader,
+    path::Path,
     sync::{
         Arc,
         mpsc::{self, Sender},
@@ -13,10 +16,20 @@ use nokhwa::{
     pixel_format::RgbFormat,
     utils::{CameraIndex, RequestedFormat, RequestedFormatType},
 };
-use tracing::info;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
 
 use crate::bus::Event;
 
+const CAMERAS_PATH: &str = "data/cameras.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CameraConfig {
+    pub id: String,
+    /// Device index of camera
+    pub index: u32,
+}
+
 pub struct CapturedFrame {
     pub timestamp: DateTime<Local>,
     pub image: RgbImage,
@@ -25,18 +38,25 @@ pub struct CapturedFrame {
 #[derive(Debug)]
 pub enum CapturerCommand {}
 
-pub fn start_capturer(app_tx: Sender<Event>) -> Sender<CapturerCommand> {
+pub fn start_capturer(app_tx: Sender<Event>, config: CameraConfig) -> Sender<CapturerCommand> {
     let (tx, rx) = mpsc::channel();
 
     std::thread::spawn(move || {
         let requested_format =
             RequestedFormat::new::<RgbFormat>(RequestedFormatType::AbsoluteHighestResolution);
-        let mut cam =
-            Camera::new(CameraIndex::Index(0), requested_format).expect("failed to open camera");
-        cam.open_stream().expect("failed to open stream");
+        let mut cam = match Camera::new(CameraIndex::Index(config.index), requested_format)
+            .and_then(|mut cam| cam.open_stream().map(|_| cam))
+        {
+            Ok(cam) => cam,
+            Err(e) => {
+                error!("failed to open camera '{}': {e:?}", config.id);
+                return;
+            }
+        };
 
         info!(
-            "{}x{} {}fps",
+            "Camera '{}' {}x{} {}fps",
+            config.id,
             cam.resolution().height(),
             cam.resolution().width(),
             cam.frame_rate()
@@ -47,22 +67,58 @@ pub fn start_capturer(app_tx: Sender<Event>) -> Sender<CapturerCommand> {
                 info!("Received command: {:?}", cmd);
             }
 
-            let frame = cam.frame().expect("failed to get next frame");
-
-            let frame: RgbImage = frame
-                .decode_image::<RgbFormat>()
-                .expect("failed to decode frame image");
+            let frame: RgbImage = match cam
+                .frame()
+                .and_then(|frame| frame.decode_image::<RgbFormat>())
+            {
+                Ok(frame) => frame,
+                Err(e) => {
+                    error!("failed to capture frame from camera '{}': {e:?}", config.id);
+                    continue;
+                }
+            };
 
             let frame = CapturedFrame {
                 timestamp: Local::now(),
                 image: frame,
             };
 
-            app_tx
-                .send(Event::NewFrame(Arc::new(frame)))
-                .expect("failed to send frame to bus");
+            if app_tx
+                .send(Event::NewFrame {
+                    camera: config.id.clone(),
+                    frame: Arc::new(frame),
+                })
+                .is_err()
+            {
+                return;
+            }
         }
     });
 
     tx
 }
+
+fn default_cameras() -> Vec<CameraConfig> {
+    vec![CameraConfig {
+        id: "Camera 1".to_string(),
+        index: 0,
+    }]
+}
+
+pub fn load_cameras() -> Vec<CameraConfig> {
+    if !Path::new(CAMERAS_PATH).exists() {
+        return default_cameras();
+    }
+
+    let cameras: anyhow::Result<Vec<CameraConfig>> = fs::File::open(CAMERAS_PATH)
+        .map_err(anyhow::Error::from)
+        .and_then(|f| Ok(serde_json::from_reader(BufReader::new(f))?));
+    match cameras {
+        Ok(cameras) if !cameras.is_empty() => cameras,
+        Ok(_) => default_cameras(),
+        Err(e) => {
+            error!("failed to load cameras from {CAMERAS_PATH}: {e:?}");
+            default_cameras()
+        }
+    }
+}
@@ -0,0 +1,531 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::{Arc, mpsc},
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use clap::{Args, Parser, Subcommand};
+use image::Rgb;
+use imageproc::{drawing::draw_hollow_polygon_mut, point::Point};
+use tracing::info;
+
+use crate::{
+    TIMESTAMP_UI_FORMAT,
+    bus::Event,
+    coding::ClipCodec,
+    config::load_config,
+    hits::{
+        archive::{export_archive, import_archive},
+        manager::{ReprocessScope, process_next, reprocess_hits, save_processed},
+        processor::{HitProcessorCommand, start_hit_processor},
+        storage::{HitOrder, HitQuery, HitStorage, StorageBackend, open_storage},
+    },
+    report::{
+        export::{ExportFormat, collect_rows, write_rows},
+        sheet::{ReportFormat, build_report, write_report},
+    },
+    targets::{recognizer::recognize_target, settings::load_targets},
+    vision::{laser::find_red_laser, zones::ZoneMap},
+};
+
+/// Laser dry fire trainer, starts the UI when no command is given
+#[derive(Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Run capture, detection and storage without UI, control it through the network api
+    #[arg(long)]
+    pub headless: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run stored hits through hit processor again
+    Reprocess(ReprocessArgs),
+    /// Write stored hits as CSV or JSON
+    Export(ExportArgs),
+    /// Write printable session report as PDF or SVG
+    Report(ReportArgs),
+    /// Write stored hits with clips, sessions and targets to tar archive
+    Archive(ArchiveArgs),
+    /// Merge tar archive into stored hits
+    Import(ImportArgs),
+    /// List stored hits, one per line
+    Hits(HitsArgs),
+    /// Print stored hit as JSON
+    Hit(HitArgs),
+    /// List stored sessions, one per line
+    Sessions(SessionsArgs),
+    /// Find target outline on image and print it as JSON
+    Recognize(RecognizeArgs),
+    /// Look for laser on every frame of clip
+    Detect(DetectArgs),
+    /// Build zone map of target image
+    Zones(ZonesArgs),
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+pub struct ReprocessArgs {
+    /// Every stored hit
+    #[arg(long)]
+    all: bool,
+    /// Hits processed by older processor version
+    #[arg(long)]
+    outdated: bool,
+    /// Hits of session with id
+    #[arg(long)]
+    session: Option<String>,
+    /// Single hit, timestamp as shown in hit list
+    #[arg(long)]
+    hit: Option<String>,
+}
+
+/// Which stored hits a command works on, every hit if nothing is given
+#[derive(Args)]
+pub struct HitFilterArgs {
+    /// Only hits of session, can be given multiple times
+    #[arg(long)]
+    session: Vec<String>,
+    #[arg(long)]
+    shooter: Option<String>,
+    /// First day of hits, as YYYY-MM-DD
+    #[arg(long)]
+    from: Option<NaiveDate>,
+    /// Last day of hits, as YYYY-MM-DD
+    #[arg(long)]
+    to: Option<NaiveDate>,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[arg(long, value_enum, default_value = "csv")]
+    format: ExportFormat,
+    #[command(flatten)]
+    filter: HitFilterArgs,
+    /// Storage to read, defaults to the one in config
+    #[arg(long, value_enum)]
+    storage: Option<StorageBackend>,
+    /// Output file, standard output if not given
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ArchiveArgs {
+    #[command(flatten)]
+    filter: HitFilterArgs,
+    /// Storage to read, defaults to the one in config
+    #[arg(long, value_enum)]
+    storage: Option<StorageBackend>,
+    /// Output tar file, standard output if not given
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// Tar archive written by archive command
+    path: PathBuf,
+    /// Storage to merge into, defaults to the one in config
+    #[arg(long, value_enum)]
+    storage: Option<StorageBackend>,
+}
+
+#[derive(Args)]
+pub struct ReportArgs {
+    /// Id of session
+    #[arg(long)]
+    session: String,
+    #[arg(long, value_enum, default_value = "pdf")]
+    format: ReportFormat,
+    /// Storage to read, defaults to the one in config
+    #[arg(long, value_enum)]
+    storage: Option<StorageBackend>,
+    /// Output file, standard output if not given
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct HitsArgs {
+    #[command(flatten)]
+    filter: HitFilterArgs,
+    #[arg(long)]
+    lane: Option<String>,
+    /// Only that many latest hits
+    #[arg(long)]
+    last: Option<usize>,
+    /// Storage to read, defaults to the one in config
+    #[arg(long, value_enum)]
+    storage: Option<StorageBackend>,
+}
+
+#[derive(Args)]
+pub struct HitArgs {
+    /// Timestamp as shown in hit list
+    hit: String,
+    /// Storage to read, defaults to the one in config
+    #[arg(long, value_enum)]
+    storage: Option<StorageBackend>,
+}
+
+#[derive(Args)]
+pub struct SessionsArgs {
+    /// Storage to read, defaults to the one in config
+    #[arg(long, value_enum)]
+    storage: Option<StorageBackend>,
+}
+
+#[derive(Args)]
+pub struct RecognizeArgs {
+    /// Camera or lane frame to look at
+    image: PathBuf,
+    /// Image with found outline drawn on it
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct DetectArgs {
+    /// Clip file, `.lsc` or any video ffmpeg can read
+    #[arg(required_unless_present = "hit", conflicts_with = "hit")]
+    clip: Option<PathBuf>,
+    /// Clip of stored hit, timestamp as shown in hit list
+    #[arg(long)]
+    hit: Option<String>,
+    /// Storage to read hit from, defaults to the one in config
+    #[arg(long, value_enum)]
+    storage: Option<StorageBackend>,
+}
+
+#[derive(Args)]
+pub struct ZonesArgs {
+    /// Target image with zones outlined
+    image: PathBuf,
+    /// Zone map to write, same form as `zonemap.png` of target
+    #[arg(long, short, default_value = "zonemap.png")]
+    output: PathBuf,
+}
+
+impl HitFilterArgs {
+    fn queries(&self) -> Result<Vec<HitQuery>> {
+        let base = HitQuery {
+            shooter: self.shooter.clone(),
+            from: self
+                .from
+                .map(|d| local_time(d, NaiveTime::MIN))
+                .transpose()?,
+            to: self
+                .to
+                .map(|d| local_time(d, NaiveTime::from_hms_milli_opt(23, 59, 59, 999).unwrap()))
+                .transpose()?,
+            ..Default::default()
+        };
+        if self.session.is_empty() {
+            return Ok(vec![base]);
+        }
+        Ok(self
+            .session
+            .iter()
+            .map(|id| HitQuery {
+                session: Some(id.clone()),
+                ..base.clone()
+            })
+            .collect())
+    }
+}
+
+impl ReprocessArgs {
+    fn scope(&self) -> Result<ReprocessScope> {
+        if let Some(hit) = &self.hit {
+            return Ok(ReprocessScope::Hit(parse_timestamp(hit)?));
+        }
+        if let Some(session) = &self.session {
+            return Ok(ReprocessScope::Session(session.clone()));
+        }
+        if self.outdated {
+            return Ok(ReprocessScope::Outdated);
+        }
+        Ok(ReprocessScope::All)
+    }
+}
+
+fn local_time(date: NaiveDate, time: NaiveTime) -> Result<DateTime<Local>> {
+    date.and_time(time)
+        .and_local_timezone(Local)
+        .earliest()
+        .context("invalid local time")
+}
+
+fn parse_timestamp(text: &str) -> Result<DateTime<Local>> {
+    NaiveDateTime::parse_from_str(text, TIMESTAMP_UI_FORMAT)
+        .with_context(|| format!("timestamp must look like {TIMESTAMP_UI_FORMAT}"))?
+        .and_local_timezone(Local)
+        .single()
+        .context("ambiguous local time")
+}
+
+pub fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Reprocess(args) => reprocess(args.scope()?),
+        Command::Export(args) => export(args),
+        Command::Report(args) => report(args),
+        Command::Archive(args) => archive(args),
+        Command::Import(args) => import(args),
+        Command::Hits(args) => hits(args),
+        Command::Hit(args) => hit(args),
+        Command::Sessions(args) => sessions(args),
+        Command::Recognize(args) => recognize(args),
+        Command::Detect(args) => detect(args),
+        Command::Zones(args) => zones(args),
+    }
+}
+
+/// Storage of config unless another backend is given
+fn storage(backend: Option<StorageBackend>) -> Box<dyn HitStorage> {
+    let mut config = load_config();
+    if let Some(backend) = backend {
+        config.storage = backend;
+    }
+    open_storage(&config)
+}
+
+fn export(args: ExportArgs) -> Result<()> {
+    let mut storage = storage(args.storage);
+    let rows = collect_rows(&mut *storage, &args.filter.queries()?)?;
+
+    match &args.output {
+        Some(path) => {
+            let f = fs::File::create(path).with_context(|| format!("failed to create {path:?}"))?;
+            write_rows(&rows, args.format, BufWriter::new(f))?;
+            info!("Exported {} hits to {path:?}", rows.len());
+        }
+        None => write_rows(&rows, args.format, std::io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+fn report(args: ReportArgs) -> Result<()> {
+    let mut storage = storage(args.storage);
+    let report = build_report(&mut *storage, &load_targets(), &args.session)?;
+
+    match &args.output {
+        Some(path) => {
+            let f = fs::File::create(path).with_context(|| format!("failed to create {path:?}"))?;
+            write_report(&report, args.format, BufWriter::new(f))?;
+            info!("Saved report of session {} to {path:?}", args.session);
+        }
+        None => write_report(&report, args.format, std::io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+fn archive(args: ArchiveArgs) -> Result<()> {
+    let mut storage = storage(args.storage);
+    let targets = load_targets();
+    let queries = args.filter.queries()?;
+
+    match &args.output {
+        Some(path) => {
+            let f = fs::File::create(path).with_context(|| format!("failed to create {path:?}"))?;
+            let count = export_archive(&mut *storage, &targets, &queries, BufWriter::new(f))?;
+            info!("Archived {count} hits to {path:?}");
+        }
+        None => {
+            export_archive(&mut *storage, &targets, &queries, std::io::stdout().lock())?;
+        }
+    }
+    Ok(())
+}
+
+fn import(args: ImportArgs) -> Result<()> {
+    let mut storage = storage(args.storage);
+    let summary = import_archive(&mut *storage, &args.path)?;
+    info!("Imported {summary} from {:?}", args.path);
+    Ok(())
+}
+
+/// Process hits in scope with the same processor as the UI, returns once all are saved.
+/// Other unprocessed hits and deleted hits are left alone for the UI.
+fn reprocess(scope: ReprocessScope) -> Result<()> {
+    let config = load_config();
+    let targets = Arc::new(load_targets());
+    let mut storage = open_storage(&config);
+    let mut queue: VecDeque<_> = reprocess_hits(&mut *storage, &scope)
+        .context("failed to find hits to reprocess")?
+        .into();
+    info!("Reprocessing {} hits", queue.len());
+
+    let (bus_tx, bus_rx) = mpsc::channel();
+    let hit_processor = start_hit_processor(bus_tx.clone(), targets.clone());
+
+    let mut count = 0;
+    for event in bus_rx {
+        match event {
+            Event::HitProcessorReady if !process_next(&mut *storage, &bus_tx, &mut queue) => {
+                break;
+            }
+            Event::ProcessHit {
+                timestamp,
+                clip,
+                target_info,
+                target_id,
+            } => hit_processor.send(HitProcessorCommand::ProcessHit {
+                timestamp,
+                clip,
+                target_info,
+                target_id,
+            })?,
+            Event::ProcessedHit {
+                timestamp,
+                processed,
+            } => {
+                info!("Hit {timestamp} scored {}", processed.score);
+                save_processed(&mut *storage, &targets, timestamp, processed)
+                    .with_context(|| format!("failed to save hit {timestamp}"))?;
+                count += 1;
+            }
+            _ => {}
+        }
+    }
+
+    info!("Reprocessed {count} hits");
+    Ok(())
+}
+
+fn hits(args: HitsArgs) -> Result<()> {
+    let mut storage = storage(args.storage);
+    let mut out = std::io::stdout().lock();
+    for mut query in args.filter.queries()? {
+        query.lane = args.lane.clone();
+        if args.last.is_some() {
+            query.order = HitOrder::NewestFirst;
+            query.limit = args.last;
+        }
+        for (timestamp, hit) in storage.query_hits(&query)? {
+            let score = hit
+                .processed
+                .as_ref()
+                .map_or("-".to_string(), |p| p.score.to_string());
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{score}",
+                timestamp.format(TIMESTAMP_UI_FORMAT),
+                hit.lane.as_deref().unwrap_or("-"),
+                hit.session.as_deref().unwrap_or("-"),
+                hit.target_id.as_deref().unwrap_or("-"),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn hit(args: HitArgs) -> Result<()> {
+    let mut storage = storage(args.storage);
+    let timestamp = parse_timestamp(&args.hit)?;
+    let data = storage
+        .load_data(timestamp)
+        .with_context(|| format!("no hit {}", args.hit))?;
+    info!("Clip of hit is in {:?}", storage.hit_dir(timestamp));
+    let mut out = std::io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, &data)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn sessions(args: SessionsArgs) -> Result<()> {
+    let mut storage = storage(args.storage);
+    let mut sessions = storage.get_all_sessions()?;
+    sessions.sort_by_key(|s| s.started_at);
+    let mut out = std::io::stdout().lock();
+    for session in sessions {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            session.id,
+            session.shooter,
+            session.started_at.format(TIMESTAMP_UI_FORMAT),
+            session.ended_at.map_or("-".to_string(), |t| t
+                .format(TIMESTAMP_UI_FORMAT)
+                .to_string()),
+        )?;
+    }
+    Ok(())
+}
+
+fn recognize(args: RecognizeArgs) -> Result<()> {
+    let mut image = image::open(&args.image)
+        .with_context(|| format!("failed to open {:?}", args.image))?
+        .to_rgb8();
+    let target_info = recognize_target(&image).context("no target found on image")?;
+
+    if let Some(path) = &args.output {
+        let polygon: Vec<Point<f32>> = target_info.rect.iter().map(|p| p.into()).collect();
+        draw_hollow_polygon_mut(&mut image, &polygon, Rgb([0, 255, 0]));
+        image
+            .save(path)
+            .with_context(|| format!("failed to save {path:?}"))?;
+        info!("Saved target outline to {path:?}");
+    }
+    let mut out = std::io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, &target_info)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Laser position per frame as offset from first frame in milliseconds, x and y
+fn detect(args: DetectArgs) -> Result<()> {
+    let clip = match (&args.hit, &args.clip) {
+        (Some(hit), _) => storage(args.storage).load_clip(parse_timestamp(hit)?)?,
+        (None, Some(path)) => {
+            let codec = if path.extension().is_some_and(|e| e == "lsc") {
+                ClipCodec::Mjpeg
+            } else {
+                ClipCodec::Ffmpeg
+            };
+            codec
+                .load(path, Local::now())
+                .with_context(|| format!("failed to load {path:?}"))?
+        }
+        (None, None) => bail!("clip file or hit is needed"),
+    };
+
+    let start = clip.timestamps.first().copied();
+    let mut out = std::io::stdout().lock();
+    let mut found = 0;
+    for (frame, timestamp) in clip.frames.iter().zip(&clip.timestamps) {
+        let offset = start.map_or(0, |start| (*timestamp - start).num_milliseconds());
+        match find_red_laser(frame) {
+            Some(pos) => {
+                found += 1;
+                writeln!(out, "{offset}\t{:.1}\t{:.1}", pos.x, pos.y)?;
+            }
+            None => writeln!(out, "{offset}\t-\t-")?,
+        }
+    }
+    info!("Laser seen on {found} of {} frames", clip.frames.len());
+    Ok(())
+}
+
+fn zones(args: ZonesArgs) -> Result<()> {
+    let image = image::open(&args.image)
+        .with_context(|| format!("failed to open {:?}", args.image))?
+        .to_luma8();
+    let zone_map = ZoneMap::recognize(&image);
+    zone_map
+        .map()
+        .save(&args.output)
+        .with_context(|| format!("failed to save {:?}", args.output))?;
+    info!(
+        "Saved zone map with {} zones to {:?}",
+        zone_map.count(),
+        args.output
+    );
+    Ok(())
+}
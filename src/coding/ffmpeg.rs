@@ -1,150 +1,375 @@
 use std::{
-    error::Error,
-    io::Write,
+    fmt,
+    io::{BufReader, Read, Write},
     path::Path,
-    process::{Command, Stdio},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::OnceLock,
+    thread::JoinHandle,
 };
 
+use anyhow::{Context, Result, anyhow, bail};
 use image::RgbImage;
 
-pub fn save_video(frames: &[RgbImage], fps: u32, output_path: &Path) -> Result<(), Box<dyn Error>> {
-    if frames.is_empty() {
-        return Err("No frames to save".into());
-    }
-
-    let (width, height) = frames[0].dimensions();
-
-    // 1. Launch the ffmpeg subprocess
-    // We direct it to read raw RGB video from stdin and output MP4 (H.264)
-    let mut child = Command::new("ffmpeg")
-        .args([
-            "-y", // Overwrite output file if it exists
-            "-f",
-            "rawvideo", // Input format is raw video
-            "-pixel_format",
-            "rgb24", // Input pixel format (RgbImage is rgb24)
-            "-video_size",
-            &format!("{}x{}", width, height),
-            "-framerate",
-            &fps.to_string(),
-            "-i",
-            "-", // Read input from stdin
-            "-c:v",
-            "libx264", // Encode using H.264 (standard)
-            "-pix_fmt",
-            "yuv420p", // Ensure compatibility with browsers/players
-            "-preset",
-            "medium", // Balance between speed and compression
-            output_path.to_str().ok_or("Invalid path")?,
-        ])
-        .stdin(Stdio::piped()) // Pipe our data to ffmpeg's stdin
-        .stdout(Stdio::null()) // Show ffmpeg logs in console (optional)
-        .stderr(Stdio::null()) // Show errors in console
-        .spawn()?;
-
-    // 2. Write frames to the ffmpeg stdin
-    let mut stdin = child.stdin.take().ok_or("Failed to open stdin")?;
+/// Oldest supported version, `-fps_mode` appeared in 5.1
+const MIN_VERSION: (u32, u32) = (5, 1);
+/// How many trailing stderr lines are attached to errors
+const STDERR_TAIL_LINES: usize = 15;
 
-    for frame in frames {
-        // Verify dimensions to prevent ffmpeg errors
-        if frame.dimensions() != (width, height) {
-            return Err("All frames must have the same dimensions".into());
+/// Exact rational frame rate such as 30000/1001
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fps {
+    pub num: u32,
+    pub den: u32,
+}
+
+impl Fps {
+    pub fn new(num: u32, den: u32) -> Self {
+        Self {
+            num,
+            den: den.max(1),
+        }
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let (num, den) = text.trim().split_once('/').unwrap_or((text.trim(), "1"));
+        let fps = Self::new(num.parse()?, den.parse()?);
+        if fps.num == 0 {
+            bail!("invalid frame rate '{text}'");
         }
+        Ok(fps)
+    }
+}
+
+impl From<u32> for Fps {
+    fn from(fps: u32) -> Self {
+        Self::new(fps, 1)
+    }
+}
+
+impl fmt::Display for Fps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+/// Check once that `ffmpeg` and `ffprobe` exist and are recent enough
+pub fn check_ffmpeg() -> Result<()> {
+    static CHECKED: OnceLock<Result<(), String>> = OnceLock::new();
+    CHECKED
+        .get_or_init(|| {
+            ["ffmpeg", "ffprobe"]
+                .into_iter()
+                .try_for_each(check_binary)
+                .map_err(|e| format!("{e:#}"))
+        })
+        .clone()
+        .map_err(|e| anyhow!(e))
+}
+
+fn check_binary(name: &str) -> Result<()> {
+    let output = Command::new(name)
+        .arg("-version")
+        .output()
+        .with_context(|| format!("`{name}` not found, is it installed?"))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(2))
+        .with_context(|| format!("unexpected `{name} -version` output"))?;
+
+    // Git builds are versioned like N-112345-gdeadbeef and are always new enough
+    if version.starts_with("N-") {
+        return Ok(());
+    }
+    let mut parts = version
+        .trim_start_matches('n')
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|v| v.parse::<u32>().unwrap_or(0));
+    let found = (parts.next().unwrap_or(0), parts.next().unwrap_or(0));
+    if found < MIN_VERSION {
+        bail!(
+            "`{name}` version {version} is too old, need at least {}.{}",
+            MIN_VERSION.0,
+            MIN_VERSION.1
+        );
+    }
+    Ok(())
+}
 
-        // Write the raw bytes (R, G, B, R, G, B...)
-        stdin.write(frame.as_raw())?;
+/// Collects stderr of child process in background so it can't block on full pipe
+struct StderrCollector(Option<JoinHandle<String>>);
+
+impl StderrCollector {
+    fn start(child: &mut Child) -> Self {
+        Self(child.stderr.take().map(|mut stderr| {
+            std::thread::spawn(move || {
+                let mut out = String::new();
+                let _ = stderr.read_to_string(&mut out);
+                out
+            })
+        }))
     }
 
-    // 3. Close stdin to signal EOF and wait for ffmpeg to finish
-    drop(stdin); // Explicitly close stdin
+    fn tail(&mut self) -> String {
+        let out = self
+            .0
+            .take()
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default();
+        let lines: Vec<&str> = out.lines().collect();
+        lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
+    }
+}
 
+/// Wait for child and turn failure into error with its stderr output
+fn wait_child(name: &str, child: &mut Child, stderr: &mut StderrCollector) -> Result<()> {
     let status = child.wait()?;
+    let tail = stderr.tail();
+    if !status.success() {
+        bail!("{name} exited with {status}:\n{tail}");
+    }
+    Ok(())
+}
+
+/// Streaming H.264 encoder, frames are piped to ffmpeg as they are written
+pub struct VideoEncoder {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stderr: StderrCollector,
+    size: (u32, u32),
+}
+
+impl VideoEncoder {
+    pub fn new(output_path: &Path, width: u32, height: u32, fps: Fps) -> Result<Self> {
+        check_ffmpeg()?;
+
+        let mut child = Command::new("ffmpeg")
+            .args(["-y", "-hide_banner", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pixel_format", "rgb24"])
+            .args(["-video_size", &format!("{width}x{height}")])
+            .args(["-framerate", &fps.to_string()])
+            .args(["-i", "-"])
+            .args([
+                "-c:v", "libx264", "-pix_fmt", "yuv420p", "-preset", "medium",
+            ])
+            .arg(output_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to start ffmpeg")?;
+        let stdin = child.stdin.take();
+        let stderr = StderrCollector::start(&mut child);
+
+        Ok(Self {
+            child,
+            stdin,
+            stderr,
+            size: (width, height),
+        })
+    }
 
-    if status.success() {
+    pub fn write_frame(&mut self, frame: &RgbImage) -> Result<()> {
+        if frame.dimensions() != self.size {
+            bail!(
+                "frame is {:?}, encoder expects {:?}",
+                frame.dimensions(),
+                self.size
+            );
+        }
+        let Some(stdin) = &mut self.stdin else {
+            bail!("encoder is already finished");
+        };
+        if let Err(e) = stdin.write_all(frame.as_raw()) {
+            // Pipe breaks when ffmpeg dies, its stderr tells why
+            self.stdin = None;
+            wait_child("ffmpeg", &mut self.child, &mut self.stderr)?;
+            return Err(e).context("ffmpeg stopped accepting frames");
+        }
         Ok(())
-    } else {
-        Err(format!("FFmpeg exited with error code: {}", status).into())
-    }
-}
-
-use std::io::Read;
-
-pub fn load_video(input_path: &Path) -> Result<(Vec<RgbImage>, u32), Box<dyn Error>> {
-    // 1. Get Video Metadata (Width, Height, FPS) using ffprobe
-    // We need these to calculate buffer size and return the correct FPS.
-    // Command: ffprobe -v error -select_streams v:0 -show_entries stream=width,height,r_frame_rate -of csv=p=0 input.mp4
-    let probe_output = Command::new("ffprobe")
-        .args([
-            "-v",
-            "error",
-            "-select_streams",
-            "v:0",
-            "-show_entries",
-            "stream=width,height,r_frame_rate",
-            "-of",
-            "csv=p=0",
-            input_path.to_str().ok_or("Invalid path")?,
-        ])
-        .output()?;
-
-    if !probe_output.status.success() {
-        return Err("Failed to probe video file (is ffprobe installed?)".into());
-    }
-
-    let output_str = String::from_utf8(probe_output.stdout)?;
-    let parts: Vec<&str> = output_str.trim().split(',').collect();
-
-    if parts.len() != 3 {
-        return Err("Failed to parse ffprobe output".into());
-    }
-
-    let width: u32 = parts[0].parse()?;
-    let height: u32 = parts[1].parse()?;
-
-    // FPS often comes as "30/1" or "30000/1001". We parse and round to nearest u32.
-    let fps_parts: Vec<&str> = parts[2].split('/').collect();
-    let fps_num: f64 = fps_parts[0].parse()?;
-    let fps_den: f64 = fps_parts.get(1).unwrap_or(&"1").parse()?;
-    let fps = (fps_num / fps_den).round() as u32;
-
-    // 2. Launch ffmpeg to decode video to raw RGB24
-    let mut child = Command::new("ffmpeg")
-        .args([
-            "-i",
-            input_path.to_str().ok_or("Invalid path")?,
-            "-f",
-            "rawvideo",
-            "-pix_fmt",
-            "rgb24",
-            "-", // Output to stdout
-        ])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null()) // Hide logs
-        .spawn()?;
-
-    let mut stdout = child.stdout.take().ok_or("Failed to open stdout")?;
-
-    // 3. Read frames from stdout
-    let mut frames = Vec::new();
-    // RGB24 means 3 bytes per pixel
-    let frame_size = (width * height * 3) as usize;
-    let mut buffer = vec![0u8; frame_size];
-
-    // Read exact number of bytes for one frame repeatedly until EOF
-    while stdout.read_exact(&mut buffer).is_ok() {
-        // Create RgbImage from the raw buffer
-        if let Some(img) = RgbImage::from_raw(width, height, buffer.clone()) {
-            frames.push(img);
+    }
+
+    /// Close input and wait for ffmpeg to write the file
+    pub fn finish(mut self) -> Result<()> {
+        self.stdin = None;
+        wait_child("ffmpeg", &mut self.child, &mut self.stderr)
+    }
+}
+
+impl Drop for VideoEncoder {
+    fn drop(&mut self) {
+        // Unfinished encoder, don't leave ffmpeg running
+        if self.stdin.take().is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
         }
     }
+}
 
-    // Wait for ffmpeg to finish cleanly
-    let status = child.wait()?;
-    if !status.success() {
-        // You might choose to ignore this if you successfully read frames,
-        // but it's good practice to check.
-        return Err("ffmpeg process finished with error".into());
+/// Streaming decoder yielding frames with their presentation time in seconds
+pub struct VideoDecoder {
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+    stderr: StderrCollector,
+    width: u32,
+    height: u32,
+    fps: Fps,
+    timestamps: Vec<f64>,
+    index: usize,
+    done: bool,
+}
+
+impl VideoDecoder {
+    pub fn open(input_path: &Path) -> Result<Self> {
+        check_ffmpeg()?;
+
+        let (width, height, fps) = probe_stream(input_path)?;
+        let timestamps = probe_timestamps(input_path)?;
+
+        let mut child = Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "error", "-i"])
+            .arg(input_path)
+            .args(["-fps_mode", "passthrough"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to start ffmpeg")?;
+        let stdout = BufReader::new(child.stdout.take().context("failed to open stdout")?);
+        let stderr = StderrCollector::start(&mut child);
+
+        Ok(Self {
+            child,
+            stdout,
+            stderr,
+            width,
+            height,
+            fps,
+            timestamps,
+            index: 0,
+            done: false,
+        })
+    }
+
+    pub fn fps(&self) -> Fps {
+        self.fps
+    }
+
+    /// Presentation time of every frame in seconds, known before decoding
+    pub fn timestamps(&self) -> &[f64] {
+        &self.timestamps
+    }
+
+    fn read_frame(&mut self) -> Result<Option<(f64, RgbImage)>> {
+        let mut buffer = vec![0u8; (self.width * self.height * 3) as usize];
+        match self.stdout.read_exact(&mut buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                wait_child("ffmpeg", &mut self.child, &mut self.stderr)?;
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        let timestamp = self
+            .timestamps
+            .get(self.index)
+            .copied()
+            .unwrap_or(self.index as f64 / self.fps.as_f64());
+        self.index += 1;
+        let frame = RgbImage::from_raw(self.width, self.height, buffer)
+            .context("decoded frame has wrong size")?;
+        Ok(Some((timestamp, frame)))
+    }
+}
+
+impl Iterator for VideoDecoder {
+    type Item = Result<(f64, RgbImage)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_frame().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
+}
+
+impl Drop for VideoDecoder {
+    fn drop(&mut self) {
+        // No-op if ffmpeg has already exited
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn run_ffprobe(input_path: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(args)
+        .args(["-of", "csv=p=0"])
+        .arg(input_path)
+        .output()
+        .context("failed to start ffprobe")?;
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed on {input_path:?}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
     }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Width, height and frame rate of first video stream
+fn probe_stream(input_path: &Path) -> Result<(u32, u32, Fps)> {
+    let output = run_ffprobe(
+        input_path,
+        &["-show_entries", "stream=width,height,r_frame_rate"],
+    )?;
+    let parts: Vec<&str> = output.trim().split(',').collect();
+    let [width, height, fps] = parts.as_slice() else {
+        bail!("unexpected ffprobe output '{}'", output.trim());
+    };
+    Ok((width.parse()?, height.parse()?, Fps::parse(fps)?))
+}
+
+/// Presentation times of all frames in seconds, sorted
+fn probe_timestamps(input_path: &Path) -> Result<Vec<f64>> {
+    let output = run_ffprobe(input_path, &["-show_entries", "packet=pts_time"])?;
+    let mut timestamps: Vec<f64> = output
+        .lines()
+        .filter_map(|line| line.trim().trim_end_matches(',').parse().ok())
+        .collect();
+    // Packets come in decode order
+    timestamps.sort_by(f64::total_cmp);
+    Ok(timestamps)
+}
+
+pub fn save_video(frames: &[RgbImage], fps: Fps, output_path: &Path) -> Result<()> {
+    let Some(first) = frames.first() else {
+        bail!("no frames to save");
+    };
+
+    let (width, height) = first.dimensions();
+    let mut encoder = VideoEncoder::new(output_path, width, height, fps)?;
+    for frame in frames {
+        encoder.write_frame(frame)?;
+    }
+    encoder.finish()
+}
 
+pub fn load_video(input_path: &Path) -> Result<(Vec<(f64, RgbImage)>, Fps)> {
+    let decoder = VideoDecoder::open(input_path)?;
+    let fps = decoder.fps();
+    let frames = decoder.collect::<Result<Vec<_>>>()?;
     Ok((frames, fps))
 }
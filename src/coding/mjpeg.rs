@@ -0,0 +1,307 @@
+use std::{
+    fs,
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local, TimeDelta};
+use image::{ImageFormat, RgbImage, codecs::jpeg::JpegEncoder};
+
+use crate::coding::Clip;
+
+/// Version 1 layout: magic, fps (u32 LE), frame count (u32 LE),
+/// then every frame as length (u32 LE) followed by JPEG bytes
+const MAGIC_V1: &[u8; 4] = b"LSC1";
+/// Version 2 layout: magic, frame count (u32 LE), then every frame as capture time
+/// (i64 LE microseconds since epoch), length (u32 LE) and JPEG bytes
+const MAGIC_V2: &[u8; 4] = b"LSC2";
+const JPEG_QUALITY: u8 = 85;
+
+/// Frame count of file that was not finished, frames are read until end of file
+const COUNT_UNKNOWN: u32 = u32::MAX;
+
+/// Streaming writer for clips of unknown length such as session recordings
+pub struct ClipWriter {
+    w: BufWriter<fs::File>,
+    count: u32,
+    buf: Vec<u8>,
+}
+
+impl ClipWriter {
+    pub fn create(output_path: &Path) -> Result<Self> {
+        let mut w = BufWriter::new(fs::File::create(output_path)?);
+        w.write_all(MAGIC_V2)?;
+        w.write_all(&COUNT_UNKNOWN.to_le_bytes())?;
+        Ok(Self {
+            w,
+            count: 0,
+            buf: Vec::new(),
+        })
+    }
+
+    pub fn write_frame(&mut self, frame: &RgbImage, timestamp: DateTime<Local>) -> Result<()> {
+        self.buf.clear();
+        JpegEncoder::new_with_quality(&mut self.buf, JPEG_QUALITY).encode_image(frame)?;
+        self.w
+            .write_all(&timestamp.timestamp_micros().to_le_bytes())?;
+        self.w.write_all(&(self.buf.len() as u32).to_le_bytes())?;
+        self.w.write_all(&self.buf)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Write final frame count into header
+    pub fn finish(mut self) -> Result<()> {
+        self.w.seek(SeekFrom::Start(MAGIC_V2.len() as u64))?;
+        self.w.write_all(&self.count.to_le_bytes())?;
+        self.w.flush()?;
+        Ok(())
+    }
+}
+
+pub fn save_video(clip: &Clip, output_path: &Path) -> Result<()> {
+    if clip.frames.is_empty() {
+        bail!("no frames to save");
+    }
+    if clip.frames.len() != clip.timestamps.len() {
+        bail!(
+            "clip has {} frames but {} timestamps",
+            clip.frames.len(),
+            clip.timestamps.len()
+        );
+    }
+
+    let mut writer = ClipWriter::create(output_path)?;
+    for (frame, timestamp) in clip.frames.iter().zip(&clip.timestamps) {
+        writer.write_frame(frame, *timestamp)?;
+    }
+    writer.finish()
+}
+
+/// Load clip, version 1 files have no timestamps and are spaced from `start` by their fps
+pub fn load_video(input_path: &Path, start: DateTime<Local>) -> Result<Clip> {
+    let mut r = BufReader::new(fs::File::open(input_path)?);
+
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    match &magic {
+        MAGIC_V1 => {}
+        MAGIC_V2 => return read_frames(&mut r, |_| true),
+        _ => bail!("clip {input_path:?} has unknown format"),
+    }
+
+    let fps = read_u32(&mut r)?;
+    let count = read_u32(&mut r)?;
+    // Every frame has at least its length
+    ensure_left(&mut r, count as u64 * 4)?;
+    let mut frames = Vec::with_capacity(count as usize);
+    let mut buf = Vec::new();
+    for _ in 0..count {
+        let len = read_u32(&mut r)?;
+        ensure_left(&mut r, len as u64)?;
+        buf.resize(len as usize, 0);
+        r.read_exact(&mut buf)?;
+        let frame = image::load_from_memory_with_format(&buf, ImageFormat::Jpeg)?;
+        frames.push(frame.to_rgb8());
+    }
+    Ok(Clip::with_fps(frames, start, fps.max(1) as f64))
+}
+
+/// Clip file opened for random access, frames are decoded one at a time
+pub struct FrameReader {
+    r: BufReader<fs::File>,
+    /// Offset and length of every JPEG frame
+    frames: Vec<(u64, u32)>,
+}
+
+impl FrameReader {
+    /// Index frames without decoding them, version 1 files are spaced from `start` by their fps
+    pub fn open(input_path: &Path, start: DateTime<Local>) -> Result<(Self, Vec<DateTime<Local>>)> {
+        let mut r = BufReader::new(fs::File::open(input_path)?);
+
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        let fps = match &magic {
+            MAGIC_V1 => Some(read_u32(&mut r)?.max(1)),
+            MAGIC_V2 => None,
+            _ => bail!("clip {input_path:?} has unknown format"),
+        };
+
+        let count = read_u32(&mut r)?;
+        let mut frames = Vec::new();
+        let mut timestamps = Vec::new();
+        for i in 0..count {
+            let timestamp = match fps {
+                Some(fps) => start + TimeDelta::microseconds(i as i64 * 1_000_000 / fps as i64),
+                None => match read_timestamp(&mut r) {
+                    Ok(v) => v,
+                    // Unfinished recording ends wherever writing stopped
+                    Err(_) if count == COUNT_UNKNOWN => break,
+                    Err(e) => return Err(e),
+                },
+            };
+            let len = match read_u32(&mut r) {
+                Ok(v) => v,
+                Err(e) if count == COUNT_UNKNOWN && is_eof(&e) => break,
+                Err(e) => return Err(e),
+            };
+            match ensure_left(&mut r, len as u64) {
+                Ok(()) => {}
+                Err(_) if count == COUNT_UNKNOWN => break,
+                Err(e) => return Err(e),
+            }
+            frames.push((r.stream_position()?, len));
+            timestamps.push(timestamp);
+            r.seek_relative(len as i64)?;
+        }
+
+        Ok((Self { r, frames }, timestamps))
+    }
+
+    /// Frame lengths were checked against file size when it was opened
+    pub fn frame(&mut self, index: usize) -> Result<RgbImage> {
+        let (offset, len) = *self.frames.get(index).context("frame index out of range")?;
+        self.r.seek(SeekFrom::Start(offset))?;
+        let mut buf = vec![0u8; len as usize];
+        self.r.read_exact(&mut buf)?;
+        let frame = image::load_from_memory_with_format(&buf, ImageFormat::Jpeg)?;
+        Ok(frame.to_rgb8())
+    }
+}
+
+/// Load only frames captured within `from..=to`, others are skipped without decoding
+pub fn load_range(input_path: &Path, from: DateTime<Local>, to: DateTime<Local>) -> Result<Clip> {
+    let mut r = BufReader::new(fs::File::open(input_path)?);
+
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC_V2 {
+        bail!("clip {input_path:?} has no frame timestamps");
+    }
+    read_frames(&mut r, |timestamp| timestamp >= from && timestamp <= to)
+}
+
+/// Read version 2 frames following the magic, decoding those accepted by `keep`
+fn read_frames(
+    r: &mut BufReader<fs::File>,
+    keep: impl Fn(DateTime<Local>) -> bool,
+) -> Result<Clip> {
+    let count = read_u32(r)?;
+
+    let mut clip = Clip::default();
+    let mut buf = Vec::new();
+    for _ in 0..count {
+        match read_frame(r, &keep, &mut buf) {
+            Ok(Some((timestamp, frame))) => {
+                clip.frames.push(frame);
+                clip.timestamps.push(timestamp);
+            }
+            Ok(None) => {}
+            // Unfinished recording ends wherever writing stopped
+            Err(e) if count == COUNT_UNKNOWN && is_eof(&e) => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(clip)
+}
+
+/// Read next frame, `None` if it was skipped
+fn read_frame(
+    r: &mut BufReader<fs::File>,
+    keep: impl Fn(DateTime<Local>) -> bool,
+    buf: &mut Vec<u8>,
+) -> Result<Option<(DateTime<Local>, RgbImage)>> {
+    let timestamp = read_timestamp(r)?;
+    let len = read_u32(r)?;
+    ensure_left(r, len as u64)?;
+    if !keep(timestamp) {
+        r.seek_relative(len as i64)?;
+        return Ok(None);
+    }
+    buf.resize(len as usize, 0);
+    r.read_exact(buf)?;
+    let frame = image::load_from_memory_with_format(buf, ImageFormat::Jpeg)?;
+    Ok(Some((timestamp, frame.to_rgb8())))
+}
+
+/// Fail as end of file if fewer than `len` bytes are left, so corrupt lengths can't force
+/// huge allocations
+fn ensure_left(r: &mut BufReader<fs::File>, len: u64) -> Result<()> {
+    let left = r
+        .get_ref()
+        .metadata()?
+        .len()
+        .saturating_sub(r.stream_position()?);
+    if len > left {
+        return Err(std::io::Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("clip has {left} bytes left but {len} are expected"),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn read_timestamp(r: &mut impl Read) -> Result<DateTime<Local>> {
+    let mut micros = [0u8; 8];
+    r.read_exact(&mut micros)?;
+    Ok(DateTime::from_timestamp_micros(i64::from_le_bytes(micros))
+        .context("invalid frame timestamp")?
+        .with_timezone(&Local))
+}
+
+/// Reading stopped at end of file, as it does where unfinished recording was cut off
+fn is_eof(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof)
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recording cut off two bytes into length of its second frame
+    fn truncated_recording(path: &Path) -> DateTime<Local> {
+        let start = Local::now();
+        let mut writer = ClipWriter::create(path).unwrap();
+        writer.write_frame(&RgbImage::new(8, 8), start).unwrap();
+        writer.write_frame(&RgbImage::new(8, 8), start).unwrap();
+        drop(writer);
+
+        let first_len = {
+            let data = fs::read(path).unwrap();
+            u32::from_le_bytes(data[16..20].try_into().unwrap()) as u64
+        };
+        let cut = 8 + (8 + 4 + first_len) + 8 + 2;
+        fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(cut)
+            .unwrap();
+        start
+    }
+
+    #[test]
+    fn unfinished_recording_ends_at_truncated_length() {
+        let path = std::env::temp_dir().join(format!("mjpeg-test-{}.lsc", std::process::id()));
+        let start = truncated_recording(&path);
+
+        let opened = FrameReader::open(&path, start);
+        let loaded = load_video(&path, start);
+        fs::remove_file(&path).unwrap();
+
+        let (mut reader, timestamps) = opened.unwrap();
+        assert_eq!(timestamps.len(), 1);
+        assert_eq!(reader.frame(0).unwrap().dimensions(), (8, 8));
+        assert_eq!(loaded.unwrap().frames.len(), 1);
+    }
+}
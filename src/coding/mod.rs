@@ -1 +1,273 @@
 pub mod ffmpeg;
+pub mod mjpeg;
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local, TimeDelta};
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    capturer::CapturedFrame,
+    coding::ffmpeg::{Fps, VideoDecoder},
+};
+
+/// Width of hit list thumbnails, height follows frame aspect
+const THUMBNAIL_WIDTH: u32 = 96;
+/// Decoded frames kept for stepping back in clips played through ffmpeg
+const RECENT_FRAMES: usize = 30;
+
+/// Hit clip frames with the time every frame was captured
+#[derive(Clone, Default)]
+pub struct Clip {
+    pub frames: Vec<RgbImage>,
+    pub timestamps: Vec<DateTime<Local>>,
+}
+
+impl Clip {
+    pub fn from_captured(frames: &[Arc<CapturedFrame>]) -> Self {
+        Self {
+            frames: frames.iter().map(|f| f.image.clone()).collect(),
+            timestamps: frames.iter().map(|f| f.timestamp).collect(),
+        }
+    }
+
+    /// Clip saved without timestamps, frame times are given as offsets from start in seconds
+    pub fn with_offsets(frames: Vec<RgbImage>, start: DateTime<Local>, offsets: &[f64]) -> Self {
+        let timestamps = offsets
+            .iter()
+            .map(|v| start + TimeDelta::microseconds((v * 1e6) as i64))
+            .collect();
+        Self { frames, timestamps }
+    }
+
+    /// Clip saved without timestamps, frames are evenly spaced
+    pub fn with_fps(frames: Vec<RgbImage>, start: DateTime<Local>, fps: f64) -> Self {
+        let offsets: Vec<f64> = (0..frames.len()).map(|i| i as f64 / fps).collect();
+        Self::with_offsets(frames, start, &offsets)
+    }
+
+    /// Small copy of frame captured closest to `at`, none for empty clip
+    pub fn thumbnail(&self, at: DateTime<Local>) -> Option<RgbImage> {
+        let index = (0..self.frames.len()).min_by_key(|i| {
+            self.timestamps.get(*i).map_or(i64::MAX, |t| {
+                (*t - at).num_microseconds().unwrap_or(i64::MAX).abs()
+            })
+        })?;
+        let frame = &self.frames[index];
+        let height = THUMBNAIL_WIDTH * frame.height() / frame.width().max(1);
+        Some(image::imageops::thumbnail(
+            frame,
+            THUMBNAIL_WIDTH,
+            height.max(1),
+        ))
+    }
+
+    /// Average frame rate over whole clip
+    pub fn fps(&self) -> f64 {
+        match (self.timestamps.first(), self.timestamps.last()) {
+            (Some(first), Some(last)) if self.timestamps.len() > 1 && last > first => {
+                (self.timestamps.len() - 1) as f64 / (*last - *first).as_seconds_f64()
+            }
+            _ => 20.0,
+        }
+    }
+}
+
+/// Codec used to store hit clips
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipCodec {
+    /// Built-in JPEG frame container, needs no external tools
+    #[default]
+    Mjpeg,
+    /// H.264 mp4 through `ffmpeg` executable
+    Ffmpeg,
+}
+
+impl ClipCodec {
+    pub const ALL: [ClipCodec; 2] = [ClipCodec::Mjpeg, ClipCodec::Ffmpeg];
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            ClipCodec::Mjpeg => "clip.lsc",
+            ClipCodec::Ffmpeg => "clip.mp4",
+        }
+    }
+
+    pub fn save(&self, clip: &Clip, output_path: &Path) -> Result<()> {
+        match self {
+            ClipCodec::Mjpeg => mjpeg::save_video(clip, output_path),
+            ClipCodec::Ffmpeg => {
+                let fps = Fps::new((clip.fps() * 1000.0).round() as u32, 1000);
+                ffmpeg::save_video(&clip.frames, fps, output_path)?;
+                // mp4 only keeps constant rate, real capture times go to sidecar
+                let f = fs::File::create(output_path.with_extension("json"))?;
+                serde_json::to_writer(BufWriter::new(f), &clip.timestamps)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Load clip, `start` is used as first frame time for clips saved without timestamps
+    pub fn load(&self, input_path: &Path, start: DateTime<Local>) -> Result<Clip> {
+        match self {
+            ClipCodec::Mjpeg => mjpeg::load_video(input_path, start),
+            ClipCodec::Ffmpeg => {
+                let (frames, _fps) = ffmpeg::load_video(input_path)?;
+                let (offsets, frames): (Vec<f64>, Vec<RgbImage>) = frames.into_iter().unzip();
+
+                let sidecar = input_path.with_extension("json");
+                if sidecar.exists() {
+                    let f = fs::File::open(sidecar)?;
+                    let timestamps: Vec<DateTime<Local>> =
+                        serde_json::from_reader(BufReader::new(f))?;
+                    if timestamps.len() == frames.len() {
+                        return Ok(Clip { frames, timestamps });
+                    }
+                }
+                let first = offsets.first().copied().unwrap_or(0.0);
+                let offsets: Vec<f64> = offsets.iter().map(|v| v - first).collect();
+                Ok(Clip::with_offsets(frames, start, &offsets))
+            }
+        }
+    }
+}
+
+/// Find clip saved in dir with any codec
+pub fn find_clip(dir: &Path) -> Option<(ClipCodec, PathBuf)> {
+    ClipCodec::ALL
+        .into_iter()
+        .map(|codec| (codec, dir.join(codec.file_name())))
+        .find(|(_, path)| path.exists())
+}
+
+/// Load clip saved in dir with any codec
+pub fn load_clip(dir: &Path, start: DateTime<Local>) -> Result<Clip> {
+    let Some((codec, path)) = find_clip(dir) else {
+        bail!("no clip found in {dir:?}");
+    };
+    codec.load(&path, start)
+}
+
+/// Clip opened for playback, frames are decoded only when asked for
+pub struct ClipReader {
+    timestamps: Vec<DateTime<Local>>,
+    source: FrameSource,
+}
+
+enum FrameSource {
+    /// Frames decoded already, such as session recording excerpts
+    Memory(Vec<RgbImage>),
+    Mjpeg(mjpeg::FrameReader),
+    /// Decoder only goes forward, latest frames are kept for stepping back and it is
+    /// restarted for frames before them
+    Ffmpeg {
+        path: PathBuf,
+        decoder: VideoDecoder,
+        /// Frames right before `next`
+        recent: VecDeque<RgbImage>,
+        /// Index of frame decoder yields next
+        next: usize,
+    },
+}
+
+impl From<Clip> for ClipReader {
+    fn from(clip: Clip) -> Self {
+        Self {
+            timestamps: clip.timestamps,
+            source: FrameSource::Memory(clip.frames),
+        }
+    }
+}
+
+impl ClipReader {
+    /// Open clip saved in dir with any codec, `start` is first frame time for clips saved without timestamps
+    pub fn open(dir: &Path, start: DateTime<Local>) -> Result<Self> {
+        let Some((codec, path)) = find_clip(dir) else {
+            bail!("no clip found in {dir:?}");
+        };
+        match codec {
+            ClipCodec::Mjpeg => {
+                let (reader, timestamps) = mjpeg::FrameReader::open(&path, start)?;
+                Ok(Self {
+                    timestamps,
+                    source: FrameSource::Mjpeg(reader),
+                })
+            }
+            ClipCodec::Ffmpeg => {
+                let decoder = VideoDecoder::open(&path)?;
+                let offsets = decoder.timestamps();
+                let first = offsets.first().copied().unwrap_or(0.0);
+                let mut timestamps: Vec<_> = offsets
+                    .iter()
+                    .map(|v| start + TimeDelta::microseconds(((v - first) * 1e6) as i64))
+                    .collect();
+
+                let sidecar = path.with_extension("json");
+                if sidecar.exists() {
+                    let f = fs::File::open(sidecar)?;
+                    let captured: Vec<DateTime<Local>> =
+                        serde_json::from_reader(BufReader::new(f))?;
+                    if captured.len() == timestamps.len() {
+                        timestamps = captured;
+                    }
+                }
+                Ok(Self {
+                    timestamps,
+                    source: FrameSource::Ffmpeg {
+                        path,
+                        decoder,
+                        recent: VecDeque::new(),
+                        next: 0,
+                    },
+                })
+            }
+        }
+    }
+
+    /// Capture time of every frame
+    pub fn timestamps(&self) -> &[DateTime<Local>] {
+        &self.timestamps
+    }
+
+    pub fn frame(&mut self, index: usize) -> Result<RgbImage> {
+        match &mut self.source {
+            FrameSource::Memory(frames) => frames
+                .get(index)
+                .cloned()
+                .context("frame index out of range"),
+            FrameSource::Mjpeg(reader) => reader.frame(index),
+            FrameSource::Ffmpeg {
+                path,
+                decoder,
+                recent,
+                next,
+            } => {
+                if index < *next - recent.len() {
+                    *decoder = VideoDecoder::open(path)?;
+                    recent.clear();
+                    *next = 0;
+                }
+                while *next <= index {
+                    let Some(frame) = decoder.next() else {
+                        bail!("frame index out of range");
+                    };
+                    recent.push_back(frame?.1);
+                    *next += 1;
+                    if recent.len() > RECENT_FRAMES {
+                        recent.pop_front();
+                    }
+                }
+                Ok(recent[recent.len() - (*next - index)].clone())
+            }
+        }
+    }
+}
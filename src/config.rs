@@ -0,0 +1,40 @@
+use std::{fs, io::BufReader, path::Path};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{
+    api::ApiConfig, coding::ClipCodec, hits::storage::StorageBackend, recorder::RecorderConfig,
+    recording::RecordingConfig,
+};
+
+const CONFIG_PATH: &str = "data/config.json";
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct AppConfig {
+    /// Codec for newly saved hit clips
+    pub clip_codec: ClipCodec,
+    /// Backend for hit metadata and sessions, clips are always files
+    pub storage: StorageBackend,
+    pub recorder: RecorderConfig,
+    /// Continuous recording of sessions
+    pub recording: RecordingConfig,
+    /// HTTP server for scoreboards
+    pub api: ApiConfig,
+}
+
+pub fn load_config() -> AppConfig {
+    if !Path::new(CONFIG_PATH).exists() {
+        return AppConfig::default();
+    }
+
+    let config: Result<AppConfig> = fs::File::open(CONFIG_PATH)
+        .map_err(anyhow::Error::from)
+        .and_then(|f| Ok(serde_json::from_reader(BufReader::new(f))?));
+    config.unwrap_or_else(|e| {
+        error!("failed to load config from {CONFIG_PATH}: {e:?}");
+        AppConfig::default()
+    })
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, fs, io::BufReader, path::Path};
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::timer::ShotTiming;
+
+pub mod runner;
+
+const DRILLS_PATH: &str = "drills";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Scoring {
+    /// Points minus penalties divided by total time
+    HitFactor,
+    /// Points minus penalties
+    PointsMinusPenalties,
+}
+
+fn default_strings() -> u32 {
+    1
+}
+
+fn default_min_delay() -> f32 {
+    2.0
+}
+
+fn default_max_delay() -> f32 {
+    4.0
+}
+
+fn default_miss_penalty() -> f32 {
+    10.0
+}
+
+/// Drill definition as stored in `drills/<id>.json`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DrillDefinition {
+    pub name: String,
+    /// Shots per string
+    pub shots: u32,
+    #[serde(default = "default_strings")]
+    pub strings: u32,
+    /// String time limit in seconds
+    #[serde(default)]
+    pub time_limit: Option<f32>,
+    /// Zones that count, all zones count if empty
+    #[serde(default)]
+    pub required_zones: Vec<u8>,
+    pub scoring: Scoring,
+    /// Penalty for each missing shot or shot outside required zones
+    #[serde(default = "default_miss_penalty")]
+    pub miss_penalty: f32,
+    /// Random start delay bounds in seconds
+    #[serde(default = "default_min_delay")]
+    pub min_delay: f32,
+    #[serde(default = "default_max_delay")]
+    pub max_delay: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DrillShot {
+    pub timestamp: DateTime<Local>,
+    pub timing: ShotTiming,
+    pub zone: Option<u8>,
+    pub points: f32,
+    /// Shot is in required zone and counts
+    pub counted: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StringOutcome {
+    pub shots: Vec<DrillShot>,
+    /// Time of last shot in seconds, time limit if string timed out
+    pub time: f32,
+    pub misses: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DrillOutcome {
+    pub drill_id: String,
+    pub name: String,
+    pub scoring: Scoring,
+    pub started_at: DateTime<Local>,
+    pub strings: Vec<StringOutcome>,
+    pub points: f32,
+    pub penalties: f32,
+    pub time: f32,
+    /// Final result according to scoring
+    pub result: f32,
+}
+
+impl DrillDefinition {
+    /// Zone counts for this drill
+    pub fn counts_zone(&self, zone: Option<u8>) -> bool {
+        match zone {
+            Some(zone) => self.required_zones.is_empty() || self.required_zones.contains(&zone),
+            None => false,
+        }
+    }
+
+    /// Score finished strings
+    pub fn score(
+        &self,
+        drill_id: String,
+        started_at: DateTime<Local>,
+        strings: Vec<StringOutcome>,
+    ) -> DrillOutcome {
+        let shots = || strings.iter().flat_map(|s| s.shots.iter());
+        let points: f32 = shots().filter(|s| s.counted).map(|s| s.points).sum();
+        let missed: u32 = strings.iter().map(|s| s.misses).sum::<u32>()
+            + shots().filter(|s| !s.counted).count() as u32;
+        let penalties = missed as f32 * self.miss_penalty;
+        let time: f32 = strings.iter().map(|s| s.time).sum();
+
+        let result = match self.scoring {
+            Scoring::HitFactor if time > 0.0 => (points - penalties).max(0.0) / time,
+            Scoring::HitFactor => 0.0,
+            Scoring::PointsMinusPenalties => points - penalties,
+        };
+
+        DrillOutcome {
+            drill_id,
+            name: self.name.clone(),
+            scoring: self.scoring,
+            started_at,
+            strings,
+            points,
+            penalties,
+            time,
+            result,
+        }
+    }
+}
+
+fn load_drill(path: &Path) -> Result<DrillDefinition> {
+    let f = fs::File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(f))?)
+}
+
+/// Load drill definitions by id (file stem)
+pub fn load_drills() -> HashMap<String, DrillDefinition> {
+    match fs::read_dir(DRILLS_PATH) {
+        Err(e) => {
+            error!("failed to load drills: {e:?}");
+            HashMap::new()
+        }
+        Ok(i) => i
+            .filter_map(|entry| {
+                let path = entry
+                    .inspect_err(|e| error!("failed to explore drills dir: {e}"))
+                    .ok()?
+                    .path();
+                if path.extension().is_none_or(|ext| ext != "json") {
+                    return None;
+                }
+
+                let id = path.file_stem()?.to_string_lossy().to_string();
+                let drill = load_drill(&path)
+                    .inspect_err(|e| error!("failed to load drill '{id}' from {path:?}: {e:?}"))
+                    .ok()?;
+
+                Some((id, drill))
+            })
+            .collect(),
+    }
+}
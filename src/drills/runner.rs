@@ -0,0 +1,433 @@
+use std::{
+    collections::HashMap,
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use serde_json::json;
+use tracing::info;
+
+use crate::{
+    bus::{CommandReply, Event, send_reply},
+    drills::{DrillDefinition, DrillShot, StringOutcome},
+    hits::processor::HitProcessResult,
+    timer::{ShotTimer, ShotTimerConfig, ShotTiming, cue::play_start_signal},
+};
+
+/// Pause before start delay of every next string
+const STRING_PAUSE: Duration = Duration::from_secs(3);
+/// How long to wait for hit processing after last string
+const SCORING_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub enum DrillRunnerCommand {
+    Start {
+        id: String,
+        drill: DrillDefinition,
+        reply: Option<CommandReply>,
+    },
+    Abort {
+        reply: Option<CommandReply>,
+    },
+    Shot {
+        timestamp: DateTime<Local>,
+    },
+    Processed {
+        timestamp: DateTime<Local>,
+        result: HitProcessResult,
+    },
+}
+
+#[derive(Clone)]
+pub struct DrillProgress {
+    pub name: String,
+    /// 1-based current string
+    pub string: u32,
+    pub strings: u32,
+    pub shots: u32,
+    pub shots_per_string: u32,
+    pub status: String,
+    /// False once drill was aborted
+    pub running: bool,
+}
+
+enum Phase {
+    /// Waiting for start signal of next string
+    Standby { start_at: Instant },
+    Shooting {
+        timer: ShotTimer,
+        shots: Vec<(DateTime<Local>, ShotTiming)>,
+        deadline: Option<Instant>,
+    },
+    /// All strings shot, waiting for processing results
+    Scoring { deadline: Instant },
+}
+
+/// Shots of finished string
+struct ShotString {
+    shots: Vec<(DateTime<Local>, ShotTiming)>,
+    /// Time limit ran out before all shots were fired
+    timed_out: bool,
+}
+
+struct Run {
+    id: String,
+    drill: DrillDefinition,
+    started_at: DateTime<Local>,
+    strings: Vec<ShotString>,
+    results: HashMap<DateTime<Local>, HitProcessResult>,
+    phase: Phase,
+}
+
+impl Run {
+    fn new(id: String, drill: DrillDefinition) -> Self {
+        let mut run = Self {
+            id,
+            drill,
+            started_at: Local::now(),
+            strings: Vec::new(),
+            results: HashMap::new(),
+            phase: Phase::Scoring {
+                deadline: Instant::now(),
+            },
+        };
+        run.standby(Duration::ZERO);
+        run
+    }
+
+    fn standby(&mut self, pause: Duration) {
+        let config = ShotTimerConfig {
+            min_delay: Duration::from_secs_f32(self.drill.min_delay.max(0.0)),
+            max_delay: Duration::from_secs_f32(self.drill.max_delay.max(0.0)),
+            par: None,
+        };
+        self.phase = Phase::Standby {
+            start_at: Instant::now() + pause + config.random_delay(),
+        };
+    }
+
+    fn time_limit(&self) -> Option<Duration> {
+        self.drill
+            .time_limit
+            .map(|v| Duration::from_secs_f32(v.max(0.0)))
+    }
+
+    fn wake_at(&self) -> Option<Instant> {
+        match &self.phase {
+            Phase::Standby { start_at } => Some(*start_at),
+            Phase::Shooting { deadline, .. } => *deadline,
+            Phase::Scoring { deadline } => Some(*deadline),
+        }
+    }
+
+    fn progress(&self, status: impl Into<String>) -> DrillProgress {
+        let shots = match &self.phase {
+            Phase::Shooting { shots, .. } => shots.len() as u32,
+            _ => 0,
+        };
+        DrillProgress {
+            name: self.drill.name.clone(),
+            string: (self.strings.len() as u32 + 1).min(self.drill.strings),
+            strings: self.drill.strings,
+            shots,
+            shots_per_string: self.drill.shots,
+            status: status.into(),
+            running: true,
+        }
+    }
+
+    fn start_string(&mut self) {
+        let time_limit = self.time_limit();
+        let timer = ShotTimer::start(Local::now(), time_limit);
+        play_start_signal();
+        self.phase = Phase::Shooting {
+            timer,
+            shots: Vec::new(),
+            deadline: time_limit.map(|v| Instant::now() + v),
+        };
+    }
+
+    fn finish_string(&mut self, timed_out: bool) {
+        let phase = std::mem::replace(
+            &mut self.phase,
+            Phase::Scoring {
+                deadline: Instant::now() + SCORING_TIMEOUT,
+            },
+        );
+        if let Phase::Shooting { shots, .. } = phase {
+            self.strings.push(ShotString { shots, timed_out });
+        }
+        if (self.strings.len() as u32) < self.drill.strings {
+            self.standby(STRING_PAUSE);
+        }
+    }
+
+    fn all_processed(&self) -> bool {
+        self.strings
+            .iter()
+            .flat_map(|s| &s.shots)
+            .all(|(timestamp, _)| self.results.contains_key(timestamp))
+    }
+
+    /// All strings shot and processed, or processing took too long
+    fn finished(&self, now: Instant) -> bool {
+        match &self.phase {
+            Phase::Scoring { deadline } => self.all_processed() || *deadline <= now,
+            _ => false,
+        }
+    }
+
+    fn outcome(self) -> crate::drills::DrillOutcome {
+        let time_limit = self.drill.time_limit.unwrap_or(0.0);
+        let strings = self
+            .strings
+            .into_iter()
+            .map(|ShotString { shots, timed_out }| {
+                let time = match shots.last() {
+                    Some((_, timing)) if !timed_out => timing.since_start,
+                    _ => time_limit,
+                };
+                let misses = self.drill.shots.saturating_sub(shots.len() as u32);
+                let shots = shots
+                    .into_iter()
+                    .map(|(timestamp, timing)| {
+                        let result = self.results.get(&timestamp);
+                        let zone = result.and_then(|r| r.zone);
+                        DrillShot {
+                            timestamp,
+                            timing,
+                            zone,
+                            points: result.map(|r| r.score).unwrap_or(0.0),
+                            counted: self.drill.counts_zone(zone),
+                        }
+                    })
+                    .collect();
+                StringOutcome {
+                    shots,
+                    time,
+                    misses,
+                }
+            })
+            .collect();
+        self.drill.score(self.id, self.started_at, strings)
+    }
+}
+
+pub fn start_drill_runner(bus_tx: Sender<Event>) -> Sender<DrillRunnerCommand> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut run: Option<Run> = None;
+        let send_progress = |run: &Run, status: &str| {
+            bus_tx
+                .send(Event::DrillProgress(run.progress(status)))
+                .expect("failed to send drill progress");
+        };
+
+        loop {
+            let wake_at = run.as_ref().and_then(Run::wake_at);
+            let msg = match wake_at {
+                Some(wake_at) => {
+                    match rx.recv_timeout(wake_at.saturating_duration_since(Instant::now())) {
+                        Ok(v) => Some(v),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match rx.recv() {
+                    Ok(v) => Some(v),
+                    Err(_) => break,
+                },
+            };
+
+            match msg {
+                Some(DrillRunnerCommand::Start { id, drill, reply }) => {
+                    info!("Starting drill '{id}'");
+                    send_reply(reply, Ok(json!({ "drill": id })));
+                    let new_run = Run::new(id, drill);
+                    send_progress(&new_run, "Standby");
+                    run = Some(new_run);
+                    continue;
+                }
+                Some(DrillRunnerCommand::Abort { reply }) => {
+                    let Some(run) = run.take() else {
+                        send_reply(reply, Err("no drill is running".to_string()));
+                        continue;
+                    };
+                    info!("Aborted drill '{}'", run.id);
+                    send_reply(reply, Ok(json!({ "drill": run.id })));
+                    bus_tx
+                        .send(Event::DrillProgress(DrillProgress {
+                            running: false,
+                            ..run.progress("Aborted")
+                        }))
+                        .expect("failed to send drill progress");
+                    continue;
+                }
+                _ => {}
+            }
+
+            let Some(current) = &mut run else {
+                continue;
+            };
+
+            match (msg, &mut current.phase) {
+                (None, Phase::Standby { .. }) => {
+                    current.start_string();
+                    send_progress(current, "GO");
+                }
+                (None, Phase::Shooting { .. }) => {
+                    current.finish_string(true);
+                    send_progress(current, "Time");
+                }
+                (None, Phase::Scoring { .. }) => {}
+                (
+                    Some(DrillRunnerCommand::Shot { timestamp }),
+                    Phase::Shooting { timer, shots, .. },
+                ) => {
+                    let Some(timing) = timer.record(timestamp) else {
+                        continue;
+                    };
+                    shots.push((timestamp, timing));
+                    if shots.len() as u32 >= current.drill.shots {
+                        current.finish_string(false);
+                        send_progress(current, "String done");
+                    } else {
+                        send_progress(current, "GO");
+                    }
+                }
+                (Some(DrillRunnerCommand::Processed { timestamp, result }), _) => {
+                    current.results.insert(timestamp, result);
+                }
+                _ => {}
+            }
+
+            if current.finished(Instant::now())
+                && let Some(run) = run.take()
+            {
+                let outcome = run.outcome();
+                info!("Drill '{}' finished: {}", outcome.drill_id, outcome.result);
+                bus_tx
+                    .send(Event::DrillFinished { outcome })
+                    .expect("failed to send drill outcome");
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+    use serde_json::json;
+
+    use super::*;
+    use crate::drills::Scoring;
+
+    fn drill(scoring: Scoring) -> DrillDefinition {
+        serde_json::from_value(json!({
+            "name": "Test",
+            "shots": 2,
+            "time_limit": 5.0,
+            "required_zones": [1],
+            "scoring": scoring,
+            "miss_penalty": 10.0,
+        }))
+        .unwrap()
+    }
+
+    fn result(score: f32, zone: u8) -> HitProcessResult {
+        HitProcessResult {
+            score,
+            hit_pos: None,
+            hit_pos_norm: None,
+            zone: Some(zone),
+            corrected: false,
+            processor_version: 0,
+        }
+    }
+
+    /// Run with one string of shots fired at seconds after start signal, shot in scoring
+    fn shot_run(
+        scoring: Scoring,
+        shots_at: &[f32],
+        timed_out: bool,
+    ) -> (Run, Vec<DateTime<Local>>) {
+        let mut run = Run::new("test".to_string(), drill(scoring));
+        let start = Local::now();
+        let mut timer = ShotTimer::start(start, run.time_limit());
+        let shots: Vec<_> = shots_at
+            .iter()
+            .map(|secs| {
+                let at = start + TimeDelta::microseconds((secs * 1e6) as i64);
+                (at, timer.record(at).unwrap())
+            })
+            .collect();
+        let timestamps = shots.iter().map(|(at, _)| *at).collect();
+        run.phase = Phase::Shooting {
+            timer,
+            shots,
+            deadline: None,
+        };
+        run.finish_string(timed_out);
+        (run, timestamps)
+    }
+
+    #[test]
+    fn hit_factor_divides_points_by_time() {
+        let (mut run, shots) = shot_run(Scoring::HitFactor, &[1.0, 2.0], false);
+        run.results.insert(shots[0], result(5.0, 1));
+        run.results.insert(shots[1], result(4.0, 1));
+
+        let outcome = run.outcome();
+        assert_eq!(outcome.points, 9.0);
+        assert_eq!(outcome.penalties, 0.0);
+        assert_eq!(outcome.time, 2.0);
+        assert_eq!(outcome.result, 4.5);
+    }
+
+    #[test]
+    fn points_minus_penalties_counts_required_zones_only() {
+        let (mut run, shots) = shot_run(Scoring::PointsMinusPenalties, &[1.0, 2.0], false);
+        run.results.insert(shots[0], result(5.0, 1));
+        run.results.insert(shots[1], result(3.0, 2));
+
+        let outcome = run.outcome();
+        assert_eq!(outcome.points, 5.0);
+        assert_eq!(outcome.penalties, 10.0);
+        assert_eq!(outcome.result, -5.0);
+    }
+
+    #[test]
+    fn timed_out_string_takes_time_limit() {
+        let (mut run, shots) = shot_run(Scoring::HitFactor, &[1.0], true);
+        run.results.insert(shots[0], result(30.0, 1));
+
+        let outcome = run.outcome();
+        assert_eq!(outcome.strings[0].misses, 1);
+        assert_eq!(outcome.time, 5.0);
+        assert_eq!(outcome.result, (30.0 - 10.0) / 5.0);
+    }
+
+    #[test]
+    fn scoring_gives_up_on_unprocessed_shots_after_timeout() {
+        let (mut run, shots) = shot_run(Scoring::PointsMinusPenalties, &[1.0, 2.0], false);
+        run.results.insert(shots[0], result(5.0, 1));
+        let now = Instant::now();
+        assert!(!run.finished(now));
+        assert!(run.finished(now + SCORING_TIMEOUT));
+
+        let outcome = run.outcome();
+        assert!(!outcome.strings[0].shots[1].counted);
+        assert_eq!(outcome.points, 5.0);
+        assert_eq!(outcome.penalties, 10.0);
+    }
+
+    #[test]
+    fn scoring_ends_once_every_shot_is_processed() {
+        let (mut run, shots) = shot_run(Scoring::HitFactor, &[1.0, 2.0], false);
+        for shot in shots {
+            run.results.insert(shot, result(5.0, 1));
+        }
+        assert!(run.finished(Instant::now()));
+    }
+}
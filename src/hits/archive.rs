@@ -0,0 +1,369 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{BufReader, ErrorKind, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local, NaiveDateTime, TimeDelta, TimeZone};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::{
+    hits::storage::{
+        HitData, HitQuery, HitStorage,
+        file::{DATA_FILE, TIMESTAMP_DIR_FORMAT},
+        schema,
+    },
+    sessions::Session,
+    targets::settings::{TARGETS_PATH, Target},
+};
+
+/// Bumped when archive layout changes
+const ARCHIVE_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const HITS_DIR: &str = "hits";
+const SESSIONS_DIR: &str = "sessions";
+const TARGETS_DIR: &str = "targets";
+/// Every import unpacks its archive into its own directory below this before merging
+const IMPORT_PATH: &str = "data/import";
+
+/// What archive holds, first entry of every archive
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    exported_at: DateTime<Local>,
+    hits: usize,
+    sessions: Vec<String>,
+    targets: Vec<String>,
+}
+
+/// Hits added by import, under the timestamps they were stored with
+pub struct ImportSummary {
+    pub hits: Vec<(DateTime<Local>, HitData)>,
+    /// Hits already stored before import
+    pub skipped: usize,
+    pub sessions: usize,
+    pub targets: usize,
+}
+
+impl std::fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} hits, {} sessions and {} targets, skipped {} hits already stored",
+            self.hits.len(),
+            self.sessions,
+            self.targets,
+            self.skipped
+        )
+    }
+}
+
+/// Write hits matching any query to tar archive with their files, sessions and targets
+pub fn export_archive(
+    storage: &mut dyn HitStorage,
+    targets: &HashMap<String, Target>,
+    queries: &[HitQuery],
+    w: impl Write,
+) -> Result<usize> {
+    let mut hits = HashMap::new();
+    for query in queries {
+        hits.extend(storage.query_hits(query)?);
+    }
+    let mut hits: Vec<_> = hits.into_iter().collect();
+    hits.sort_by_key(|(timestamp, _)| *timestamp);
+
+    let session_ids: BTreeSet<_> = hits
+        .iter()
+        .filter_map(|(_, hit)| hit.session.clone())
+        .collect();
+    let target_ids: BTreeSet<_> = hits
+        .iter()
+        .filter_map(|(_, hit)| hit.target_id.clone())
+        .collect();
+
+    let mut builder = tar::Builder::new(w);
+    let manifest = Manifest {
+        version: ARCHIVE_VERSION,
+        exported_at: Local::now(),
+        hits: hits.len(),
+        sessions: session_ids.iter().cloned().collect(),
+        targets: target_ids.iter().cloned().collect(),
+    };
+    append_json(
+        &mut builder,
+        MANIFEST_FILE,
+        &serde_json::to_value(&manifest)?,
+    )?;
+
+    for id in &session_ids {
+        match storage.load_session(id) {
+            Ok(Some(session)) => append_json(
+                &mut builder,
+                &format!("{SESSIONS_DIR}/{id}.json"),
+                &schema::to_value(&session)?,
+            )?,
+            Ok(None) => error!("session {id} not found, it is not archived"),
+            Err(e) => error!("failed to archive session {id}: {e:?}"),
+        }
+    }
+
+    for id in &target_ids {
+        let Some(target) = targets.get(id) else {
+            error!("unknown target '{id}' is not archived");
+            continue;
+        };
+        builder.append_dir_all(format!("{TARGETS_DIR}/{id}"), target.dir())?;
+    }
+
+    for (timestamp, hit) in &hits {
+        let name = format!("{HITS_DIR}/{}", timestamp.format(TIMESTAMP_DIR_FORMAT));
+        append_json(
+            &mut builder,
+            &format!("{name}/{DATA_FILE}"),
+            &schema::to_value(hit)?,
+        )?;
+
+        // Clip, thumbnails and whatever else the hit directory holds
+        let dir = storage.hit_dir(*timestamp);
+        if !dir.exists() {
+            continue;
+        }
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() || entry.file_name() == DATA_FILE {
+                continue;
+            }
+            builder.append_path_with_name(
+                entry.path(),
+                format!("{name}/{}", entry.file_name().to_string_lossy()),
+            )?;
+        }
+    }
+
+    builder.into_inner()?.flush()?;
+    Ok(hits.len())
+}
+
+fn append_json(
+    builder: &mut tar::Builder<impl Write>,
+    path: &str,
+    value: &serde_json::Value,
+) -> Result<()> {
+    let data = serde_json::to_vec_pretty(value)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Local::now().timestamp() as u64);
+    builder.append_data(&mut header, path, data.as_slice())?;
+    Ok(())
+}
+
+/// Merge archive into storage, moving hits to later free timestamps where taken
+pub fn import_archive(storage: &mut dyn HitStorage, archive: &Path) -> Result<ImportSummary> {
+    let f = fs::File::open(archive).with_context(|| format!("failed to open {archive:?}"))?;
+    let dir = staging_dir()?;
+    let result = tar::Archive::new(BufReader::new(f))
+        .unpack(&dir)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| merge(storage, &dir));
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        error!("failed to clean up {dir:?}: {e:?}");
+    }
+    result
+}
+
+/// Create directory no other import uses
+fn staging_dir() -> Result<PathBuf> {
+    let base = Path::new(IMPORT_PATH);
+    fs::create_dir_all(base)?;
+    for n in 0.. {
+        let dir = base.join(format!("{}-{n}", std::process::id()));
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            // Left by earlier import or one still running
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    unreachable!()
+}
+
+fn merge(storage: &mut dyn HitStorage, dir: &Path) -> Result<ImportSummary> {
+    let f = fs::File::open(dir.join(MANIFEST_FILE)).context("archive has no manifest")?;
+    let manifest: Manifest = serde_json::from_reader(BufReader::new(f))?;
+    if manifest.version > ARCHIVE_VERSION {
+        bail!(
+            "archive has version {}, newer than supported {ARCHIVE_VERSION}",
+            manifest.version
+        );
+    }
+
+    // Session ids are start times, a session on another machine may have started at the same one
+    let mut session_ids = HashMap::new();
+    let mut sessions = 0;
+    for id in &manifest.sessions {
+        if !is_file_name(id) {
+            error!("skipping session {id:?} in archive, its id is no file name");
+            continue;
+        }
+        let path = dir.join(SESSIONS_DIR).join(format!("{id}.json"));
+        let mut session: Session = match read_record(&path) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed to import session {id}: {e:?}");
+                continue;
+            }
+        };
+        if !is_file_name(&session.id) {
+            error!(
+                "skipping session {:?} in archive, its id is no file name",
+                session.id
+            );
+            continue;
+        }
+        let mut n = 1;
+        loop {
+            let stored = storage
+                .load_session(&session.id)
+                .with_context(|| format!("failed to check stored session {}", session.id))?;
+            match stored {
+                Some(existing)
+                    if existing.started_at == session.started_at
+                        && existing.shooter == session.shooter =>
+                {
+                    break;
+                }
+                Some(_) => {
+                    session.id = format!("{id}-{n}");
+                    n += 1;
+                }
+                None => {
+                    storage.save_session(&session)?;
+                    sessions += 1;
+                    break;
+                }
+            }
+        }
+        if session.id != *id {
+            info!("Imported session {id} as {}", session.id);
+        }
+        session_ids.insert(id.clone(), session.id);
+    }
+
+    // Local target definitions win over archived ones
+    let mut targets = 0;
+    for id in &manifest.targets {
+        if !is_file_name(id) {
+            error!("skipping target {id:?} in archive, its id is no file name");
+            continue;
+        }
+        let from = dir.join(TARGETS_DIR).join(id);
+        let to = Path::new(TARGETS_PATH).join(id);
+        if to.exists() || !from.exists() {
+            continue;
+        }
+        copy_dir(&from, &to)?;
+        info!("Imported target '{id}', it is available after restart");
+        targets += 1;
+    }
+
+    let mut entries: Vec<_> = match fs::read_dir(dir.join(HITS_DIR)) {
+        Ok(entries) => entries.collect::<Result<_, _>>()?,
+        Err(_) => Vec::new(),
+    };
+    entries.sort_by_key(|e| e.file_name());
+
+    let mut hits = Vec::new();
+    let mut skipped = 0;
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(timestamp) = NaiveDateTime::parse_from_str(&name, TIMESTAMP_DIR_FORMAT)
+            .ok()
+            .and_then(|naive| Local.from_local_datetime(&naive).single())
+        else {
+            error!("skipping {name:?} in archive, it is no hit");
+            continue;
+        };
+        let mut hit: HitData = match read_record(&entry.path().join(DATA_FILE)) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed to import hit {name}: {e:?}");
+                continue;
+            }
+        };
+        if let Some(id) = hit.session.as_ref().and_then(|id| session_ids.get(id)) {
+            hit.session = Some(id.clone());
+        }
+
+        let Some(timestamp) = free_timestamp(storage, timestamp, &hit)? else {
+            skipped += 1;
+            continue;
+        };
+        let to = storage.hit_dir(timestamp);
+        fs::create_dir_all(&to)?;
+        for file in fs::read_dir(entry.path())? {
+            let file = file?;
+            if file.file_type()?.is_file() && file.file_name() != DATA_FILE {
+                fs::copy(file.path(), to.join(file.file_name()))?;
+            }
+        }
+        storage.save_data(timestamp, hit.clone())?;
+        hits.push((timestamp, hit));
+    }
+
+    Ok(ImportSummary {
+        hits,
+        skipped,
+        sessions,
+        targets,
+    })
+}
+
+/// Ids from archive become file and directory names, they must not lead elsewhere
+fn is_file_name(id: &str) -> bool {
+    let mut components = Path::new(id).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    )
+}
+
+fn read_record<T: schema::Record>(path: &Path) -> Result<T> {
+    Ok(schema::from_str(&fs::read_to_string(path)?)?.record)
+}
+
+/// First timestamp from `timestamp` on that no other hit has, none if the same hit is stored already
+fn free_timestamp(
+    storage: &mut dyn HitStorage,
+    mut timestamp: DateTime<Local>,
+    hit: &HitData,
+) -> Result<Option<DateTime<Local>>> {
+    let value = schema::to_value(hit)?;
+    loop {
+        match storage.load_data(timestamp) {
+            Ok(existing) if schema::to_value(&existing)? == value => return Ok(None),
+            Ok(_) => {}
+            // Directory without data may hold a clip still
+            Err(_) if !storage.hit_dir(timestamp).exists() => return Ok(Some(timestamp)),
+            Err(_) => {}
+        }
+        timestamp += TimeDelta::milliseconds(1);
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), dest)?;
+        }
+    }
+    Ok(())
+}
@@ -6,67 +6,105 @@ use std::{
     },
 };
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, TimeDelta};
 use imageproc::point::Point;
 use tracing::info;
 
 use crate::{
-    bus::Event, capturer::CapturedFrame, coding::ffmpeg::save_video, hits::LaserInfo,
-    recorder::Recorder, targets::TargetInfo, util::point::MyPoint, vision::laser::find_red_laser,
+    bus::Event, capturer::CapturedFrame, coding::Clip, hits::LaserInfo, recorder::Recorder,
+    targets::TargetInfo, util::point::MyPoint, vision::laser::find_red_laser,
 };
 
 pub enum HitDetectorCommand {
     NewFrame(Arc<CapturedFrame>),
 }
 
+/// Shot whose laser is gone, sent once its post-roll frames are recorded
+struct PendingHit {
+    shot_at: DateTime<Local>,
+    shot_pos: Point<f32>,
+    target_info: Option<TargetInfo>,
+    clip_end: DateTime<Local>,
+}
+
 pub fn start_hit_detector(
     bus: Sender<Event>,
+    lane: String,
     laser_info: Arc<RwLock<Option<LaserInfo>>>,
     target_info: Arc<RwLock<Option<TargetInfo>>>,
     recorder: Arc<Recorder>,
 ) -> Sender<HitDetectorCommand> {
     let (tx, rx) = mpsc::channel();
     std::thread::spawn(move || {
-        let mut clip: Vec<Arc<CapturedFrame>> = Vec::with_capacity(60);
         let mut recording = false;
         let mut recording_target_info = None;
-        let mut last_laser_at = Local::now();
+        let mut shot_at = Local::now();
+        let mut shot_pos = Point::new(0.0, 0.0);
+        let mut pending: Option<PendingHit> = None;
+        let mut last_clip_end: Option<DateTime<Local>> = None;
+
+        // Clip runs from pre-roll before shot up to `to`, without frames of previous clip
+        let send_hit = |hit: PendingHit, to: DateTime<Local>, last_clip_end: &mut Option<_>| {
+            let mut clip = recorder.snapshot(hit.shot_at - recorder.config().pre_roll(), to);
+            if let Some(end) = *last_clip_end {
+                clip.retain(|f| f.timestamp > end);
+            }
+            *last_clip_end = clip.last().map(|f| f.timestamp).or(*last_clip_end);
+            bus.send(Event::NewHit {
+                lane: lane.clone(),
+                timestamp: hit.shot_at,
+                laser_pos: hit.shot_pos,
+                clip: Clip::from_captured(&clip),
+                target_info: hit.target_info.unwrap_or(TargetInfo {
+                    rect: [
+                        Point::new(0.0, 0.0).into(),
+                        Point::new(0.0, 0.0).into(),
+                        Point::new(0.0, 0.0).into(),
+                        Point::new(0.0, 0.0).into(),
+                    ],
+                }),
+            })
+            .expect("Failed to send hit event");
+        };
+
         for msg in rx {
             match msg {
                 HitDetectorCommand::NewFrame(frame) => {
-                    if let Some(laser_flash) = find_red_laser(&frame.image) {
+                    let laser_flash = find_red_laser(&frame.image);
+
+                    // Post-roll ends early when next shot comes, its frames belong to next clip
+                    if let Some(hit) = pending
+                        .take_if(|hit| laser_flash.is_some() || frame.timestamp >= hit.clip_end)
+                    {
+                        let to = if laser_flash.is_some() {
+                            frame.timestamp - TimeDelta::microseconds(1)
+                        } else {
+                            frame.timestamp
+                        };
+                        send_hit(hit, to, &mut last_clip_end);
+                    }
+
+                    if let Some(laser_flash) = laser_flash {
                         info!("Laser: {:?}", laser_flash);
                         if !recording {
                             *laser_info.write().unwrap() = Some(LaserInfo { pos: laser_flash });
-                            clip = recorder.frames();
-                            clip.retain(|f| f.timestamp > last_laser_at);
                             recording = true;
                             recording_target_info = target_info.read().unwrap().clone();
-                        } else {
-                            clip.push(frame);
+                            shot_at = frame.timestamp;
+                            shot_pos = Point::new(
+                                laser_flash.x / frame.image.width() as f32,
+                                laser_flash.y / frame.image.height() as f32,
+                            );
                         }
                     } else if recording {
-                        // let clip_path = "data/hello.mp4";
-                        let v: Vec<_> = clip.iter().map(|c| c.image.clone()).collect();
-                        // save_video(&v, 20, Path::new("data/hello.mp4"))
-                        //     .expect("failed to save clip");
-                        bus.send(Event::NewHit {
-                            timestamp: frame.timestamp.into(),
-                            clip: (v, 20),
-                            target_info: recording_target_info.take().unwrap_or(TargetInfo {
-                                rect: [
-                                    Point::new(0.0, 0.0).into(),
-                                    Point::new(0.0, 0.0).into(),
-                                    Point::new(0.0, 0.0).into(),
-                                    Point::new(0.0, 0.0).into(),
-                                ],
-                            }),
-                        })
-                        .expect("Failed to send hit event");
-                        // info!("Saved clip in {clip_path}");
-                        clip.clear();
+                        // Shot is over once laser is gone, post-roll only extends its clip
                         recording = false;
-                        last_laser_at = Local::now();
+                        pending = Some(PendingHit {
+                            shot_at,
+                            shot_pos,
+                            target_info: recording_target_info.take(),
+                            clip_end: frame.timestamp + recorder.config().post_roll(),
+                        });
                     }
                 }
             }
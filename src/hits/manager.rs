@@ -1,28 +1,50 @@
 use core::time;
-use std::sync::{
-    Arc,
-    mpsc::{self, Sender},
+use std::{
+    collections::HashMap,
+    fs,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        mpsc::{self, Sender},
+    },
 };
 
+use anyhow::Result;
 use chrono::{DateTime, Local};
-use image::RgbImage;
+use imageproc::point::Point;
+use serde_json::json;
 use tracing::{error, info};
 
 use crate::{
-    bus::Event,
+    bus::{CommandReply, Event, send_reply},
+    coding::Clip,
+    drills::DrillOutcome,
     hits::{
-        processor::HitProcessResult,
-        storage::{HitData, HitStorage},
+        archive::{export_archive, import_archive},
+        processor::{HitProcessResult, PROCESSOR_VERSION, score_hit},
+        storage::{HitData, HitQuery, HitStorage, SharedStorage},
+    },
+    report::{
+        EXPORTS_PATH,
+        export::{ExportFormat, collect_rows, write_rows},
+        heatmap::{HeatmapRequest, render_heatmap},
+        sheet::{ReportFormat, build_report, write_report},
     },
-    targets::TargetInfo,
+    sessions::Session,
+    targets::{TargetInfo, settings::Target},
+    timer::ShotTiming,
+    util::point::MyPoint,
 };
 use std::collections::VecDeque;
 
 pub enum HitManagerCommand {
     NewHit {
         timestamp: DateTime<Local>,
-        clip: (Vec<RgbImage>, u32),
+        clip: Clip,
         target_info: TargetInfo,
+        target_id: Option<String>,
+        lane: String,
     },
     HitProcessorReady,
     ProcessedHit {
@@ -32,16 +54,97 @@ pub enum HitManagerCommand {
     RequestHitClip {
         timestamp: DateTime<Local>,
     },
+    RequestThumbnail {
+        timestamp: DateTime<Local>,
+    },
+    RenderHeatmap {
+        request: HeatmapRequest,
+        preview_path: PathBuf,
+        export: bool,
+    },
+    ShotTimed {
+        timestamp: DateTime<Local>,
+        timing: ShotTiming,
+    },
+    StartSession {
+        shooter: String,
+        reply: Option<CommandReply>,
+    },
+    EndSession {
+        reply: Option<CommandReply>,
+    },
+    DrillFinished {
+        outcome: DrillOutcome,
+    },
+    DeleteHit {
+        timestamp: DateTime<Local>,
+    },
+    /// Restore most recently deleted hit
+    UndoDelete,
+    /// Remove deleted hits for good, also those deleted in earlier runs
+    PurgeDeleted,
+    AnnotateHit {
+        timestamp: DateTime<Local>,
+        notes: String,
+        tags: Vec<String>,
+        flyer: bool,
+    },
+    /// Set hit position by hand, relative to clip frame size (0..1)
+    CorrectHit {
+        timestamp: DateTime<Local>,
+        pos: Point<f32>,
+    },
+    Reprocess {
+        scope: ReprocessScope,
+    },
+    /// Export hits matching any query to exports directory
+    ExportHits {
+        queries: Vec<HitQuery>,
+        format: ExportFormat,
+    },
+    /// Write printable report of session to exports directory
+    ExportReport {
+        session: String,
+        format: ReportFormat,
+    },
+    /// Write hits matching any query with their files to archive in exports directory
+    ExportArchive {
+        queries: Vec<HitQuery>,
+    },
+    ImportArchive {
+        path: PathBuf,
+    },
+}
+
+/// Hits to run through hit processor again
+#[derive(Clone, Debug)]
+pub enum ReprocessScope {
+    Hit(DateTime<Local>),
+    Session(String),
+    /// Hits processed by older processor version
+    Outdated,
+    All,
 }
 
 pub fn start_hit_manager(
     bus_tx: Sender<Event>,
-    mut storage: Box<dyn HitStorage>,
+    shared_storage: SharedStorage,
+    targets: Arc<HashMap<String, Target>>,
 ) -> Sender<HitManagerCommand> {
     let (tx, rx) = mpsc::channel();
 
     std::thread::spawn(move || {
+        let mut guard = shared_storage.lock().unwrap();
+        let storage = guard.as_mut();
         let mut processor_ready = false;
+        let mut session: Option<Session> = None;
+        // Deletes can be undone until restart, newest last
+        let mut deleted: Vec<DateTime<Local>> = Vec::new();
+        match storage.upgrade_records() {
+            Ok(0) => {}
+            Ok(count) => info!("Upgraded {count} records to current schema"),
+            Err(e) => error!("failed to upgrade records: {e:?}"),
+        }
         let unprocessed: VecDeque<(DateTime<Local>, HitData)> = {
             let hits = storage
                 .get_all_hits()
@@ -49,6 +152,16 @@ pub fn start_hit_manager(
             bus_tx
                 .send(Event::LoadedHits { hits: hits.clone() })
                 .expect("failed to send loaded hits event");
+            // Sessions are only loaded to find broken records
+            if let Err(e) = storage.get_all_sessions() {
+                error!("failed to load sessions: {e:?}");
+            }
+            let errors = storage.load_errors();
+            if !errors.is_empty() {
+                bus_tx
+                    .send(Event::LoadErrors { errors })
+                    .expect("failed to send load errors");
+            }
             let mut v: Vec<_> = hits
                 .into_iter()
                 .filter_map(|(timestamp, hit)| {
@@ -62,22 +175,32 @@ pub fn start_hit_manager(
             v.sort_by_key(|v| v.0);
             v.into()
         };
-        // let unprocessed = storage
-        //     .get_unprocessed_hits_old_sorted()
-        //     .expect("failed to get unprocessed hits");
         let mut unprocessed_hits: VecDeque<_> = VecDeque::from(unprocessed);
+        drop(guard);
         for msg in rx {
+            // Api server reads storage between commands
+            let mut guard = shared_storage.lock().unwrap();
+            let storage = guard.as_mut();
             match msg {
                 HitManagerCommand::NewHit {
                     timestamp,
                     clip,
                     target_info,
+                    target_id,
+                    lane,
                 } => {
                     let data = HitData {
                         target_info: target_info.clone(),
+                        target_id,
+                        lane: Some(lane),
+                        session: session.as_ref().map(|s| s.id.clone()),
                         processed: None,
+                        shot_timing: None,
+                        notes: String::new(),
+                        tags: Vec::new(),
+                        flyer: false,
                     };
-                    if let Err(e) = storage.new_hit(timestamp, (&clip.0, clip.1), data.clone()) {
+                    if let Err(e) = storage.new_hit(timestamp, &clip, data.clone()) {
                         error!("failed to create clip in storage: {e:?}");
                         continue;
                     };
@@ -88,41 +211,152 @@ pub fn start_hit_manager(
                                 timestamp,
                                 clip,
                                 target_info,
+                                target_id: data.target_id,
                             })
                             .expect("failed to request hit process");
+                        processor_ready = false;
                     } else {
                         unprocessed_hits.push_back((timestamp, data));
                     }
                 }
                 HitManagerCommand::HitProcessorReady => {
-                    processor_ready = true;
-                    while processor_ready {
-                        let (timestamp, data) = match unprocessed_hits.pop_front() {
-                            Some(t) => t,
-                            None => break,
-                        };
-                        let clip = match storage.load_clip(timestamp) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                error!("failed to load unprocessed hit clip: {e:?}");
-                                continue;
-                            }
-                        };
-
+                    processor_ready = !process_next(&mut *storage, &bus_tx, &mut unprocessed_hits);
+                    if processor_ready {
                         bus_tx
-                            .send(Event::ProcessHit {
-                                timestamp,
-                                clip,
-                                target_info: data.target_info,
-                            })
-                            .expect("failed to request hit process");
-                        processor_ready = false;
+                            .send(Event::ProcessingIdle)
+                            .expect("failed to send processing idle");
+                    }
+                }
+                HitManagerCommand::Reprocess { scope } => {
+                    let hits = match reprocess_hits(&mut *storage, &scope) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("failed to find hits to reprocess: {e:?}");
+                            continue;
+                        }
+                    };
+                    info!("Reprocessing {} hits", hits.len());
+                    for hit in hits {
+                        if !unprocessed_hits.iter().any(|(t, _)| *t == hit.0) {
+                            unprocessed_hits.push_back(hit);
+                        }
+                    }
+                    if processor_ready {
+                        processor_ready =
+                            !process_next(&mut *storage, &bus_tx, &mut unprocessed_hits);
                     }
                 }
                 HitManagerCommand::ProcessedHit {
                     timestamp,
                     processed,
                 } => {
+                    let data = match save_processed(&mut *storage, &targets, timestamp, processed) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("failed to save hit {timestamp} process result: {e:?}");
+                            continue;
+                        }
+                    };
+                    bus_tx
+                        .send(Event::HitChanged { timestamp, data })
+                        .expect("failed to send hit change");
+                }
+                HitManagerCommand::DeleteHit { timestamp } => {
+                    if let Err(e) = storage.delete_hit(timestamp) {
+                        error!("failed to delete hit {timestamp}: {e:?}");
+                        continue;
+                    }
+                    info!("Deleted hit {timestamp}");
+                    deleted.push(timestamp);
+                    bus_tx
+                        .send(Event::HitDeleted { timestamp })
+                        .expect("failed to send hit deletion");
+                }
+                HitManagerCommand::UndoDelete => {
+                    let Some(&timestamp) = deleted.last() else {
+                        info!("No deleted hit to restore");
+                        continue;
+                    };
+                    // Stays restorable if it failed
+                    let data = match storage.restore_hit(timestamp) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("failed to restore hit {timestamp}: {e:?}");
+                            continue;
+                        }
+                    };
+                    deleted.pop();
+                    info!("Restored hit {timestamp}");
+                    bus_tx
+                        .send(Event::HitChanged { timestamp, data })
+                        .expect("failed to send hit change");
+                }
+                HitManagerCommand::PurgeDeleted => {
+                    if let Err(e) = storage.purge_deleted_hits() {
+                        error!("failed to purge deleted hits: {e:?}");
+                        continue;
+                    }
+                    info!("Purged deleted hits");
+                    deleted.clear();
+                }
+                HitManagerCommand::AnnotateHit {
+                    timestamp,
+                    notes,
+                    tags,
+                    flyer,
+                } => {
+                    let mut data = match storage.load_data(timestamp) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("failed to load hit {timestamp} from storage: {e:?}");
+                            continue;
+                        }
+                    };
+                    data.notes = notes;
+                    data.tags = tags;
+                    data.flyer = flyer;
+                    if let Err(e) = storage.save_data(timestamp, data.clone()) {
+                        error!("failed to save hit {timestamp} notes: {e:?}");
+                        continue;
+                    }
+                    bus_tx
+                        .send(Event::HitChanged { timestamp, data })
+                        .expect("failed to send hit change");
+                }
+                HitManagerCommand::CorrectHit { timestamp, pos } => {
+                    let mut data = match storage.load_data(timestamp) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("failed to load hit {timestamp} from storage: {e:?}");
+                            continue;
+                        }
+                    };
+                    let (zone, score) = score_hit(&targets, data.target_id.as_deref(), pos);
+                    // Pixel position only known if detection found frame size
+                    let hit_pos = data.processed.as_ref().and_then(|p| {
+                        let (px, norm) = (p.hit_pos.as_ref()?, p.hit_pos_norm.as_ref()?);
+                        (norm.x > 0.0 && norm.y > 0.0).then(|| {
+                            MyPoint::from(Point::new(pos.x * px.x / norm.x, pos.y * px.y / norm.y))
+                        })
+                    });
+                    info!("Corrected hit {timestamp} to {pos:?}, score {score}");
+                    data.processed = Some(HitProcessResult {
+                        score,
+                        hit_pos,
+                        hit_pos_norm: Some(MyPoint::from(pos)),
+                        zone,
+                        corrected: true,
+                        processor_version: PROCESSOR_VERSION,
+                    });
+                    if let Err(e) = storage.save_data(timestamp, data.clone()) {
+                        error!("failed to save hit {timestamp} correction: {e:?}");
+                        continue;
+                    }
+                    bus_tx
+                        .send(Event::HitChanged { timestamp, data })
+                        .expect("failed to send hit change");
+                }
+                HitManagerCommand::ShotTimed { timestamp, timing } => {
                     let mut data = match storage.load_data(timestamp) {
                         Ok(v) => v,
                         Err(e) => {
@@ -130,27 +364,321 @@ pub fn start_hit_manager(
                             continue;
                         }
                     };
-                    data.processed = Some(processed);
-                    storage
-                        .save_data(timestamp, data)
-                        .expect("failed to save hit {timestamp} process result");
+                    data.shot_timing = Some(timing);
+                    if let Err(e) = storage.save_data(timestamp, data) {
+                        error!("failed to save hit {timestamp} shot timing: {e:?}");
+                    }
+                }
+                HitManagerCommand::StartSession { shooter, reply } => {
+                    if let Some(mut old) = session.take() {
+                        old.ended_at = Some(Local::now());
+                        if let Err(e) = storage.save_session(&old) {
+                            error!("failed to save session {}: {e:?}", old.id);
+                        }
+                    }
+                    let new = Session::start(shooter);
+                    info!("Started session {} for '{}'", new.id, new.shooter);
+                    if let Err(e) = storage.save_session(&new) {
+                        error!("failed to save session {}: {e:?}", new.id);
+                        send_reply(reply, Err(format!("failed to save session: {e}")));
+                        continue;
+                    }
+                    send_reply(reply, Ok(json!({ "session": new.id })));
+                    session = Some(new);
+                    bus_tx
+                        .send(Event::SessionChanged {
+                            session: session.clone(),
+                        })
+                        .expect("failed to send session change");
+                }
+                HitManagerCommand::EndSession { reply } => {
+                    let Some(mut old) = session.take() else {
+                        send_reply(reply, Err("no session is running".to_string()));
+                        continue;
+                    };
+                    old.ended_at = Some(Local::now());
+                    match storage.save_session(&old) {
+                        Ok(()) => send_reply(reply, Ok(json!({ "session": old.id }))),
+                        Err(e) => {
+                            error!("failed to save session {}: {e:?}", old.id);
+                            send_reply(reply, Err(format!("failed to save session: {e}")));
+                        }
+                    }
+                    bus_tx
+                        .send(Event::SessionChanged { session: None })
+                        .expect("failed to send session change");
+                }
+                HitManagerCommand::DrillFinished { outcome } => {
+                    let Some(session) = &mut session else {
+                        info!("Drill '{}' finished outside of session", outcome.drill_id);
+                        continue;
+                    };
+                    session.drills.push(outcome);
+                    if let Err(e) = storage.save_session(session) {
+                        error!("failed to save session {} drill outcome: {e:?}", session.id);
+                    }
                 }
                 HitManagerCommand::RequestHitClip { timestamp } => {
-                    let clip = match storage.load_clip(timestamp) {
+                    let reader = match storage.open_clip(timestamp) {
                         Ok(v) => v,
                         Err(e) => {
-                            error!("failed to load hit {timestamp} clip from storage: {e:?}");
+                            error!("failed to open hit {timestamp} clip from storage: {e:?}");
                             continue;
                         }
                     };
 
                     bus_tx
-                        .send(Event::LoadedHitClip { timestamp, clip })
+                        .send(Event::HitClipOpened { timestamp, reader })
                         .unwrap();
                 }
+                HitManagerCommand::RequestThumbnail { timestamp } => {
+                    let image = match storage.load_thumbnail(timestamp) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("failed to load hit {timestamp} thumbnail: {e:?}");
+                            continue;
+                        }
+                    };
+
+                    bus_tx
+                        .send(Event::LoadedThumbnail {
+                            timestamp,
+                            image: Arc::new(image),
+                        })
+                        .expect("failed to send thumbnail");
+                }
+                HitManagerCommand::ExportHits { queries, format } => {
+                    let path = Path::new(EXPORTS_PATH).join(format!(
+                        "hits_{}.{}",
+                        Local::now().format("%Y-%m-%d_%H-%M-%S"),
+                        format.extension()
+                    ));
+                    let exported = collect_rows(&mut *storage, &queries).and_then(|rows| {
+                        fs::create_dir_all(EXPORTS_PATH)?;
+                        let f = fs::File::create(&path)?;
+                        write_rows(&rows, format, BufWriter::new(f))?;
+                        Ok(rows.len())
+                    });
+                    let (path, count) = match exported {
+                        Ok(count) => {
+                            info!("Exported {count} hits to {path:?}");
+                            (Some(path), count)
+                        }
+                        Err(e) => {
+                            error!("failed to export hits to {path:?}: {e:?}");
+                            (None, 0)
+                        }
+                    };
+                    bus_tx
+                        .send(Event::HitsExported { path, count })
+                        .expect("failed to send hits export");
+                }
+                HitManagerCommand::ExportArchive { queries } => {
+                    let path = Path::new(EXPORTS_PATH).join(format!(
+                        "hits_{}.tar",
+                        Local::now().format("%Y-%m-%d_%H-%M-%S")
+                    ));
+                    let exported = fs::create_dir_all(EXPORTS_PATH)
+                        .and_then(|_| fs::File::create(&path))
+                        .map_err(anyhow::Error::from)
+                        .and_then(|f| {
+                            export_archive(&mut *storage, &targets, &queries, BufWriter::new(f))
+                        });
+                    let (path, count) = match exported {
+                        Ok(count) => {
+                            info!("Archived {count} hits to {path:?}");
+                            (Some(path), count)
+                        }
+                        Err(e) => {
+                            error!("failed to archive hits to {path:?}: {e:?}");
+                            (None, 0)
+                        }
+                    };
+                    bus_tx
+                        .send(Event::HitsExported { path, count })
+                        .expect("failed to send hits export");
+                }
+                HitManagerCommand::ImportArchive { path } => {
+                    let summary = match import_archive(&mut *storage, &path) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("failed to import {path:?}: {e:?}");
+                            bus_tx
+                                .send(Event::ArchiveImported { path, count: None })
+                                .expect("failed to send archive import");
+                            continue;
+                        }
+                    };
+                    info!("Imported {summary} from {path:?}");
+                    for (timestamp, data) in &summary.hits {
+                        if data.processed.is_none() {
+                            unprocessed_hits.push_back((*timestamp, data.clone()));
+                        }
+                        bus_tx
+                            .send(Event::HitChanged {
+                                timestamp: *timestamp,
+                                data: data.clone(),
+                            })
+                            .expect("failed to send hit change");
+                    }
+                    if processor_ready {
+                        processor_ready =
+                            !process_next(&mut *storage, &bus_tx, &mut unprocessed_hits);
+                    }
+                    bus_tx
+                        .send(Event::ArchiveImported {
+                            path,
+                            count: Some(summary.hits.len()),
+                        })
+                        .expect("failed to send archive import");
+                }
+                HitManagerCommand::ExportReport { session, format } => {
+                    let path = Path::new(EXPORTS_PATH)
+                        .join(format!("report_{session}.{}", format.extension()));
+                    let exported =
+                        build_report(&mut *storage, &targets, &session).and_then(|report| {
+                            fs::create_dir_all(EXPORTS_PATH)?;
+                            let f = fs::File::create(&path)?;
+                            write_report(&report, format, BufWriter::new(f))
+                        });
+                    let path = match exported {
+                        Ok(_) => {
+                            info!("Exported report of session {session} to {path:?}");
+                            Some(path)
+                        }
+                        Err(e) => {
+                            error!("failed to export report to {path:?}: {e:?}");
+                            None
+                        }
+                    };
+                    bus_tx
+                        .send(Event::ReportExported { path })
+                        .expect("failed to send report export");
+                }
+                HitManagerCommand::RenderHeatmap {
+                    request,
+                    preview_path,
+                    export,
+                } => {
+                    let image = match render_heatmap(&mut *storage, &request, &preview_path) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("failed to render heatmap: {e:?}");
+                            continue;
+                        }
+                    };
+
+                    let exported = if export {
+                        let path = Path::new(EXPORTS_PATH).join(format!(
+                            "heatmap_{}_{}.png",
+                            request.target_id,
+                            Local::now().format("%Y-%m-%d_%H-%M-%S")
+                        ));
+                        match fs::create_dir_all(EXPORTS_PATH)
+                            .map_err(anyhow::Error::from)
+                            .and_then(|_| image.save(&path).map_err(anyhow::Error::from))
+                        {
+                            Ok(_) => {
+                                info!("Exported heatmap to {path:?}");
+                                Some(path)
+                            }
+                            Err(e) => {
+                                error!("failed to export heatmap to {path:?}: {e:?}");
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    };
+
+                    bus_tx
+                        .send(Event::HeatmapReady {
+                            image: Arc::new(image),
+                            exported,
+                        })
+                        .expect("failed to send heatmap");
+                }
             }
         }
     });
 
     tx
 }
+
+/// Send oldest queued hit to processor, false if there was none to send
+pub fn process_next(
+    storage: &mut dyn HitStorage,
+    bus_tx: &Sender<Event>,
+    queue: &mut VecDeque<(DateTime<Local>, HitData)>,
+) -> bool {
+    while let Some((timestamp, data)) = queue.pop_front() {
+        let clip = match storage.load_clip(timestamp) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed to load unprocessed hit clip: {e:?}");
+                continue;
+            }
+        };
+
+        bus_tx
+            .send(Event::ProcessHit {
+                timestamp,
+                clip,
+                target_info: data.target_info,
+                target_id: data.target_id,
+            })
+            .expect("failed to request hit process");
+        return true;
+    }
+    false
+}
+
+/// Store process result with hit, hand corrected position is kept and only its score is redone
+pub fn save_processed(
+    storage: &mut dyn HitStorage,
+    targets: &HashMap<String, Target>,
+    timestamp: DateTime<Local>,
+    processed: HitProcessResult,
+) -> Result<HitData> {
+    let mut data = storage.load_data(timestamp)?;
+    let processed = match data.processed.take() {
+        Some(old) if old.corrected => {
+            let (zone, score) = match &old.hit_pos_norm {
+                Some(pos) => score_hit(targets, data.target_id.as_deref(), **pos),
+                None => (None, 0.0),
+            };
+            HitProcessResult {
+                score,
+                zone,
+                processor_version: processed.processor_version,
+                ..old
+            }
+        }
+        _ => processed,
+    };
+    data.processed = Some(processed);
+    storage.save_data(timestamp, data.clone())?;
+    Ok(data)
+}
+
+/// Hits in scope, oldest first
+pub fn reprocess_hits(
+    storage: &mut dyn HitStorage,
+    scope: &ReprocessScope,
+) -> Result<Vec<(DateTime<Local>, HitData)>> {
+    let query = match scope {
+        ReprocessScope::Hit(timestamp) => {
+            return Ok(vec![(*timestamp, storage.load_data(*timestamp)?)]);
+        }
+        ReprocessScope::Session(id) => HitQuery {
+            session: Some(id.clone()),
+            ..Default::default()
+        },
+        ReprocessScope::Outdated => HitQuery {
+            outdated: true,
+            ..Default::default()
+        },
+        ReprocessScope::All => HitQuery::default(),
+    };
+    storage.query_hits(&query)
+}
@@ -1,7 +1,9 @@
 use imageproc::point::Point;
 
+pub mod archive;
 pub mod detector;
 pub mod manager;
+pub mod player;
 pub mod processor;
 pub mod storage;
 
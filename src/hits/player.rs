@@ -0,0 +1,85 @@
+use std::sync::{
+    Arc,
+    mpsc::{self, Sender},
+};
+
+use chrono::{DateTime, Local};
+use imageproc::point::Point;
+use tracing::error;
+
+use crate::{bus::Event, coding::ClipReader, vision::laser::find_red_laser};
+
+pub enum ClipPlayerCommand {
+    /// Show clip starting at frame, `timestamp` identifies the hit it belongs to
+    Open {
+        timestamp: DateTime<Local>,
+        reader: Box<ClipReader>,
+        start: usize,
+        title: String,
+    },
+    /// Decode frame of open clip
+    Frame { index: usize },
+}
+
+/// Decode frames of the clip shown in hit player as playback reaches them, looking for laser on each
+pub fn start_clip_player(bus_tx: Sender<Event>) -> Sender<ClipPlayerCommand> {
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let mut clip: Option<(DateTime<Local>, ClipReader)> = None;
+        let mut shown: Option<usize> = None;
+        while let Ok(first) = rx.recv() {
+            // Playback may ask faster than frames decode, only the latest request matters
+            let mut wanted = None;
+            for cmd in std::iter::once(first).chain(rx.try_iter()) {
+                match cmd {
+                    ClipPlayerCommand::Open {
+                        timestamp,
+                        reader,
+                        start,
+                        title,
+                    } => {
+                        bus_tx
+                            .send(Event::ClipOpened {
+                                timestamp,
+                                frame_times: reader.timestamps().to_vec(),
+                                start,
+                                title,
+                            })
+                            .expect("failed to send opened clip");
+                        clip = Some((timestamp, *reader));
+                        shown = None;
+                        wanted = Some(start);
+                    }
+                    ClipPlayerCommand::Frame { index } => wanted = Some(index),
+                }
+            }
+
+            let (Some(index), Some((timestamp, reader))) = (wanted, &mut clip) else {
+                continue;
+            };
+            if shown == Some(index) {
+                continue;
+            }
+            let frame = match reader.frame(index) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("failed to decode frame {index} of clip {timestamp}: {e:?}");
+                    continue;
+                }
+            };
+            shown = Some(index);
+            let laser = find_red_laser(&frame)
+                .map(|pos| Point::new(pos.x / frame.width() as f32, pos.y / frame.height() as f32));
+            bus_tx
+                .send(Event::ClipFrame {
+                    timestamp: *timestamp,
+                    frame: Arc::new(frame),
+                    laser,
+                })
+                .expect("failed to send clip frame");
+        }
+    });
+
+    tx
+}
@@ -1,25 +1,68 @@
-use std::sync::mpsc::Sender;
+use std::{
+    collections::HashMap,
+    sync::{Arc, mpsc::Sender},
+};
 
+use imageproc::point::Point;
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::{error, info};
 
-use crate::{bus::Event, util::point::MyPoint, vision::laser::find_red_laser};
+use crate::{
+    bus::Event, targets::settings::Target, util::point::MyPoint, vision::laser::find_red_laser,
+};
+
+/// Bumped whenever detection or scoring changes, so results of older versions can be redone
+pub const PROCESSOR_VERSION: u32 = 1;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct HitProcessResult {
     pub score: f32,
     pub hit_pos: Option<MyPoint<f32>>,
+    /// Hit position relative to clip frame size (0..1)
+    #[serde(default)]
+    pub hit_pos_norm: Option<MyPoint<f32>>,
+    /// Target zone hit, none if target is unknown
+    #[serde(default)]
+    pub zone: Option<u8>,
+    /// Position was set by hand and is kept over detection
+    #[serde(default)]
+    pub corrected: bool,
+    /// Processor version that produced result, 0 for results saved before versioning
+    #[serde(default)]
+    pub processor_version: u32,
+}
+
+/// Zone and score at normalized position on target, unknown targets score nothing
+pub fn score_hit(
+    targets: &HashMap<String, Target>,
+    target_id: Option<&str>,
+    pos: Point<f32>,
+) -> (Option<u8>, f32) {
+    let Some(target) = target_id.and_then(|id| targets.get(id)) else {
+        return (None, 0.0);
+    };
+    match target.score_at(pos) {
+        Ok((zone, score)) => (Some(zone), score as f32),
+        Err(e) => {
+            error!("failed to score hit at {pos:?}: {e:?}");
+            (None, 0.0)
+        }
+    }
 }
 
 pub enum HitProcessorCommand {
     ProcessHit {
         timestamp: chrono::DateTime<chrono::Local>,
-        clip: (Vec<image::RgbImage>, u32),
+        clip: crate::coding::Clip,
         target_info: crate::targets::TargetInfo,
+        target_id: Option<String>,
     },
 }
 
-pub fn start_hit_processor(bus_tx: Sender<Event>) -> Sender<HitProcessorCommand> {
+pub fn start_hit_processor(
+    bus_tx: Sender<Event>,
+    targets: Arc<HashMap<String, Target>>,
+) -> Sender<HitProcessorCommand> {
     let (tx, rx) = std::sync::mpsc::channel();
     std::thread::spawn(move || {
         bus_tx
@@ -31,19 +74,34 @@ pub fn start_hit_processor(bus_tx: Sender<Event>) -> Sender<HitProcessorCommand>
                     timestamp,
                     clip,
                     target_info,
+                    target_id,
                 } => {
                     info!("Processing {timestamp:?}");
                     let mut hit_pos = None;
-                    for frame in clip.0 {
+                    let mut hit_pos_norm = None;
+                    for frame in clip.frames {
                         if let Some(pos) = find_red_laser(&frame) {
                             hit_pos = Some(MyPoint::from(pos));
+                            hit_pos_norm = Some(MyPoint::from(Point::new(
+                                pos.x / frame.width() as f32,
+                                pos.y / frame.height() as f32,
+                            )));
                             break;
                         }
                     }
 
+                    let (zone, score) = match &hit_pos_norm {
+                        Some(pos) => score_hit(&targets, target_id.as_deref(), **pos),
+                        None => (None, 0.0),
+                    };
+
                     let res = HitProcessResult {
-                        score: 0.0,
+                        score,
                         hit_pos: hit_pos,
+                        hit_pos_norm,
+                        zone,
+                        corrected: false,
+                        processor_version: PROCESSOR_VERSION,
                     };
 
                     bus_tx
@@ -1,144 +1,334 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use chrono::{DateTime, Local, TimeZone};
 use image::RgbImage;
-use std::{fs, io::BufReader, io::BufWriter, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use tracing::{error, info};
 
-use crate::hits::storage::{HitData, HitStorage};
+use crate::{
+    coding::{Clip, ClipCodec, ClipReader},
+    hits::storage::{
+        HitData, HitQuery, HitStorage, RecordError,
+        schema::{self, Loaded, Record},
+    },
+    sessions::Session,
+};
 
-const TIMESTAMP_DIR_FORMAT: &'static str = "%Y-%m-%d_%H-%M-%S%.3f";
+pub const TIMESTAMP_DIR_FORMAT: &'static str = "%Y-%m-%d_%H-%M-%S%.3f";
+const SESSIONS_DIR: &str = "sessions";
+pub const DATA_FILE: &str = "data.json";
+const THUMBNAIL_FILE: &str = "thumb.jpg";
+/// Deleted hit directories wait here until purged
+const TRASH_DIR: &str = "trash";
 
 pub struct FileHitStorage {
     base: PathBuf,
+    codec: ClipCodec,
+    hit_errors: Vec<RecordError>,
+    session_errors: Vec<RecordError>,
+}
+
+/// Read record, upgrading it from older schema in memory only
+fn read_record<T: Record>(path: &Path) -> Result<T> {
+    Ok(load_record(path)?.record)
+}
+
+fn load_record<T: Record>(path: &Path) -> Result<Loaded<T>> {
+    let f = fs::File::open(path)?;
+    schema::from_value(serde_json::from_reader(BufReader::new(f))?)
+}
+
+/// Write record next to its path and move it in place, so a crash can't leave it half written
+fn write_record<T: Record>(path: &Path, record: &T) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    let mut w = BufWriter::new(fs::File::create(&tmp)?);
+    serde_json::to_writer_pretty(&mut w, &schema::to_value(record)?)?;
+    w.flush()?;
+    w.get_ref().sync_all()?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// Write record back if it was stored in older schema, true if it was
+fn upgrade_record<T: Record>(path: &Path) -> Result<bool> {
+    let Loaded { record, migrated } = load_record::<T>(path)?;
+    if migrated {
+        info!("Upgrading {path:?} to schema version {}", T::VERSION);
+        write_record(path, &record)?;
+    }
+    Ok(migrated)
 }
 
 impl FileHitStorage {
-    pub fn new(base: impl Into<PathBuf>) -> Self {
-        FileHitStorage { base: base.into() }
+    pub fn new(base: impl Into<PathBuf>, codec: ClipCodec) -> Self {
+        FileHitStorage {
+            base: base.into(),
+            codec,
+            hit_errors: Vec::new(),
+            session_errors: Vec::new(),
+        }
     }
 
     fn dir_for(&self, timestamp: DateTime<Local>) -> PathBuf {
         let name = timestamp.format(TIMESTAMP_DIR_FORMAT).to_string();
         self.base.join(name)
     }
+
+    fn trash_dir_for(&self, timestamp: DateTime<Local>) -> PathBuf {
+        let name = timestamp.format(TIMESTAMP_DIR_FORMAT).to_string();
+        self.base.join(TRASH_DIR).join(name)
+    }
+
+    /// Move deleted hit directory back from trash
+    pub fn restore_dir(&self, timestamp: DateTime<Local>) -> Result<()> {
+        let trash = self.trash_dir_for(timestamp);
+        if !trash.exists() {
+            bail!("deleted hit {timestamp} not found");
+        }
+        fs::rename(trash, self.dir_for(timestamp))?;
+        Ok(())
+    }
+
+    fn session_path(&self, id: &str) -> PathBuf {
+        self.base.join(SESSIONS_DIR).join(format!("{id}.json"))
+    }
+
+    /// Data files of hit directories by hit timestamp
+    fn hit_files(&self) -> Result<Vec<(DateTime<Local>, PathBuf)>> {
+        let mut out = Vec::new();
+        if !self.base.exists() {
+            return Ok(out);
+        }
+
+        for entry in fs::read_dir(&self.base)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            // Other directories such as sessions and trash are no hits
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(timestamp) =
+                chrono::NaiveDateTime::parse_from_str(&name, TIMESTAMP_DIR_FORMAT)
+                    .ok()
+                    .and_then(|naive| Local.from_local_datetime(&naive).single())
+            else {
+                continue;
+            };
+            out.push((timestamp, entry.path().join(DATA_FILE)));
+        }
+        Ok(out)
+    }
+
+    fn session_files(&self) -> Result<Vec<PathBuf>> {
+        let dir = self.base.join(SESSIONS_DIR);
+        let mut out = Vec::new();
+        if !dir.exists() {
+            return Ok(out);
+        }
+
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                out.push(path);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Every hit matching query filter, unsorted
+    fn matching_hits(&mut self, query: &HitQuery) -> Result<Vec<(DateTime<Local>, HitData)>> {
+        // Shooter is only known from the session a hit belongs to
+        let shooters: HashMap<String, String> = if query.shooter.is_some() {
+            self.get_all_sessions()?
+                .into_iter()
+                .map(|s| (s.id, s.shooter))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        Ok(self
+            .get_all_hits()?
+            .into_iter()
+            .filter(|(timestamp, hit)| {
+                let shooter = hit.session.as_ref().and_then(|id| shooters.get(id));
+                query.matches(*timestamp, hit, shooter.map(String::as_str))
+            })
+            .collect())
+    }
 }
 
 impl HitStorage for FileHitStorage {
-    fn save_clip(&mut self, timestamp: DateTime<Local>, clip: (&[RgbImage], u32)) -> Result<()> {
-        let (frames, fps) = clip;
+    fn save_clip(&mut self, timestamp: DateTime<Local>, clip: &Clip) -> Result<()> {
         let dir = self.dir_for(timestamp);
         fs::create_dir_all(&dir)?;
-        let clip_path = dir.join("clip.mp4");
-        crate::coding::ffmpeg::save_video(frames, fps, &clip_path)
-            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        let clip_path = dir.join(self.codec.file_name());
+        self.codec.save(clip, &clip_path)?;
+        if let Some(thumbnail) = clip.thumbnail(timestamp)
+            && let Err(e) = thumbnail.save(dir.join(THUMBNAIL_FILE))
+        {
+            error!("failed to save hit {timestamp} thumbnail: {e:?}");
+        }
         Ok(())
     }
 
-    fn load_clip(&mut self, timestamp: DateTime<Local>) -> Result<(Vec<RgbImage>, u32)> {
+    fn load_clip(&mut self, timestamp: DateTime<Local>) -> Result<Clip> {
         let dir = self.dir_for(timestamp);
-        let clip_path = dir.join("clip.mp4");
-        let (frames, fps) = crate::coding::ffmpeg::load_video(&clip_path)
-            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
-        Ok((frames, fps))
+        crate::coding::load_clip(&dir, timestamp)
+    }
+
+    fn open_clip(&mut self, timestamp: DateTime<Local>) -> Result<ClipReader> {
+        ClipReader::open(&self.dir_for(timestamp), timestamp)
+    }
+
+    fn load_thumbnail(&mut self, timestamp: DateTime<Local>) -> Result<RgbImage> {
+        let path = self.dir_for(timestamp).join(THUMBNAIL_FILE);
+        if path.exists() {
+            return Ok(image::open(path)?.to_rgb8());
+        }
+        let thumbnail = self
+            .load_clip(timestamp)?
+            .thumbnail(timestamp)
+            .context("clip has no frames")?;
+        thumbnail.save(path)?;
+        Ok(thumbnail)
     }
 
     fn save_data(&mut self, timestamp: DateTime<Local>, data: HitData) -> Result<()> {
         let dir = self.dir_for(timestamp);
         fs::create_dir_all(&dir)?;
-        let data_path = dir.join("data.json");
-        let f = fs::File::create(data_path)?;
-        let w = BufWriter::new(f);
-        serde_json::to_writer_pretty(w, &data)?;
-        Ok(())
+        write_record(&dir.join(DATA_FILE), &data)
     }
 
     fn load_data(&mut self, timestamp: DateTime<Local>) -> Result<HitData> {
-        let dir = self.dir_for(timestamp);
-        let data_path = dir.join("data.json");
-        let f = fs::File::open(data_path)?;
-        let r = BufReader::new(f);
-        let data = serde_json::from_reader(r)?;
-        Ok(data)
-    }
-
-    fn new_hit(
-        &mut self,
-        timestamp: DateTime<Local>,
-        clip: (&[RgbImage], u32),
-        data: HitData,
-    ) -> Result<()> {
+        read_record(&self.dir_for(timestamp).join(DATA_FILE))
+    }
+
+    fn new_hit(&mut self, timestamp: DateTime<Local>, clip: &Clip, data: HitData) -> Result<()> {
         self.save_clip(timestamp, clip)?;
         self.save_data(timestamp, data)?;
         Ok(())
     }
 
-    fn get_unprocessed_hits_old_sorted(&mut self) -> Result<Vec<DateTime<Local>>> {
-        let mut out = Vec::new();
-        if !self.base.exists() {
-            return Ok(out);
+    fn hit_dir(&self, timestamp: DateTime<Local>) -> PathBuf {
+        self.dir_for(timestamp)
+    }
+
+    fn delete_hit(&mut self, timestamp: DateTime<Local>) -> Result<()> {
+        let dir = self.dir_for(timestamp);
+        if !dir.exists() {
+            bail!("hit {timestamp} not found");
+        }
+        let trash = self.trash_dir_for(timestamp);
+        fs::create_dir_all(self.base.join(TRASH_DIR))?;
+        if trash.exists() {
+            fs::remove_dir_all(&trash)?;
         }
+        fs::rename(dir, trash)?;
+        Ok(())
+    }
 
-        for entry in fs::read_dir(&self.base)? {
-            let entry = entry?;
-            if !entry.file_type()?.is_dir() {
-                continue;
-            }
+    fn restore_hit(&mut self, timestamp: DateTime<Local>) -> Result<HitData> {
+        self.restore_dir(timestamp)?;
+        self.load_data(timestamp)
+    }
 
-            let name = entry.file_name().to_string_lossy().to_string();
-            if let Ok(naive) = chrono::NaiveDateTime::parse_from_str(&name, TIMESTAMP_DIR_FORMAT) {
-                if let Some(dt) = Local.from_local_datetime(&naive).single() {
-                    // try to read data.json and check processed field
-                    let data_path = entry.path().join("data.json");
-                    if data_path.exists() {
-                        if let Ok(f) = fs::File::open(&data_path) {
-                            let res: Result<HitData, _> =
-                                serde_json::from_reader(BufReader::new(f));
-                            if let Ok(hit) = res {
-                                if hit.processed.is_none() {
-                                    out.push(dt);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    fn purge_deleted_hits(&mut self) -> Result<()> {
+        let trash = self.base.join(TRASH_DIR);
+        if trash.exists() {
+            fs::remove_dir_all(trash)?;
         }
+        Ok(())
+    }
 
-        // sort oldest -> newest
+    fn get_unprocessed_hits_old_sorted(&mut self) -> Result<Vec<DateTime<Local>>> {
+        let mut out: Vec<_> = self
+            .get_all_hits()?
+            .into_iter()
+            .filter(|(_, hit)| hit.processed.is_none())
+            .map(|(timestamp, _)| timestamp)
+            .collect();
         out.sort();
-
         Ok(out)
     }
 
-    fn get_all_hits(&mut self) -> Result<std::collections::HashMap<DateTime<Local>, HitData>> {
-        use std::collections::HashMap;
+    fn get_all_hits(&mut self) -> Result<HashMap<DateTime<Local>, HitData>> {
         let mut out = HashMap::new();
-        if !self.base.exists() {
-            return Ok(out);
+        self.hit_errors.clear();
+        for (timestamp, data_path) in self.hit_files()? {
+            match read_record(&data_path) {
+                Ok(hit) => {
+                    out.insert(timestamp, hit);
+                }
+                Err(e) => self
+                    .hit_errors
+                    .push(RecordError::new(data_path.display(), &e)),
+            }
         }
+        Ok(out)
+    }
 
-        for entry in fs::read_dir(&self.base)? {
-            let entry = entry?;
-            if !entry.file_type()?.is_dir() {
-                continue;
-            }
+    fn query_hits(&mut self, query: &HitQuery) -> Result<Vec<(DateTime<Local>, HitData)>> {
+        let hits = self.matching_hits(query)?;
+        Ok(query.sort_and_page(hits))
+    }
 
-            let name = entry.file_name().to_string_lossy().to_string();
-            if let Ok(naive) = chrono::NaiveDateTime::parse_from_str(&name, TIMESTAMP_DIR_FORMAT) {
-                if let Some(dt) = Local.from_local_datetime(&naive).single() {
-                    let data_path = entry.path().join("data.json");
-                    if data_path.exists() {
-                        if let Ok(f) = fs::File::open(&data_path) {
-                            let res: Result<HitData, _> =
-                                serde_json::from_reader(BufReader::new(f));
-                            if let Ok(hit) = res {
-                                out.insert(dt, hit);
-                            }
-                        }
-                    }
-                }
+    fn count_hits(&mut self, query: &HitQuery) -> Result<usize> {
+        Ok(self.matching_hits(query)?.len())
+    }
+
+    fn save_session(&mut self, session: &Session) -> Result<()> {
+        fs::create_dir_all(self.base.join(SESSIONS_DIR))?;
+        write_record(&self.session_path(&session.id), session)
+    }
+
+    fn load_session(&mut self, id: &str) -> Result<Option<Session>> {
+        let path = self.session_path(id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        read_record(&path).map(Some)
+    }
+
+    fn get_all_sessions(&mut self) -> Result<Vec<Session>> {
+        let mut out = Vec::new();
+        self.session_errors.clear();
+        for path in self.session_files()? {
+            match read_record::<Session>(&path) {
+                Ok(session) => out.push(session),
+                Err(e) => self
+                    .session_errors
+                    .push(RecordError::new(path.display(), &e)),
             }
         }
 
+        out.sort_by_key(|s| s.started_at);
         Ok(out)
     }
+
+    fn upgrade_records(&mut self) -> Result<usize> {
+        let hits = self.hit_files()?.into_iter().map(|(_, path)| path);
+        let mut upgraded = 0;
+        // Records that fail to load are reported by full loads
+        for path in hits {
+            upgraded += upgrade_record::<HitData>(&path).unwrap_or(false) as usize;
+        }
+        for path in self.session_files()? {
+            upgraded += upgrade_record::<Session>(&path).unwrap_or(false) as usize;
+        }
+        Ok(upgraded)
+    }
+
+    fn load_errors(&self) -> Vec<RecordError> {
+        self.hit_errors
+            .iter()
+            .chain(&self.session_errors)
+            .cloned()
+            .collect()
+    }
 }
@@ -1,36 +1,146 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
 use anyhow::Result;
 use chrono::{DateTime, Local, TimeZone};
 use image::RgbImage;
 use serde::{Deserialize, Serialize};
+use tracing::error;
 
-use crate::{hits::processor::HitProcessResult, targets::TargetInfo};
+use crate::{
+    coding::{Clip, ClipReader},
+    config::AppConfig,
+    hits::processor::HitProcessResult,
+    sessions::Session,
+    targets::TargetInfo,
+    timer::ShotTiming,
+};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct HitData {
     pub target_info: TargetInfo,
+    /// Id of the target selected when the hit was detected
+    #[serde(default)]
+    pub target_id: Option<String>,
+    /// Name of the lane the hit was detected on
+    #[serde(default)]
+    pub lane: Option<String>,
+    /// Id of the session the hit was shot in
+    #[serde(default)]
+    pub session: Option<String>,
     pub processed: Option<HitProcessResult>,
+    /// Timing relative to shot timer start signal
+    #[serde(default)]
+    pub shot_timing: Option<ShotTiming>,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Shooter called the shot bad before seeing where it went
+    #[serde(default)]
+    pub flyer: bool,
 }
 
 pub trait HitStorage: Send {
-    fn save_clip(&mut self, timestamp: DateTime<Local>, clip: (&[RgbImage], u32)) -> Result<()>;
-    fn load_clip(&mut self, timestamp: DateTime<Local>) -> Result<(Vec<RgbImage>, u32)>;
+    fn save_clip(&mut self, timestamp: DateTime<Local>, clip: &Clip) -> Result<()>;
+    fn load_clip(&mut self, timestamp: DateTime<Local>) -> Result<Clip>;
+    /// Open clip for playback without decoding its frames
+    fn open_clip(&mut self, timestamp: DateTime<Local>) -> Result<ClipReader>;
+    /// Small image of impact frame, made from clip for hits saved without one
+    fn load_thumbnail(&mut self, timestamp: DateTime<Local>) -> Result<RgbImage>;
 
     fn save_data(&mut self, timestamp: DateTime<Local>, data: HitData) -> Result<()>;
     fn load_data(&mut self, timestamp: DateTime<Local>) -> Result<HitData>;
 
-    fn new_hit(
-        &mut self,
-        timestamp: DateTime<Local>,
-        clip: (&[RgbImage], u32),
-        data: HitData,
-    ) -> Result<()>;
+    fn new_hit(&mut self, timestamp: DateTime<Local>, clip: &Clip, data: HitData) -> Result<()>;
+    /// Directory holding clip and other files of hit
+    fn hit_dir(&self, timestamp: DateTime<Local>) -> PathBuf;
+    /// Move hit with its clip out of the way, it can be restored until deleted hits are purged
+    fn delete_hit(&mut self, timestamp: DateTime<Local>) -> Result<()>;
+    fn restore_hit(&mut self, timestamp: DateTime<Local>) -> Result<HitData>;
+    /// Remove deleted hits for good, only done when user asks for it
+    fn purge_deleted_hits(&mut self) -> Result<()>;
 
     fn get_unprocessed_hits_old_sorted(&mut self) -> Result<Vec<DateTime<Local>>>;
     fn get_all_hits(&mut self) -> Result<HashMap<DateTime<Local>, HitData>>;
+    /// Page of hits matching query in its sort order
+    fn query_hits(&mut self, query: &HitQuery) -> Result<Vec<(DateTime<Local>, HitData)>>;
+    /// Number of hits matching query filter, ignoring its page
+    fn count_hits(&mut self, query: &HitQuery) -> Result<usize>;
+
+    fn save_session(&mut self, session: &Session) -> Result<()>;
+    /// None if there is no session with id
+    fn load_session(&mut self, id: &str) -> Result<Option<Session>>;
+    fn get_all_sessions(&mut self) -> Result<Vec<Session>>;
+
+    /// Write records stored in older schema back in current one, returns how many were.
+    /// Reads only upgrade records in memory.
+    fn upgrade_records(&mut self) -> Result<usize>;
+
+    /// Records skipped by the last full load of hits and sessions
+    fn load_errors(&self) -> Vec<RecordError>;
+}
+
+/// Stored record that could not be loaded
+#[derive(Clone, Debug)]
+pub struct RecordError {
+    /// Path or key of record
+    pub record: String,
+    pub error: String,
+}
+
+impl RecordError {
+    pub fn new(record: impl ToString, error: &anyhow::Error) -> Self {
+        error!("failed to load {}: {error:?}", record.to_string());
+        Self {
+            record: record.to_string(),
+            error: format!("{error:#}"),
+        }
+    }
 }
 
 pub mod file;
+pub mod query;
+pub mod schema;
+pub mod sqlite;
 
 pub use file::FileHitStorage;
+pub use query::{HitOrder, HitQuery};
+pub use sqlite::SqliteHitStorage;
+
+const HITS_PATH: &str = "data/hits";
+const HITS_DB_PATH: &str = "data/hits.db";
+
+/// Where hit metadata and sessions are kept
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// `data.json` in every hit directory
+    #[default]
+    Files,
+    /// SQLite database, existing hit directories are imported on first start
+    Sqlite,
+}
+
+/// Storage opened once and shared by hit manager and api server
+pub type SharedStorage = Arc<Mutex<Box<dyn HitStorage>>>;
+
+/// Open hit storage selected in config
+pub fn open_storage(config: &AppConfig) -> Box<dyn HitStorage> {
+    let base = Path::new(HITS_PATH);
+    match config.storage {
+        StorageBackend::Files => Box::new(FileHitStorage::new(base, config.clip_codec)),
+        StorageBackend::Sqlite => {
+            match SqliteHitStorage::open(HITS_DB_PATH, base, config.clip_codec) {
+                Ok(v) => Box::new(v),
+                Err(e) => {
+                    error!("failed to open hit database, using files: {e:?}");
+                    Box::new(FileHitStorage::new(base, config.clip_codec))
+                }
+            }
+        }
+    }
+}
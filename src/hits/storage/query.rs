@@ -0,0 +1,201 @@
+use std::cmp::Ordering;
+
+use chrono::{DateTime, Local};
+
+use crate::hits::{processor::PROCESSOR_VERSION, storage::HitData};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HitOrder {
+    #[default]
+    OldestFirst,
+    NewestFirst,
+    /// Unprocessed hits come last
+    HighestScore,
+    /// Unprocessed hits come last
+    LowestScore,
+}
+
+/// Filter, sort order and page of hits, unset fields match every hit
+#[derive(Clone, Debug, Default)]
+pub struct HitQuery {
+    pub from: Option<DateTime<Local>>,
+    pub to: Option<DateTime<Local>>,
+    pub session: Option<String>,
+    pub shooter: Option<String>,
+    pub lane: Option<String>,
+    pub target_id: Option<String>,
+    /// Score range only matches processed hits
+    pub min_score: Option<f32>,
+    pub max_score: Option<f32>,
+    pub processed: Option<bool>,
+    /// Only hits processed by older processor version
+    pub outdated: bool,
+    pub order: HitOrder,
+    /// Number of matching hits skipped
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl HitQuery {
+    /// Whether hit matches filter, `shooter` is the shooter of the hit session
+    pub fn matches(
+        &self,
+        timestamp: DateTime<Local>,
+        hit: &HitData,
+        shooter: Option<&str>,
+    ) -> bool {
+        let score = hit.processed.as_ref().map(|p| p.score);
+        self.from.is_none_or(|from| timestamp >= from)
+            && self.to.is_none_or(|to| timestamp <= to)
+            && self
+                .session
+                .as_ref()
+                .is_none_or(|v| hit.session.as_ref() == Some(v))
+            && self
+                .shooter
+                .as_ref()
+                .is_none_or(|v| shooter == Some(v.as_str()))
+            && self
+                .lane
+                .as_ref()
+                .is_none_or(|v| hit.lane.as_ref() == Some(v))
+            && self
+                .target_id
+                .as_ref()
+                .is_none_or(|v| hit.target_id.as_ref() == Some(v))
+            && self
+                .min_score
+                .is_none_or(|min| score.is_some_and(|s| s >= min))
+            && self
+                .max_score
+                .is_none_or(|max| score.is_some_and(|s| s <= max))
+            && self.processed.is_none_or(|v| hit.processed.is_some() == v)
+            && (!self.outdated
+                || hit
+                    .processed
+                    .as_ref()
+                    .is_some_and(|p| p.processor_version < PROCESSOR_VERSION))
+    }
+
+    /// Sort matching hits and cut out requested page
+    pub fn sort_and_page(
+        &self,
+        mut hits: Vec<(DateTime<Local>, HitData)>,
+    ) -> Vec<(DateTime<Local>, HitData)> {
+        let score = |hit: &HitData| hit.processed.as_ref().map(|p| p.score);
+        hits.sort_by(|(ta, a), (tb, b)| match self.order {
+            HitOrder::OldestFirst => ta.cmp(tb),
+            HitOrder::NewestFirst => tb.cmp(ta),
+            HitOrder::HighestScore => cmp_score(score(a), score(b), true).then(ta.cmp(tb)),
+            HitOrder::LowestScore => cmp_score(score(a), score(b), false).then(ta.cmp(tb)),
+        });
+        hits.into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+/// Compare scores with missing ones sorted after all others
+fn cmp_score(a: Option<f32>, b: Option<f32>, descending: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) if descending => b.total_cmp(&a),
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+
+    const BASE: i64 = 1_700_000_000;
+
+    fn at(second: i64) -> DateTime<Local> {
+        Local.timestamp_opt(BASE + second, 0).unwrap()
+    }
+
+    fn hit(score: Option<f32>, processor_version: u32) -> HitData {
+        let processed = score.map(|score| {
+            json!({ "score": score, "hit_pos": null, "processor_version": processor_version })
+        });
+        serde_json::from_value(json!({
+            "target_info": { "rect": [
+                { "x": 0.0, "y": 0.0 },
+                { "x": 1.0, "y": 0.0 },
+                { "x": 1.0, "y": 1.0 },
+                { "x": 0.0, "y": 1.0 },
+            ] },
+            "processed": processed,
+        }))
+        .unwrap()
+    }
+
+    fn hits() -> Vec<(DateTime<Local>, HitData)> {
+        vec![
+            (at(0), hit(None, 0)),
+            (at(1), hit(Some(5.0), PROCESSOR_VERSION)),
+            (at(2), hit(Some(9.0), PROCESSOR_VERSION)),
+            (at(3), hit(Some(5.0), PROCESSOR_VERSION)),
+        ]
+    }
+
+    fn seconds(query: HitQuery) -> Vec<i64> {
+        query
+            .sort_and_page(hits())
+            .iter()
+            .map(|(t, _)| t.timestamp() - BASE)
+            .collect()
+    }
+
+    #[test]
+    fn score_orders_put_unprocessed_last() {
+        let highest = HitQuery {
+            order: HitOrder::HighestScore,
+            ..Default::default()
+        };
+        assert_eq!(seconds(highest), [2, 1, 3, 0]);
+        let lowest = HitQuery {
+            order: HitOrder::LowestScore,
+            ..Default::default()
+        };
+        assert_eq!(seconds(lowest), [1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn page_is_cut_after_sorting() {
+        let query = HitQuery {
+            order: HitOrder::NewestFirst,
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(seconds(query), [2, 1]);
+    }
+
+    #[test]
+    fn score_range_only_matches_processed_hits() {
+        let query = HitQuery {
+            min_score: Some(0.0),
+            ..Default::default()
+        };
+        assert!(!query.matches(at(0), &hit(None, 0), None));
+        assert!(query.matches(at(0), &hit(Some(0.0), PROCESSOR_VERSION), None));
+    }
+
+    #[test]
+    fn outdated_matches_older_processor_results() {
+        let query = HitQuery {
+            outdated: true,
+            ..Default::default()
+        };
+        assert!(query.matches(at(0), &hit(Some(1.0), 0), None));
+        assert!(!query.matches(at(0), &hit(Some(1.0), PROCESSOR_VERSION), None));
+        assert!(!query.matches(at(0), &hit(None, 0), None));
+    }
+}
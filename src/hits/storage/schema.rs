@@ -0,0 +1,165 @@
+use anyhow::{Context, Result, bail};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value, json};
+
+use crate::{hits::storage::HitData, sessions::Session};
+
+const VERSION_FIELD: &str = "schema_version";
+
+/// Record written before versioning had no version field
+const UNVERSIONED: u64 = 1;
+
+/// Upgrades record from version `n` to `n + 1`
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// Stored record kind with its current version and the migrations leading there
+pub trait Record: Serialize + DeserializeOwned {
+    /// Migration at index `i` upgrades version `i + 1`
+    const MIGRATIONS: &'static [Migration];
+    const VERSION: u64 = UNVERSIONED + Self::MIGRATIONS.len() as u64;
+}
+
+impl Record for HitData {
+    const MIGRATIONS: &'static [Migration] = &[v1_to_v2];
+}
+
+impl Record for Session {
+    const MIGRATIONS: &'static [Migration] = &[v1_to_v2];
+}
+
+/// Record loaded from storage, `migrated` if it should be written back in current version
+pub struct Loaded<T> {
+    pub record: T,
+    pub migrated: bool,
+}
+
+/// Serialize record with current schema version
+pub fn to_value<T: Record>(record: &T) -> Result<Value> {
+    let mut value = serde_json::to_value(record)?;
+    let Value::Object(map) = &mut value else {
+        bail!("record is not an object");
+    };
+    map.insert(VERSION_FIELD.to_string(), json!(T::VERSION));
+    Ok(value)
+}
+
+/// Upgrade record to current schema version and parse it
+pub fn from_value<T: Record>(mut value: Value) -> Result<Loaded<T>> {
+    let Value::Object(map) = &mut value else {
+        bail!("record is not an object");
+    };
+    let version = match map.remove(VERSION_FIELD) {
+        Some(v) => v.as_u64().context("schema version is not a number")?,
+        None => UNVERSIONED,
+    };
+    if version > T::VERSION {
+        bail!(
+            "record has schema version {version}, newer than supported {}",
+            T::VERSION
+        );
+    }
+    if version < UNVERSIONED {
+        bail!("record has invalid schema version {version}");
+    }
+
+    for (i, migration) in T::MIGRATIONS
+        .iter()
+        .enumerate()
+        .skip((version - UNVERSIONED) as usize)
+    {
+        migration(map).with_context(|| format!("failed to migrate from version {}", i + 1))?;
+    }
+
+    Ok(Loaded {
+        record: serde_json::from_value(value)?,
+        migrated: version < T::VERSION,
+    })
+}
+
+pub fn from_str<T: Record>(text: &str) -> Result<Loaded<T>> {
+    from_value(serde_json::from_str(text)?)
+}
+
+/// Version 2 only adds the version field. Fields that version 1 records lack have serde
+/// defaults, so the first migration with a real shape change will be the one to version 3.
+fn v1_to_v2(_record: &mut Map<String, Value>) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target_info() -> Value {
+        json!({ "rect": [
+            { "x": 0.0, "y": 0.0 },
+            { "x": 1.0, "y": 0.0 },
+            { "x": 1.0, "y": 1.0 },
+            { "x": 0.0, "y": 1.0 },
+        ] })
+    }
+
+    #[test]
+    fn unversioned_hit_gets_missing_fields() {
+        let loaded: Loaded<HitData> = from_value(json!({
+            "target_info": target_info(),
+            "processed": { "score": 7.0, "hit_pos": null },
+        }))
+        .unwrap();
+        assert!(loaded.migrated);
+        let hit = loaded.record;
+        assert_eq!(hit.lane, None);
+        assert_eq!(hit.notes, "");
+        assert!(hit.tags.is_empty());
+        assert!(!hit.flyer);
+        let processed = hit.processed.unwrap();
+        assert_eq!(processed.score, 7.0);
+        assert_eq!(processed.zone, None);
+        assert!(!processed.corrected);
+        assert_eq!(processed.processor_version, 0);
+    }
+
+    #[test]
+    fn unversioned_session_gets_missing_fields() {
+        let loaded: Loaded<Session> = from_value(json!({
+            "id": "2024-01-01_10-00-00",
+            "shooter": "Anna",
+            "started_at": "2024-01-01T10:00:00+01:00",
+        }))
+        .unwrap();
+        assert!(loaded.migrated);
+        assert!(loaded.record.ended_at.is_none());
+        assert!(loaded.record.drills.is_empty());
+    }
+
+    #[test]
+    fn hit_without_target_info_is_refused() {
+        let result = from_value::<HitData>(json!({ "lane": "Lane 1" }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn newer_version_is_refused() {
+        let mut value = json!({ "target_info": target_info() });
+        value[VERSION_FIELD] = json!(HitData::VERSION + 1);
+        assert!(from_value::<HitData>(value).is_err());
+    }
+
+    #[test]
+    fn round_trip_keeps_record_in_current_version() {
+        let hit: HitData = from_value(json!({
+            "target_info": target_info(),
+            "lane": "Lane 1",
+            "processed": { "score": 9.5, "hit_pos": { "x": 3.0, "y": 4.0 }, "zone": 2 },
+            "tags": ["left"],
+        }))
+        .unwrap()
+        .record;
+
+        let value = to_value(&hit).unwrap();
+        assert_eq!(value[VERSION_FIELD], json!(HitData::VERSION));
+        let loaded = from_value::<HitData>(value.clone()).unwrap();
+        assert!(!loaded.migrated);
+        assert_eq!(to_value(&loaded.record).unwrap(), value);
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local};
+use image::RgbImage;
+use rusqlite::{Connection, OptionalExtension, params, params_from_iter, types::Value};
+use tracing::info;
+
+use crate::{
+    coding::{Clip, ClipCodec, ClipReader},
+    hits::{
+        processor::PROCESSOR_VERSION,
+        storage::{
+            FileHitStorage, HitData, HitOrder, HitQuery, HitStorage, RecordError,
+            schema::{self, Loaded},
+        },
+    },
+    sessions::Session,
+};
+
+/// Set in meta table once hit directories were imported
+const FILES_IMPORTED_KEY: &str = "files_imported";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY,
+    shooter TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    ended_at INTEGER,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS sessions_shooter ON sessions (shooter, started_at);
+CREATE TABLE IF NOT EXISTS hits (
+    timestamp INTEGER PRIMARY KEY,
+    session TEXT,
+    shooter TEXT,
+    lane TEXT,
+    target_id TEXT,
+    processed INTEGER NOT NULL,
+    score REAL,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS hits_session ON hits (session, timestamp);
+CREATE INDEX IF NOT EXISTS hits_shooter ON hits (shooter, timestamp);
+CREATE INDEX IF NOT EXISTS hits_processed ON hits (processed, timestamp);
+CREATE INDEX IF NOT EXISTS hits_lane ON hits (lane, timestamp);
+CREATE TABLE IF NOT EXISTS deleted_hits (
+    timestamp INTEGER PRIMARY KEY,
+    data TEXT NOT NULL
+);
+";
+
+/// Hit metadata and sessions in SQLite database, clips stay in hit directories
+pub struct SqliteHitStorage {
+    conn: Connection,
+    clips: FileHitStorage,
+    /// Hit directories and sessions that failed to import
+    import_errors: Vec<RecordError>,
+    hit_errors: Vec<RecordError>,
+    session_errors: Vec<RecordError>,
+}
+
+impl SqliteHitStorage {
+    /// Open database at `db_path`, hits saved in `base` by [`FileHitStorage`] are imported once
+    pub fn open(db_path: impl AsRef<Path>, base: &Path, codec: ClipCodec) -> Result<Self> {
+        if let Some(dir) = db_path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(db_path.as_ref())
+            .with_context(|| format!("failed to open hit database {:?}", db_path.as_ref()))?;
+        conn.execute_batch(SCHEMA)?;
+
+        let mut storage = Self {
+            conn,
+            clips: FileHitStorage::new(base, codec),
+            import_errors: Vec::new(),
+            hit_errors: Vec::new(),
+            session_errors: Vec::new(),
+        };
+        storage.import_files()?;
+        Ok(storage)
+    }
+
+    /// Import hit directories and sessions written by file storage
+    fn import_files(&mut self) -> Result<()> {
+        let imported: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                [FILES_IMPORTED_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if imported.is_some() {
+            return Ok(());
+        }
+
+        let sessions = self.clips.get_all_sessions()?;
+        let hits = self.clips.get_all_hits()?;
+        self.import_errors = self.clips.load_errors();
+        info!(
+            "Importing {} hits and {} sessions into database",
+            hits.len(),
+            sessions.len()
+        );
+
+        let tx = self.conn.transaction()?;
+        for session in &sessions {
+            insert_session(&tx, session)?;
+        }
+        for (timestamp, data) in &hits {
+            insert_hit(&tx, *timestamp, data)?;
+        }
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)",
+            params![FILES_IMPORTED_KEY, Local::now().to_rfc3339()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+fn insert_session(conn: &Connection, session: &Session) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO sessions (id, shooter, started_at, ended_at, data)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            session.id,
+            session.shooter,
+            session.started_at.timestamp_millis(),
+            session.ended_at.map(|t| t.timestamp_millis()),
+            schema::to_value(session)?.to_string(),
+        ],
+    )?;
+    // Hits keep shooter of their session for indexed lookups
+    conn.execute(
+        "UPDATE hits SET shooter = ?2 WHERE session = ?1",
+        params![session.id, session.shooter],
+    )?;
+    Ok(())
+}
+
+fn insert_hit(conn: &Connection, timestamp: DateTime<Local>, data: &HitData) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO hits
+         (timestamp, session, shooter, lane, target_id, processed, score, data)
+         VALUES (?1, ?2, (SELECT shooter FROM sessions WHERE id = ?2), ?3, ?4, ?5, ?6, ?7)",
+        params![
+            timestamp.timestamp_millis(),
+            data.session,
+            data.lane,
+            data.target_id,
+            data.processed.is_some(),
+            data.processed.as_ref().map(|p| p.score),
+            schema::to_value(data)?.to_string(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// SQL condition and its parameters for query filter
+fn where_clause(query: &HitQuery) -> (String, Vec<Value>) {
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+    let mut add = |condition: &str, value: Value| {
+        values.push(value);
+        conditions.push(condition.replace('?', &format!("?{}", values.len())));
+    };
+
+    if let Some(from) = query.from {
+        add("timestamp >= ?", Value::Integer(from.timestamp_millis()));
+    }
+    if let Some(to) = query.to {
+        add("timestamp <= ?", Value::Integer(to.timestamp_millis()));
+    }
+    if let Some(session) = &query.session {
+        add("session = ?", Value::Text(session.clone()));
+    }
+    if let Some(shooter) = &query.shooter {
+        add("shooter = ?", Value::Text(shooter.clone()));
+    }
+    if let Some(lane) = &query.lane {
+        add("lane = ?", Value::Text(lane.clone()));
+    }
+    if let Some(target_id) = &query.target_id {
+        add("target_id = ?", Value::Text(target_id.clone()));
+    }
+    if let Some(min) = query.min_score {
+        add("score >= ?", Value::Real(min as f64));
+    }
+    if let Some(max) = query.max_score {
+        add("score <= ?", Value::Real(max as f64));
+    }
+    if let Some(processed) = query.processed {
+        add("processed = ?", Value::Integer(processed as i64));
+    }
+    if query.outdated {
+        add(
+            "processed = 1 AND COALESCE(json_extract(data, '$.processed.processor_version'), 0) < ?",
+            Value::Integer(PROCESSOR_VERSION as i64),
+        );
+    }
+
+    if conditions.is_empty() {
+        (String::new(), values)
+    } else {
+        (format!("WHERE {}", conditions.join(" AND ")), values)
+    }
+}
+
+/// Hits are keyed by milliseconds like hit directories and UI timestamps
+fn from_millis(millis: i64) -> Result<DateTime<Local>> {
+    Ok(DateTime::from_timestamp_millis(millis)
+        .context("invalid hit timestamp")?
+        .with_timezone(&Local))
+}
+
+impl HitStorage for SqliteHitStorage {
+    fn save_clip(&mut self, timestamp: DateTime<Local>, clip: &Clip) -> Result<()> {
+        self.clips.save_clip(timestamp, clip)
+    }
+
+    fn load_clip(&mut self, timestamp: DateTime<Local>) -> Result<Clip> {
+        self.clips.load_clip(timestamp)
+    }
+
+    fn open_clip(&mut self, timestamp: DateTime<Local>) -> Result<ClipReader> {
+        self.clips.open_clip(timestamp)
+    }
+
+    fn load_thumbnail(&mut self, timestamp: DateTime<Local>) -> Result<RgbImage> {
+        self.clips.load_thumbnail(timestamp)
+    }
+
+    fn save_data(&mut self, timestamp: DateTime<Local>, data: HitData) -> Result<()> {
+        insert_hit(&self.conn, timestamp, &data)
+    }
+
+    fn load_data(&mut self, timestamp: DateTime<Local>) -> Result<HitData> {
+        let data: String = self
+            .conn
+            .query_row(
+                "SELECT data FROM hits WHERE timestamp = ?1",
+                [timestamp.timestamp_millis()],
+                |row| row.get(0),
+            )
+            .with_context(|| format!("hit {timestamp} not found"))?;
+        Ok(schema::from_str(&data)?.record)
+    }
+
+    fn new_hit(&mut self, timestamp: DateTime<Local>, clip: &Clip, data: HitData) -> Result<()> {
+        self.save_clip(timestamp, clip)?;
+        self.save_data(timestamp, data)?;
+        Ok(())
+    }
+
+    fn hit_dir(&self, timestamp: DateTime<Local>) -> PathBuf {
+        self.clips.hit_dir(timestamp)
+    }
+
+    fn delete_hit(&mut self, timestamp: DateTime<Local>) -> Result<()> {
+        let millis = timestamp.timestamp_millis();
+        let tx = self.conn.transaction()?;
+        let moved = tx.execute(
+            "INSERT OR REPLACE INTO deleted_hits (timestamp, data)
+             SELECT timestamp, data FROM hits WHERE timestamp = ?1",
+            [millis],
+        )?;
+        if moved == 0 {
+            bail!("hit {timestamp} not found");
+        }
+        tx.execute("DELETE FROM hits WHERE timestamp = ?1", [millis])?;
+        // Clip goes to trash together with the row, or neither does
+        self.clips.delete_hit(timestamp)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn restore_hit(&mut self, timestamp: DateTime<Local>) -> Result<HitData> {
+        let millis = timestamp.timestamp_millis();
+        let data: String = self
+            .conn
+            .query_row(
+                "SELECT data FROM deleted_hits WHERE timestamp = ?1",
+                [millis],
+                |row| row.get(0),
+            )
+            .with_context(|| format!("deleted hit {timestamp} not found"))?;
+        let data: HitData = schema::from_str(&data)?.record;
+
+        self.clips.restore_dir(timestamp)?;
+        let tx = self.conn.transaction()?;
+        insert_hit(&tx, timestamp, &data)?;
+        tx.execute("DELETE FROM deleted_hits WHERE timestamp = ?1", [millis])?;
+        tx.commit()?;
+        Ok(data)
+    }
+
+    fn purge_deleted_hits(&mut self) -> Result<()> {
+        self.clips.purge_deleted_hits()?;
+        self.conn.execute("DELETE FROM deleted_hits", [])?;
+        Ok(())
+    }
+
+    fn get_unprocessed_hits_old_sorted(&mut self) -> Result<Vec<DateTime<Local>>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT timestamp FROM hits WHERE processed = 0 ORDER BY timestamp")?;
+        let rows = stmt.query_map([], |row| row.get::<_, i64>(0))?;
+        let mut out = Vec::new();
+        for millis in rows {
+            out.push(from_millis(millis?)?);
+        }
+        Ok(out)
+    }
+
+    fn get_all_hits(&mut self) -> Result<HashMap<DateTime<Local>, HitData>> {
+        let mut stmt = self.conn.prepare("SELECT timestamp, data FROM hits")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut out = HashMap::new();
+        self.hit_errors.clear();
+        for row in rows {
+            let (millis, data) = row?;
+            let timestamp = from_millis(millis)?;
+            match schema::from_str(&data).map(|v| v.record) {
+                Ok(hit) => {
+                    out.insert(timestamp, hit);
+                }
+                Err(e) => self
+                    .hit_errors
+                    .push(RecordError::new(format!("hit {timestamp}"), &e)),
+            }
+        }
+        Ok(out)
+    }
+
+    fn query_hits(&mut self, query: &HitQuery) -> Result<Vec<(DateTime<Local>, HitData)>> {
+        let (filter, values) = where_clause(query);
+        let order = match query.order {
+            HitOrder::OldestFirst => "timestamp ASC",
+            HitOrder::NewestFirst => "timestamp DESC",
+            HitOrder::HighestScore => "score IS NULL, score DESC, timestamp ASC",
+            HitOrder::LowestScore => "score IS NULL, score ASC, timestamp ASC",
+        };
+        let limit = query.limit.map_or(-1, |v| v as i64);
+        let sql = format!(
+            "SELECT timestamp, data FROM hits {filter} ORDER BY {order} LIMIT {limit} OFFSET {}",
+            query.offset
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut out = Vec::new();
+        for row in rows {
+            let (millis, data) = row?;
+            out.push((from_millis(millis)?, schema::from_str(&data)?.record));
+        }
+        Ok(out)
+    }
+
+    fn count_hits(&mut self, query: &HitQuery) -> Result<usize> {
+        let (filter, values) = where_clause(query);
+        let count: i64 = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM hits {filter}"),
+            params_from_iter(values),
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    fn save_session(&mut self, session: &Session) -> Result<()> {
+        insert_session(&self.conn, session)
+    }
+
+    fn load_session(&mut self, id: &str) -> Result<Option<Session>> {
+        let data: Option<String> = self
+            .conn
+            .query_row("SELECT data FROM sessions WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        data.map(|data| Ok(schema::from_str(&data)?.record))
+            .transpose()
+    }
+
+    fn get_all_sessions(&mut self) -> Result<Vec<Session>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, data FROM sessions ORDER BY started_at")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut out = Vec::new();
+        self.session_errors.clear();
+        for row in rows {
+            let (id, data) = row?;
+            match schema::from_str(&data).map(|v| v.record) {
+                Ok(session) => out.push(session),
+                Err(e) => self
+                    .session_errors
+                    .push(RecordError::new(format!("session {id}"), &e)),
+            }
+        }
+        Ok(out)
+    }
+
+    fn upgrade_records(&mut self) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut upgraded = 0;
+        {
+            let mut stmt = tx.prepare("SELECT timestamp, data FROM hits")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?;
+            for row in rows {
+                let (millis, data) = row?;
+                // Rows that fail to load are reported by full loads
+                if let Ok(Loaded {
+                    record,
+                    migrated: true,
+                }) = schema::from_str::<HitData>(&data)
+                {
+                    insert_hit(&tx, from_millis(millis)?, &record)?;
+                    upgraded += 1;
+                }
+            }
+
+            let mut stmt = tx.prepare("SELECT data FROM sessions")?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            for data in rows {
+                if let Ok(Loaded {
+                    record,
+                    migrated: true,
+                }) = schema::from_str::<Session>(&data?)
+                {
+                    insert_session(&tx, &record)?;
+                    upgraded += 1;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(upgraded)
+    }
+
+    fn load_errors(&self) -> Vec<RecordError> {
+        self.import_errors
+            .iter()
+            .chain(&self.hit_errors)
+            .chain(&self.session_errors)
+            .cloned()
+            .collect()
+    }
+}
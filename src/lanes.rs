@@ -0,0 +1,229 @@
+use std::{
+    fs,
+    io::{BufReader, BufWriter},
+    path::Path,
+    sync::{Arc, RwLock, mpsc::Sender},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use image::{Rgb, RgbImage};
+use imageproc::{
+    drawing::draw_hollow_polygon_mut, geometric_transformations::Projection, point::Point,
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{
+    bus::{CommandReply, Event},
+    capturer::CapturedFrame,
+    coding::Clip,
+    hits::{
+        LaserInfo,
+        detector::{HitDetectorCommand, start_hit_detector},
+    },
+    recorder::{Recorder, RecorderConfig},
+    targets::{
+        TargetInfo,
+        recognizer::{TargetRecognizerCommand, start_target_recognizer},
+    },
+    vision::{project::unwarp_rectangle, stencil::Stencil, zones::ZoneMap},
+};
+
+const LANES_PATH: &str = "data/lanes.json";
+
+/// Size of unwarped target frames
+pub const TARGET_FRAME_WIDTH: u32 = 600;
+pub const TARGET_FRAME_HEIGHT: u32 = 800;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LaneConfig {
+    pub name: String,
+    pub stencil: Stencil,
+    /// Id of camera the lane target is seen by
+    #[serde(default)]
+    pub camera: String,
+    /// Target assigned to lane
+    #[serde(default)]
+    pub target_id: Option<String>,
+}
+
+/// Single target in camera frame with its own recognition and detection
+pub struct Lane {
+    pub config: LaneConfig,
+    pub target_info: Arc<RwLock<Option<TargetInfo>>>,
+    recorder: Arc<Recorder>,
+    last_frame: Arc<RwLock<Option<Arc<CapturedFrame>>>>,
+    /// Recognizer and detector threads stop once their senders are dropped
+    recognizer: Sender<TargetRecognizerCommand>,
+    detector: Sender<HitDetectorCommand>,
+}
+
+impl Lane {
+    pub fn start(
+        config: LaneConfig,
+        recorder_config: RecorderConfig,
+        bus_tx: Sender<Event>,
+    ) -> Self {
+        let target_info = Arc::new(RwLock::new(None));
+        let zone_map: Arc<RwLock<Option<ZoneMap>>> = Arc::new(RwLock::new(None));
+        let laser_info: Arc<RwLock<Option<LaserInfo>>> = Arc::new(RwLock::new(None));
+        let recorder = Arc::new(Recorder::new(recorder_config));
+        let last_frame = Arc::new(RwLock::new(None));
+
+        let recognizer = start_target_recognizer(
+            bus_tx.clone(),
+            config.name.clone(),
+            target_info.clone(),
+            zone_map,
+            last_frame.clone(),
+        );
+        let detector = start_hit_detector(
+            bus_tx,
+            config.name.clone(),
+            laser_info,
+            target_info.clone(),
+            recorder.clone(),
+        );
+
+        Self {
+            config,
+            target_info,
+            recorder,
+            last_frame,
+            recognizer,
+            detector,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Look for target on next frame instead of waiting for periodic recognition
+    pub fn recognize_target(&self, reply: Option<CommandReply>) {
+        self.recognizer
+            .send(TargetRecognizerCommand::RecognizeNow { reply })
+            .expect("failed to request target recognition");
+    }
+
+    pub fn set_stencil(&mut self, stencil: Stencil) {
+        self.config.stencil = stencil;
+        *self.target_info.write().unwrap() = None;
+    }
+
+    pub fn set_camera(&mut self, camera: String) {
+        self.config.camera = camera;
+        *self.target_info.write().unwrap() = None;
+    }
+
+    /// Cut lane target out of camera frame and feed it to recognizer, recorder and detector.
+    /// Recognized target outline is drawn on overlay.
+    pub fn process_frame(&self, frame: &CapturedFrame, overlay: &mut RgbImage) -> RgbImage {
+        let mut target_frame = self.config.stencil.crop(&frame.image).to_image();
+        *self.last_frame.write().unwrap() = Some(Arc::new(CapturedFrame {
+            image: target_frame.clone(),
+            timestamp: frame.timestamp,
+        }));
+
+        if let Some(target_info) = &*self.target_info.read().unwrap() {
+            if let Some(unwarped) = unwarp_rectangle(
+                &target_frame,
+                &target_info.rect,
+                TARGET_FRAME_WIDTH,
+                TARGET_FRAME_HEIGHT,
+            ) {
+                target_frame = unwarped;
+            }
+        }
+        if let Some(polygon) = self.target_polygon(frame.image.dimensions()) {
+            draw_hollow_polygon_mut(overlay, &polygon, Rgb([0, 255, 0]));
+        }
+
+        let captured_target_frame = Arc::new(CapturedFrame {
+            image: target_frame.clone(),
+            timestamp: frame.timestamp,
+        });
+        self.recorder.push_frame(captured_target_frame.clone());
+        self.detector
+            .send(HitDetectorCommand::NewFrame(captured_target_frame))
+            .expect("failed to send frame to hit detector");
+
+        target_frame
+    }
+
+    /// Recorded target frames between times
+    pub fn clip(&self, from: DateTime<Local>, to: DateTime<Local>) -> Clip {
+        Clip::from_captured(&self.recorder.snapshot(from, to))
+    }
+
+    /// Recognized target outline in camera frame coords
+    pub fn target_polygon(&self, camera_size: (u32, u32)) -> Option<Vec<Point<f32>>> {
+        let target_info = self.target_info.read().unwrap();
+        let target_info = target_info.as_ref()?;
+        let r = self.config.stencil.rect(camera_size.0, camera_size.1);
+        Some(
+            target_info
+                .rect
+                .iter()
+                .map(|p| Point::new(r.x as f32 + p.x, r.y as f32 + p.y))
+                .collect(),
+        )
+    }
+
+    /// Map position relative to lane target frame (0..1) to camera frame coords
+    pub fn to_camera(&self, pos: Point<f32>, camera_size: (u32, u32)) -> Point<f32> {
+        let r = self.config.stencil.rect(camera_size.0, camera_size.1);
+        let stencil_pos = (pos.x * r.width as f32, pos.y * r.height as f32);
+        let (x, y) = match &*self.target_info.read().unwrap() {
+            Some(target_info) => {
+                let [tl, tr, br, bl] = &target_info.rect;
+                let (w, h) = (TARGET_FRAME_WIDTH as f32, TARGET_FRAME_HEIGHT as f32);
+                Projection::from_control_points(
+                    [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)],
+                    [(tl.x, tl.y), (tr.x, tr.y), (br.x, br.y), (bl.x, bl.y)],
+                )
+                .map(|projection| projection * (pos.x * w, pos.y * h))
+                .unwrap_or(stencil_pos)
+            }
+            None => stencil_pos,
+        };
+        Point::new(r.x as f32 + x, r.y as f32 + y)
+    }
+}
+
+fn default_lanes() -> Vec<LaneConfig> {
+    vec![LaneConfig {
+        name: "Lane 1".to_string(),
+        stencil: Stencil::default(),
+        camera: String::new(),
+        target_id: None,
+    }]
+}
+
+pub fn load_lanes() -> Vec<LaneConfig> {
+    if !Path::new(LANES_PATH).exists() {
+        return default_lanes();
+    }
+
+    let lanes: Result<Vec<LaneConfig>> = fs::File::open(LANES_PATH)
+        .map_err(anyhow::Error::from)
+        .and_then(|f| Ok(serde_json::from_reader(BufReader::new(f))?));
+    match lanes {
+        Ok(lanes) if !lanes.is_empty() => lanes,
+        Ok(_) => default_lanes(),
+        Err(e) => {
+            error!("failed to load lanes from {LANES_PATH}: {e:?}");
+            default_lanes()
+        }
+    }
+}
+
+pub fn save_lanes(lanes: &[LaneConfig]) -> Result<()> {
+    if let Some(dir) = Path::new(LANES_PATH).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let f = fs::File::create(LANES_PATH)?;
+    serde_json::to_writer_pretty(BufWriter::new(f), lanes)?;
+    Ok(())
+}
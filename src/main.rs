@@ -1,30 +1,96 @@
 slint::include_modules!();
 
-use chrono::Local;
+use chrono::{DateTime, Local, NaiveDate};
+use clap::Parser;
 use slint::{ComponentHandle, Model, ModelExt};
-use tracing::info;
+use tracing::{error, info};
 
-use crate::bus::AppMessage;
+use crate::{
+    bus::AppMessage,
+    hits::{manager::ReprocessScope, storage::HitQuery},
+    report::{export::ExportFormat, heatmap::HeatmapRequest, sheet::ReportFormat},
+    timer::ShotTimerConfig,
+};
 
 const TIMESTAMP_UI_FORMAT: &'static str = "%Y-%m-%d_%H-%M-%S%.3f";
+const DATE_UI_FORMAT: &'static str = "%Y-%m-%d";
+/// Playback looping around impact covers this much before and after it
+const IMPACT_LOOP_MS: i32 = 300;
 
+mod api;
 mod bus;
 mod capturer;
+mod cli;
 mod coding;
+mod config;
+mod drills;
 mod hits;
+mod lanes;
 mod recorder;
+mod recording;
+mod report;
+mod sessions;
 mod targets;
+mod timer;
 mod util;
 mod vision;
 
+/// Run capture, detection and storage without UI until event loop stops
+fn run_headless() {
+    if !crate::config::load_config().api.enabled {
+        info!("Api is disabled in config, headless mode can only be driven by config");
+    }
+    let (_bus_tx, bus_rx) = crate::bus::start(true);
+    for msg in bus_rx {
+        match msg {
+            AppMessage::NewHit {
+                lane, timestamp, ..
+            } => info!("New hit {timestamp} on lane '{lane}'"),
+            AppMessage::HitChanged { timestamp, data } => {
+                if let Some(result) = &data.processed {
+                    info!("Hit {timestamp} scored {}", result.score);
+                }
+            }
+            AppMessage::LoadErrors { errors } => {
+                error!("{} stored records could not be loaded", errors.len());
+            }
+            AppMessage::DrillFinished { outcome } => {
+                info!(
+                    "Drill '{}' finished with {} points",
+                    outcome.name, outcome.points
+                );
+            }
+            _ => {}
+        }
+    }
+    error!("event loop stopped");
+    std::process::exit(1);
+}
+
 fn main() {
-    tracing_subscriber::fmt().init();
+    // Logs go to stderr, commands write their data to stdout
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = crate::cli::Cli::parse();
+    if let Some(command) = cli.command {
+        if let Err(e) = crate::cli::run(command) {
+            error!("{e:?}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    if cli.headless {
+        run_headless();
+        return;
+    }
 
     let ui = MainWindow::new().unwrap();
     let ui_weak = ui.as_weak();
 
     // Start app event loop
-    let (bus_tx, bus_rx) = crate::bus::start();
+    let (bus_tx, bus_rx) = crate::bus::start(false);
 
     // UI event handling thread
     std::thread::spawn(move || {
@@ -68,50 +134,120 @@ fn main() {
                         let ui = ui.upgrade().unwrap();
                         let hits: Vec<HitInfo> = hits
                             .into_iter()
-                            .map(|(timestamp, data)| {
-                                HitInfo {
-                                    timestamp: timestamp
-                                        .format(TIMESTAMP_UI_FORMAT)
-                                        .to_string()
-                                        .into(),
-                                    // target_info: data.target_info,
-                                    is_processed: data.processed.is_some(),
-                                    ..Default::default()
-                                }
-                            })
+                            .map(|(timestamp, data)| hit_info(timestamp, data))
                             .collect();
+                        set_heatmap_sessions(&ui, hits.iter().map(|h| h.session.to_string()));
                         ui.global::<HitManagerState>()
                             .set_hits(hits.as_slice().into());
                     })
                     .ok();
                 }
-                AppMessage::LoadedHitClip { timestamp, clip } => {
+                AppMessage::LoadErrors { errors } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let errors: Vec<slint::SharedString> = errors
+                            .into_iter()
+                            .map(|e| format!("{}: {}", e.record, e.error).into())
+                            .collect();
+                        ui.global::<HitManagerState>()
+                            .set_load_errors(errors.as_slice().into());
+                    })
+                    .ok();
+                }
+                AppMessage::ClipOpened {
+                    timestamp,
+                    frame_times,
+                    start,
+                    title,
+                } => {
                     let ui = ui_weak.clone();
                     slint::invoke_from_event_loop(move || {
                         let ui = ui.upgrade().unwrap();
-                        let selected_timestamp =
-                            ui.global::<HitManagerState>().get_selected_hit().timestamp;
-                        let current_timestamp = timestamp.format(TIMESTAMP_UI_FORMAT).to_string();
+                        let state = ui.global::<HitManagerState>();
 
                         // Only display if this is for the currently selected hit
-                        if selected_timestamp == current_timestamp {
-                            let (frames, _fps) = clip;
-                            let v: Vec<_> = frames.into_iter().map(|frame| 
-                                // Display first frame of the clip
-                                slint::Image::from_rgb8(
-                                    slint::SharedPixelBuffer::<slint::Rgb8Pixel>::clone_from_slice(
-                                        frame.as_raw(),
-                                        frame.width(),
-                                        frame.height(),
-                                    )
+                        if state.get_selected_hit().timestamp
+                            != timestamp.format(TIMESTAMP_UI_FORMAT).to_string()
+                        {
+                            return;
+                        }
+
+                        let times: Vec<i32> = frame_times
+                            .iter()
+                            .map(|t| (*t - frame_times[0]).num_milliseconds() as i32)
+                            .collect();
+                        state.set_selected_hit_clip_times(times.as_slice().into());
+
+                        // Mark every hit of the lane that happened during the clip
+                        let lane = state.get_selected_hit().lane;
+                        let impacts: Vec<i32> = state
+                            .get_hits()
+                            .iter()
+                            .filter(|h| h.lane == lane)
+                            .filter_map(|h| {
+                                impact_frame(&frame_times, parse_ui_timestamp(&h.timestamp))
+                            })
+                            .collect();
+                        state.set_impact_frames(impacts.as_slice().into());
+                        let impact = impact_frame(&frame_times, timestamp);
+                        let (loop_start, loop_end) =
+                            impact.map_or((0, times.len() as i32 - 1), |i| {
+                                let at = times[i as usize];
+                                (
+                                    times.partition_point(|t| *t < at - IMPACT_LOOP_MS) as i32,
+                                    times.partition_point(|t| *t <= at + IMPACT_LOOP_MS) as i32 - 1,
                                 )
-                            ).collect();
-                            ui.global::<HitManagerState>().set_selected_hit_clip(v.as_slice().into());
+                            });
+                        state.set_impact_frame(impact.unwrap_or(-1));
+                        state.set_loop_start(loop_start);
+                        state.set_loop_end(loop_end);
+                        state.set_clip_frame(slint::Image::default());
+                        state.set_clip_frame_index(start as i32);
+                        state.set_clip_title(title.into());
+                    })
+                    .ok();
+                }
+                AppMessage::ClipFrame {
+                    timestamp,
+                    frame,
+                    laser,
+                } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let state = ui.global::<HitManagerState>();
+                        if state.get_selected_hit().timestamp
+                            == timestamp.format(TIMESTAMP_UI_FORMAT).to_string()
+                        {
+                            state.set_clip_frame(slint_image(&frame));
+                            state.set_frame_has_laser(laser.is_some());
+                            if let Some(pos) = laser {
+                                state.set_frame_laser_x(pos.x);
+                                state.set_frame_laser_y(pos.y);
+                            }
                         }
                     })
                     .ok();
                 }
+                AppMessage::LoadedThumbnail { timestamp, image } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let hits = ui.global::<HitManagerState>().get_hits();
+                        let timestamp = timestamp.format(TIMESTAMP_UI_FORMAT).to_string();
+                        let Some(index) = hits.iter().position(|h| h.timestamp == timestamp) else {
+                            return;
+                        };
+                        let mut hit = hits.row_data(index).unwrap();
+                        hit.thumbnail = slint_image(&image);
+                        hit.has_thumbnail = true;
+                        hits.set_row_data(index, hit);
+                    })
+                    .ok();
+                }
                 AppMessage::NewHit {
+                    lane,
                     timestamp,
                     clip,
                     target_info,
@@ -124,6 +260,7 @@ fn main() {
                         let new_hit = HitInfo {
                             timestamp: timestamp.format(TIMESTAMP_UI_FORMAT).to_string().into(),
                             is_processed: false,
+                            lane: lane.into(),
                             ..Default::default()
                         };
 
@@ -144,6 +281,338 @@ fn main() {
                     })
                     .ok();
                 }
+                AppMessage::HitChanged { timestamp, data } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let state = ui.global::<HitManagerState>();
+                        let info = hit_info(timestamp, data);
+
+                        let mut hits: Vec<HitInfo> = state.get_hits().iter().collect();
+                        match hits.iter_mut().find(|h| h.timestamp == info.timestamp) {
+                            // Clip did not change, neither did its thumbnail
+                            Some(hit) => {
+                                *hit = HitInfo {
+                                    thumbnail: hit.thumbnail.clone(),
+                                    has_thumbnail: hit.has_thumbnail,
+                                    ..info.clone()
+                                }
+                            }
+                            // Restored hit goes back to its place in time
+                            None => {
+                                let index = hits
+                                    .iter()
+                                    .position(|h| h.timestamp > info.timestamp)
+                                    .unwrap_or(hits.len());
+                                hits.insert(index, info.clone());
+                            }
+                        }
+                        state.set_hits(hits.as_slice().into());
+
+                        if state.get_is_hit_selected()
+                            && state.get_selected_hit().timestamp == info.timestamp
+                        {
+                            state.set_selected_hit(info);
+                        }
+                    })
+                    .ok();
+                }
+                AppMessage::HitDeleted { timestamp } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let state = ui.global::<HitManagerState>();
+                        let timestamp = timestamp.format(TIMESTAMP_UI_FORMAT).to_string();
+
+                        let hits: Vec<HitInfo> = state
+                            .get_hits()
+                            .iter()
+                            .filter(|h| h.timestamp != timestamp)
+                            .collect();
+                        state.set_hits(hits.as_slice().into());
+
+                        if state.get_selected_hit().timestamp == timestamp {
+                            state.set_is_hit_selected(false);
+                        }
+                    })
+                    .ok();
+                }
+                AppMessage::LoadedTargets { targets } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let (ids, names): (Vec<slint::SharedString>, Vec<slint::SharedString>) =
+                            targets
+                                .into_iter()
+                                .map(|(id, name)| (id.into(), name.into()))
+                                .unzip();
+                        let library = ui.global::<TargetLibrary>();
+                        library.set_ids(ids.as_slice().into());
+                        library.set_names(names.as_slice().into());
+                    })
+                    .ok();
+                }
+                AppMessage::ShotTimerStarted { at } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let state = ui.global::<ShotTimerState>();
+                        state.set_cue(true);
+                        state.set_status(format!("GO {}", at.format("%H:%M:%S")).into());
+                    })
+                    .ok();
+                }
+                AppMessage::ShotTimerEnded => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let state = ui.global::<ShotTimerState>();
+                        state.set_running(false);
+                        state.set_status("Done".into());
+                    })
+                    .ok();
+                }
+                AppMessage::ShotTimed { timing, .. } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let state = ui.global::<ShotTimerState>();
+                        let mut shots: Vec<ShotInfo> = state.get_shots().iter().collect();
+                        shots.push(ShotInfo {
+                            number: timing.number as i32,
+                            time: format!("{:.2}s", timing.since_start).into(),
+                            split: timing
+                                .split
+                                .map(|v| format!("split {v:.2}s"))
+                                .unwrap_or_default()
+                                .into(),
+                            late: timing.late,
+                        });
+                        state.set_shots(shots.as_slice().into());
+                        if timing.late {
+                            state.set_status(format!("LATE {:.2}s", timing.since_start).into());
+                        } else {
+                            state.set_status(format!("{:.2}s", timing.since_start).into());
+                        }
+                    })
+                    .ok();
+                }
+                AppMessage::LoadedDrills { drills } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let (ids, names): (Vec<slint::SharedString>, Vec<slint::SharedString>) =
+                            drills
+                                .into_iter()
+                                .map(|(id, name)| (id.into(), name.into()))
+                                .unzip();
+                        let state = ui.global::<DrillState>();
+                        state.set_ids(ids.as_slice().into());
+                        state.set_names(names.as_slice().into());
+                    })
+                    .ok();
+                }
+                AppMessage::DrillProgress(progress) => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let state = ui.global::<DrillState>();
+                        let done = progress.string.saturating_sub(1) * progress.shots_per_string
+                            + progress.shots;
+                        let total = (progress.strings * progress.shots_per_string).max(1);
+                        state.set_running(progress.running);
+                        state.set_status(progress.status.into());
+                        state.set_progress_text(
+                            format!(
+                                "{}: string {}/{}, shot {}/{}",
+                                progress.name,
+                                progress.string,
+                                progress.strings,
+                                progress.shots,
+                                progress.shots_per_string
+                            )
+                            .into(),
+                        );
+                        state.set_progress(done as f32 / total as f32);
+                    })
+                    .ok();
+                }
+                AppMessage::DrillFinished { outcome } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let state = ui.global::<DrillState>();
+                        let shots: Vec<DrillShotInfo> = outcome
+                            .strings
+                            .iter()
+                            .enumerate()
+                            .flat_map(|(i, string)| {
+                                string.shots.iter().map(move |shot| DrillShotInfo {
+                                    string: i as i32 + 1,
+                                    number: shot.timing.number as i32,
+                                    time: format!("{:.2}s", shot.timing.since_start).into(),
+                                    zone: shot
+                                        .zone
+                                        .map(|z| format!("zone {z}"))
+                                        .unwrap_or("no zone".to_string())
+                                        .into(),
+                                    points: format!("{}", shot.points).into(),
+                                    counted: shot.counted,
+                                })
+                            })
+                            .collect();
+                        let result = match outcome.scoring {
+                            crate::drills::Scoring::HitFactor => {
+                                format!("Hit factor {:.4}", outcome.result)
+                            }
+                            crate::drills::Scoring::PointsMinusPenalties => {
+                                format!("Score {}", outcome.result)
+                            }
+                        };
+                        state.set_running(false);
+                        state.set_status("Done".into());
+                        state.set_progress(1.0);
+                        state.set_result(
+                            format!(
+                                "{result} ({} points, {} penalties, {:.2}s)",
+                                outcome.points, outcome.penalties, outcome.time
+                            )
+                            .into(),
+                        );
+                        state.set_shots(shots.as_slice().into());
+                    })
+                    .ok();
+                }
+                AppMessage::SessionChanged { session } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        if let Some(session) = &session {
+                            let known = ui.global::<AnalysisState>().get_session_names();
+                            set_heatmap_sessions(
+                                &ui,
+                                known
+                                    .iter()
+                                    .skip(1)
+                                    .map(|id| id.to_string())
+                                    .chain([session.id.clone()]),
+                            );
+                        }
+                        let state = ui.global::<SessionState>();
+                        state.set_active(session.is_some());
+                        state.set_title(match session {
+                            Some(session) => {
+                                format!("Session {} - {}", session.id, session.shooter).into()
+                            }
+                            None => "No session".into(),
+                        });
+                    })
+                    .ok();
+                }
+                AppMessage::LoadedCameras { cameras } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let cameras: Vec<slint::SharedString> =
+                            cameras.into_iter().map(Into::into).collect();
+                        ui.global::<LaneState>()
+                            .set_cameras(cameras.as_slice().into());
+                    })
+                    .ok();
+                }
+                AppMessage::LanesChanged { lanes, current } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let lanes: Vec<LaneInfo> = lanes
+                            .into_iter()
+                            .map(|config| {
+                                let (start_x, start_y, end_x, end_y) = config.stencil.bounds();
+                                LaneInfo {
+                                    name: config.name.into(),
+                                    start_x,
+                                    start_y,
+                                    end_x,
+                                    end_y,
+                                    camera: config.camera.into(),
+                                    target_id: config.target_id.unwrap_or_default().into(),
+                                }
+                            })
+                            .collect();
+                        let names: Vec<slint::SharedString> =
+                            lanes.iter().map(|lane| lane.name.clone()).collect();
+                        let index = lanes
+                            .iter()
+                            .position(|lane| lane.name == current)
+                            .unwrap_or(0);
+
+                        let state = ui.global::<LaneState>();
+                        state.set_lanes(lanes.as_slice().into());
+                        state.set_names(names.as_slice().into());
+                        state.set_current_index(index as i32);
+                        state.set_new_name("".into());
+                        show_lane(&ui, &lanes[index]);
+                    })
+                    .ok();
+                }
+                AppMessage::HitsExported { path, count } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        ui.global::<HitManagerState>()
+                            .set_export_status(match path {
+                                Some(path) => {
+                                    format!("Exported {count} hits to {}", path.display()).into()
+                                }
+                                None => "Export failed".into(),
+                            });
+                    })
+                    .ok();
+                }
+                AppMessage::ReportExported { path } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        ui.global::<HitManagerState>()
+                            .set_export_status(match path {
+                                Some(path) => format!("Saved report to {}", path.display()).into(),
+                                None => "Report failed".into(),
+                            });
+                    })
+                    .ok();
+                }
+                AppMessage::ArchiveImported { path, count } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        ui.global::<HitManagerState>()
+                            .set_export_status(match count {
+                                Some(count) => {
+                                    format!("Imported {count} hits from {}", path.display()).into()
+                                }
+                                None => format!("Failed to import {}", path.display()).into(),
+                            });
+                    })
+                    .ok();
+                }
+                AppMessage::HeatmapReady { image, exported } => {
+                    let ui = ui_weak.clone();
+                    slint::invoke_from_event_loop(move || {
+                        let ui = ui.upgrade().unwrap();
+                        let buffer = slint::SharedPixelBuffer::<slint::Rgb8Pixel>::clone_from_slice(
+                            image.as_raw(),
+                            image.width(),
+                            image.height(),
+                        );
+                        let state = ui.global::<AnalysisState>();
+                        state.set_heatmap(slint::Image::from_rgb8(buffer));
+                        state.set_status(match exported {
+                            Some(path) => format!("Exported to {}", path.display()).into(),
+                            None => "".into(),
+                        });
+                    })
+                    .ok();
+                }
             }
         }
     });
@@ -151,13 +620,482 @@ fn main() {
     // UI stencil change handler
     {
         let bus_tx = bus_tx.clone();
+        let ui_weak = ui.as_weak();
         ui.global::<TargetStencil>()
             .on_change(move |start_x, start_y, end_x, end_y| {
                 info!("changed {start_x}, {start_y}, {end_x}, {end_y}");
+                let ui = ui_weak.upgrade().unwrap();
+                let Some(lane) = update_current_lane(&ui, |lane| {
+                    (lane.start_x, lane.start_y) = (start_x, start_y);
+                    (lane.end_x, lane.end_y) = (end_x, end_y);
+                }) else {
+                    return;
+                };
                 bus_tx
-                    .send(crate::bus::AppCommand::NewStencil((
-                        start_x, start_y, end_x, end_y,
-                    )))
+                    .send(crate::bus::AppCommand::NewStencil {
+                        lane: lane.name.to_string(),
+                        stencil: (start_x, start_y, end_x, end_y),
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.global::<TargetLibrary>().on_select(move |id| {
+            let ui = ui_weak.upgrade().unwrap();
+            let Some(lane) = update_current_lane(&ui, |lane| lane.target_id = id.clone()) else {
+                return;
+            };
+            bus_tx
+                .send(crate::bus::AppCommand::SelectTarget {
+                    lane: lane.name.to_string(),
+                    id: Some(id.to_string()),
+                    reply: None,
+                })
+                .unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.global::<LaneState>().on_select(move || {
+            let ui = ui_weak.upgrade().unwrap();
+            let state = ui.global::<LaneState>();
+            let Some(lane) = state
+                .get_lanes()
+                .row_data(state.get_current_index().max(0) as usize)
+            else {
+                return;
+            };
+            show_lane(&ui, &lane);
+            bus_tx
+                .send(crate::bus::AppCommand::SelectLane {
+                    name: lane.name.to_string(),
+                })
+                .unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.global::<LaneState>().on_change_camera(move || {
+            let ui = ui_weak.upgrade().unwrap();
+            let state = ui.global::<LaneState>();
+            let Some(camera) = state
+                .get_cameras()
+                .row_data(state.get_camera_index().max(0) as usize)
+            else {
+                return;
+            };
+            let Some(lane) = update_current_lane(&ui, |lane| lane.camera = camera.clone()) else {
+                return;
+            };
+            show_lane(&ui, &lane);
+            bus_tx
+                .send(crate::bus::AppCommand::SetLaneCamera {
+                    lane: lane.name.to_string(),
+                    camera: camera.to_string(),
+                })
+                .unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.global::<LaneState>().on_add(move || {
+            let ui = ui_weak.upgrade().unwrap();
+            let name = ui.global::<LaneState>().get_new_name().trim().to_string();
+            if name.is_empty() {
+                return;
+            }
+            bus_tx
+                .send(crate::bus::AppCommand::AddLane { name })
+                .unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.global::<LaneState>().on_remove(move || {
+            let ui = ui_weak.upgrade().unwrap();
+            let name = ui.global::<LaneState>().get_current().to_string();
+            bus_tx
+                .send(crate::bus::AppCommand::RemoveLane { name })
+                .unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.global::<ShotTimerState>().on_start(move || {
+            let ui = ui_weak.upgrade().unwrap();
+            let state = ui.global::<ShotTimerState>();
+            let parse_secs = |text: slint::SharedString| {
+                text.trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|v| *v >= 0.0)
+                    .map(std::time::Duration::from_secs_f32)
+            };
+            let (Some(min_delay), Some(max_delay)) = (
+                parse_secs(state.get_min_delay()),
+                parse_secs(state.get_max_delay()),
+            ) else {
+                state.set_status("Invalid delay".into());
+                return;
+            };
+            let par = parse_secs(state.get_par());
+
+            state.set_shots(slint::ModelRc::default());
+            state.set_status("Standby".into());
+            state.set_running(true);
+            bus_tx
+                .send(crate::bus::AppCommand::StartShotTimer {
+                    config: ShotTimerConfig {
+                        min_delay,
+                        max_delay,
+                        par,
+                    },
+                    reply: None,
+                })
+                .unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.global::<ShotTimerState>().on_stop(move || {
+            let ui = ui_weak.upgrade().unwrap();
+            let state = ui.global::<ShotTimerState>();
+            state.set_running(false);
+            state.set_status("Idle".into());
+            bus_tx
+                .send(crate::bus::AppCommand::StopShotTimer { reply: None })
+                .unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.global::<DrillState>().on_start(move || {
+            let ui = ui_weak.upgrade().unwrap();
+            let state = ui.global::<DrillState>();
+            let Some(id) = state
+                .get_ids()
+                .row_data(state.get_current_index().max(0) as usize)
+            else {
+                state.set_status("No drill selected".into());
+                return;
+            };
+            state.set_result("".into());
+            state.set_shots(slint::ModelRc::default());
+            bus_tx
+                .send(crate::bus::AppCommand::StartDrill {
+                    id: id.to_string(),
+                    reply: None,
+                })
+                .unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<DrillState>().on_abort(move || {
+            bus_tx
+                .send(crate::bus::AppCommand::AbortDrill { reply: None })
+                .unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        let ui_weak = ui.as_weak();
+        ui.global::<SessionState>().on_start(move || {
+            let ui = ui_weak.upgrade().unwrap();
+            let shooter = ui.global::<SessionState>().get_shooter().trim().to_string();
+            bus_tx
+                .send(crate::bus::AppCommand::StartSession {
+                    shooter,
+                    reply: None,
+                })
+                .unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<SessionState>().on_end(move || {
+            bus_tx
+                .send(crate::bus::AppCommand::EndSession { reply: None })
+                .unwrap();
+        });
+    }
+
+    for export in [false, true] {
+        let bus_tx = bus_tx.clone();
+        let ui_weak = ui.as_weak();
+        let request_heatmap = move || {
+            let ui = ui_weak.upgrade().unwrap();
+            let state = ui.global::<AnalysisState>();
+            let Some(target_id) = ui
+                .global::<TargetLibrary>()
+                .get_ids()
+                .row_data(state.get_target_index().max(0) as usize)
+            else {
+                state.set_status("Select target first".into());
+                return;
+            };
+            let (Ok(from), Ok(to)) = (
+                parse_ui_date(&state.get_date_from(), false),
+                parse_ui_date(&state.get_date_to(), true),
+            ) else {
+                state.set_status(format!("Dates must be {DATE_UI_FORMAT}").into());
+                return;
+            };
+            let session = match state.get_session_index() {
+                index if index > 0 => state
+                    .get_session_names()
+                    .row_data(index as usize)
+                    .map(|id| id.to_string()),
+                _ => None,
+            };
+            state.set_status("Rendering...".into());
+            bus_tx
+                .send(crate::bus::AppCommand::RequestHeatmap {
+                    request: HeatmapRequest {
+                        target_id: target_id.to_string(),
+                        session,
+                        from,
+                        to,
+                        heat: state.get_show_heat(),
+                        scatter: state.get_show_scatter(),
+                    },
+                    export,
+                })
+                .unwrap();
+        };
+        if export {
+            ui.global::<AnalysisState>().on_export(request_heatmap);
+        } else {
+            ui.global::<AnalysisState>().on_render(request_heatmap);
+        }
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_review_recording(move |timestamp, lane| {
+                bus_tx
+                    .send(crate::bus::AppCommand::ReviewRecording {
+                        timestamp: parse_ui_timestamp(&timestamp),
+                        lane: lane.to_string(),
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_delete_hit(move |timestamp| {
+                bus_tx
+                    .send(crate::bus::AppCommand::DeleteHit {
+                        timestamp: parse_ui_timestamp(&timestamp),
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>().on_undo_delete(move || {
+            bus_tx.send(crate::bus::AppCommand::UndoDeleteHit).unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>().on_purge_deleted(move || {
+            bus_tx
+                .send(crate::bus::AppCommand::PurgeDeletedHits)
+                .unwrap();
+        });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_annotate_hit(move |timestamp, notes, tags, flyer| {
+                let tags = tags
+                    .split(',')
+                    .map(|tag| tag.trim().to_string())
+                    .filter(|tag| !tag.is_empty())
+                    .collect();
+                bus_tx
+                    .send(crate::bus::AppCommand::AnnotateHit {
+                        timestamp: parse_ui_timestamp(&timestamp),
+                        notes: notes.to_string(),
+                        tags,
+                        flyer,
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_correct_hit(move |timestamp, x, y| {
+                if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+                    return;
+                }
+                bus_tx
+                    .send(crate::bus::AppCommand::CorrectHit {
+                        timestamp: parse_ui_timestamp(&timestamp),
+                        pos: imageproc::point::Point::new(x, y),
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_reprocess_hit(move |timestamp| {
+                bus_tx
+                    .send(crate::bus::AppCommand::Reprocess {
+                        scope: ReprocessScope::Hit(parse_ui_timestamp(&timestamp)),
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_reprocess_session(move |session| {
+                bus_tx
+                    .send(crate::bus::AppCommand::Reprocess {
+                        scope: ReprocessScope::Session(session.to_string()),
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_reprocess_all(move |outdated_only| {
+                let scope = if outdated_only {
+                    ReprocessScope::Outdated
+                } else {
+                    ReprocessScope::All
+                };
+                bus_tx
+                    .send(crate::bus::AppCommand::Reprocess { scope })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_export_hits(move |lane, session, json| {
+                let query = HitQuery {
+                    lane: (!lane.is_empty()).then(|| lane.to_string()),
+                    session: (!session.is_empty()).then(|| session.to_string()),
+                    ..Default::default()
+                };
+                let format = if json {
+                    ExportFormat::Json
+                } else {
+                    ExportFormat::Csv
+                };
+                bus_tx
+                    .send(crate::bus::AppCommand::ExportHits {
+                        queries: vec![query],
+                        format,
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_export_archive(move |lane, session| {
+                let query = HitQuery {
+                    lane: (!lane.is_empty()).then(|| lane.to_string()),
+                    session: (!session.is_empty()).then(|| session.to_string()),
+                    ..Default::default()
+                };
+                bus_tx
+                    .send(crate::bus::AppCommand::ExportArchive {
+                        queries: vec![query],
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_import_archive(move |path| {
+                bus_tx
+                    .send(crate::bus::AppCommand::ImportArchive {
+                        path: std::path::PathBuf::from(path.as_str()),
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_export_report(move |session, pdf| {
+                let format = if pdf {
+                    ReportFormat::Pdf
+                } else {
+                    ReportFormat::Svg
+                };
+                bus_tx
+                    .send(crate::bus::AppCommand::ExportReport {
+                        session: session.to_string(),
+                        format,
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_request_frame(move |index| {
+                bus_tx
+                    .send(crate::bus::AppCommand::RequestClipFrame {
+                        index: index.max(0) as usize,
+                    })
+                    .unwrap();
+            });
+    }
+
+    {
+        let bus_tx = bus_tx.clone();
+        ui.global::<HitManagerState>()
+            .on_request_thumbnail(move |timestamp| {
+                bus_tx
+                    .send(crate::bus::AppCommand::RequestThumbnail {
+                        timestamp: parse_ui_timestamp(&timestamp),
+                    })
                     .unwrap();
             });
     }
@@ -166,17 +1104,142 @@ fn main() {
         .on_request_hit_clip(move |timestamp| {
             bus_tx
                 .send(crate::bus::AppCommand::RequestHitClip {
-                    timestamp: chrono::NaiveDateTime::parse_from_str(
-                        &timestamp,
-                        TIMESTAMP_UI_FORMAT,
-                    )
-                    .expect("failed to parse timestamp")
-                    .and_local_timezone(Local)
-                    .single()
-                    .expect("failed to convert to local timezone"),
+                    timestamp: parse_ui_timestamp(&timestamp),
                 })
                 .unwrap();
         });
 
     ui.run().unwrap();
 }
+
+/// Frame a hit at `at` was detected on, none if clip does not cover it
+fn impact_frame(frame_times: &[DateTime<Local>], at: DateTime<Local>) -> Option<i32> {
+    if frame_times.first().is_none_or(|first| at < *first) {
+        return None;
+    }
+    let index = frame_times.partition_point(|t| *t < at);
+    (index < frame_times.len()).then_some(index as i32)
+}
+
+fn slint_image(image: &image::RgbImage) -> slint::Image {
+    slint::Image::from_rgb8(
+        slint::SharedPixelBuffer::<slint::Rgb8Pixel>::clone_from_slice(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+        ),
+    )
+}
+
+/// Offer sessions of ids in heatmap filter, newest first, keeping the selected one
+fn set_heatmap_sessions(ui: &MainWindow, ids: impl Iterator<Item = String>) {
+    let state = ui.global::<AnalysisState>();
+    let selected = state
+        .get_session_names()
+        .row_data(state.get_session_index().max(0) as usize)
+        .filter(|_| state.get_session_index() > 0);
+    let mut ids: Vec<String> = ids.filter(|id| !id.is_empty()).collect();
+    ids.sort_unstable_by(|a, b| b.cmp(a));
+    ids.dedup();
+    let names: Vec<slint::SharedString> = std::iter::once("All sessions".to_string())
+        .chain(ids)
+        .map(Into::into)
+        .collect();
+    let index = selected
+        .and_then(|selected| names.iter().position(|name| *name == selected))
+        .unwrap_or(0);
+    state.set_session_names(names.as_slice().into());
+    state.set_session_index(index as i32);
+}
+
+fn hit_info(timestamp: DateTime<Local>, data: crate::hits::storage::HitData) -> HitInfo {
+    let pos = data
+        .processed
+        .as_ref()
+        .and_then(|p| p.hit_pos_norm.as_ref());
+    HitInfo {
+        timestamp: timestamp.format(TIMESTAMP_UI_FORMAT).to_string().into(),
+        is_processed: data.processed.is_some(),
+        processed: HitProcessResult {
+            score: data.processed.as_ref().map_or(0.0, |p| p.score),
+        },
+        lane: data.lane.unwrap_or_default().into(),
+        session: data.session.unwrap_or_default().into(),
+        notes: data.notes.into(),
+        tags: data.tags.join(", ").into(),
+        flyer: data.flyer,
+        has_pos: pos.is_some(),
+        pos_x: pos.map_or(0.0, |p| p.x),
+        pos_y: pos.map_or(0.0, |p| p.y),
+        ..Default::default()
+    }
+}
+
+/// Parse hit timestamp as shown in UI
+fn parse_ui_timestamp(text: &str) -> DateTime<Local> {
+    chrono::NaiveDateTime::parse_from_str(text, TIMESTAMP_UI_FORMAT)
+        .expect("failed to parse timestamp")
+        .and_local_timezone(Local)
+        .single()
+        .expect("failed to convert to local timezone")
+}
+
+/// Make lane current in UI, showing its camera, stencil and target
+fn show_lane(ui: &MainWindow, lane: &LaneInfo) {
+    let state = ui.global::<LaneState>();
+    state.set_current(lane.name.clone());
+    state.set_camera(lane.camera.clone());
+    let camera_index = state
+        .get_cameras()
+        .iter()
+        .position(|camera| camera == lane.camera)
+        .unwrap_or(0);
+    state.set_camera_index(camera_index as i32);
+
+    let stencil = ui.global::<TargetStencil>();
+    stencil.set_start_x(lane.start_x);
+    stencil.set_start_y(lane.start_y);
+    stencil.set_end_x(lane.end_x);
+    stencil.set_end_y(lane.end_y);
+
+    let library = ui.global::<TargetLibrary>();
+    let target_index = library
+        .get_ids()
+        .iter()
+        .position(|id| id == lane.target_id)
+        .map(|i| i as i32)
+        .unwrap_or(-1);
+    library.set_current_index(target_index);
+}
+
+/// Apply change to current lane info, returning updated lane
+fn update_current_lane(ui: &MainWindow, change: impl FnOnce(&mut LaneInfo)) -> Option<LaneInfo> {
+    let state = ui.global::<LaneState>();
+    let lanes = state.get_lanes();
+    let index = state.get_current_index().max(0) as usize;
+    let mut lane = lanes.row_data(index)?;
+    change(&mut lane);
+    lanes.set_row_data(index, lane.clone());
+    Some(lane)
+}
+
+/// Parse optional UI date as start or end of that local day
+fn parse_ui_date(text: &str, end_of_day: bool) -> Result<Option<DateTime<Local>>, ()> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    let date = NaiveDate::parse_from_str(text, DATE_UI_FORMAT).map_err(|_| ())?;
+    let time = if end_of_day {
+        date.and_hms_milli_opt(23, 59, 59, 999)
+    } else {
+        date.and_hms_opt(0, 0, 0)
+    }
+    .ok_or(())?;
+
+    time.and_local_timezone(Local)
+        .earliest()
+        .map(Some)
+        .ok_or(())
+}
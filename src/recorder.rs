@@ -1,27 +1,73 @@
 use std::{
     collections::VecDeque,
     sync::{Arc, Mutex},
-    time::Duration,
 };
 
-use chrono::{Local, TimeDelta};
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
 use tracing::error;
 
 use crate::capturer::CapturedFrame;
 
-const FRAME_EXPIRE: TimeDelta = TimeDelta::seconds(1);
+/// Upper bound of buffered frames regardless of memory budget
+const MAX_FRAMES: usize = 600;
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct RecorderConfig {
+    /// Seconds of video kept before the shot
+    pub pre_roll: f32,
+    /// Seconds of video kept after the laser is gone
+    pub post_roll: f32,
+    /// Memory cap for buffered frames of one lane in megabytes
+    pub max_memory_mb: u32,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            pre_roll: 1.0,
+            post_roll: 0.5,
+            max_memory_mb: 256,
+        }
+    }
+}
+
+impl RecorderConfig {
+    pub fn pre_roll(&self) -> TimeDelta {
+        TimeDelta::milliseconds((self.pre_roll.max(0.0) * 1000.0) as i64)
+    }
+
+    pub fn post_roll(&self) -> TimeDelta {
+        TimeDelta::milliseconds((self.post_roll.max(0.0) * 1000.0) as i64)
+    }
+}
+
+/// Ring buffer of recent frames, bounded by frame count and memory budget
 pub struct Recorder {
+    config: RecorderConfig,
     frames: Mutex<VecDeque<Arc<CapturedFrame>>>,
 }
 
 impl Recorder {
-    pub fn new() -> Self {
+    pub fn new(config: RecorderConfig) -> Self {
         Self {
+            config,
             frames: Mutex::new(VecDeque::new()),
         }
     }
 
+    pub fn config(&self) -> &RecorderConfig {
+        &self.config
+    }
+
+    /// How many frames of this size fit into memory budget
+    fn capacity(&self, frame: &CapturedFrame) -> usize {
+        let frame_bytes = frame.image.as_raw().len().max(1);
+        let budget = self.config.max_memory_mb as usize * 1024 * 1024;
+        (budget / frame_bytes).clamp(1, MAX_FRAMES)
+    }
+
     pub fn push_frame(&self, frame: Arc<CapturedFrame>) {
         let mut frames = match self.frames.lock() {
             Ok(v) => v,
@@ -31,9 +77,10 @@ impl Recorder {
             }
         };
 
-        let now = Local::now();
-
-        frames.retain(|c| (now - c.timestamp) < FRAME_EXPIRE);
+        let capacity = self.capacity(&frame);
+        while frames.len() >= capacity {
+            frames.pop_front();
+        }
 
         frames.push_back(frame);
     }
@@ -50,7 +97,25 @@ impl Recorder {
         frames.back().cloned()
     }
 
-    pub fn frames(&self) -> Vec<Arc<CapturedFrame>> {
-        self.frames.lock().unwrap().iter().cloned().collect()
+    /// Buffered frames captured within `from..=to`
+    pub fn snapshot(&self, from: DateTime<Local>, to: DateTime<Local>) -> Vec<Arc<CapturedFrame>> {
+        let frames = match self.frames.lock() {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to lock frames queue to take snapshot: {e:?}");
+                return Vec::new();
+            }
+        };
+
+        frames
+            .iter()
+            .filter(|f| f.timestamp >= from && f.timestamp <= to)
+            .cloned()
+            .collect()
+    }
+
+    /// Buffered frames from pre-roll before to post-roll after `at`
+    pub fn snapshot_around(&self, at: DateTime<Local>) -> Vec<Arc<CapturedFrame>> {
+        self.snapshot(at - self.config.pre_roll(), at + self.config.post_roll())
     }
 }
@@ -0,0 +1,332 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    fs,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        mpsc::{self, Sender},
+    },
+};
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Local, NaiveDateTime, TimeDelta, TimeZone};
+use image::imageops::{FilterType, resize};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::{
+    bus::Event,
+    capturer::CapturedFrame,
+    coding::{
+        Clip,
+        mjpeg::{ClipWriter, load_range},
+    },
+    lanes::LaneConfig,
+};
+
+const RECORDINGS_PATH: &str = "data/recordings";
+const BOOKMARKS_FILE: &str = "bookmarks.json";
+const SEGMENT_NAME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S%.3f";
+const SEGMENT_EXT: &str = "lsc";
+/// How much of the recording around a hit is loaded for review
+const REVIEW_BEFORE: TimeDelta = TimeDelta::seconds(5);
+const REVIEW_AFTER: TimeDelta = TimeDelta::seconds(5);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingSource {
+    /// Whole camera frames, one stream per camera
+    #[default]
+    Camera,
+    /// Cut out target frames, one stream per lane
+    Target,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct RecordingConfig {
+    /// Record sessions continuously to disk
+    pub enabled: bool,
+    pub source: RecordingSource,
+    /// Length of one segment file in seconds
+    pub segment_secs: u32,
+    pub max_fps: f32,
+    /// Frames wider than this are scaled down
+    pub max_width: u32,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source: RecordingSource::Camera,
+            segment_secs: 60,
+            max_fps: 10.0,
+            max_width: 960,
+        }
+    }
+}
+
+impl RecordingSource {
+    /// Recorded stream showing lane target
+    pub fn stream(&self, lane: &LaneConfig) -> String {
+        match self {
+            RecordingSource::Camera => lane.camera.clone(),
+            RecordingSource::Target => lane.name.clone(),
+        }
+    }
+}
+
+/// Hit position in session recording
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Bookmark {
+    pub timestamp: DateTime<Local>,
+    pub lane: String,
+    pub stream: String,
+}
+
+pub enum SessionRecorderCommand {
+    Start {
+        session_id: String,
+    },
+    Stop,
+    Frame {
+        stream: String,
+        frame: Arc<CapturedFrame>,
+    },
+    Bookmark(Bookmark),
+    /// Load recording of stream around timestamp for review
+    Review {
+        timestamp: DateTime<Local>,
+        stream: String,
+    },
+}
+
+struct Segment {
+    writer: ClipWriter,
+    started_at: DateTime<Local>,
+    last_frame_at: DateTime<Local>,
+}
+
+struct Recording {
+    dir: PathBuf,
+    segments: HashMap<String, Segment>,
+    bookmarks: Vec<Bookmark>,
+}
+
+impl Recording {
+    fn start(session_id: &str) -> Self {
+        let dir = Path::new(RECORDINGS_PATH).join(session_id);
+        // Keep bookmarks when session recording is resumed
+        let bookmarks = fs::File::open(dir.join(BOOKMARKS_FILE))
+            .ok()
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok())
+            .unwrap_or_default();
+        Self {
+            dir,
+            segments: HashMap::new(),
+            bookmarks,
+        }
+    }
+
+    fn write_frame(
+        &mut self,
+        config: &RecordingConfig,
+        stream: &str,
+        frame: &CapturedFrame,
+    ) -> Result<()> {
+        let min_interval = TimeDelta::milliseconds((1000.0 / config.max_fps.max(0.1)) as i64);
+        let segment_len = TimeDelta::seconds(config.segment_secs.max(1) as i64);
+
+        if let Some(segment) = self.segments.get(stream) {
+            if frame.timestamp - segment.last_frame_at < min_interval {
+                return Ok(());
+            }
+            if frame.timestamp - segment.started_at >= segment_len {
+                let segment = self.segments.remove(stream).unwrap();
+                segment.writer.finish()?;
+            }
+        }
+
+        let segment = match self.segments.entry(stream.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let dir = self.dir.join(stream_dir_name(stream));
+                fs::create_dir_all(&dir)?;
+                let name = frame.timestamp.format(SEGMENT_NAME_FORMAT);
+                let path = dir.join(format!("{name}.{SEGMENT_EXT}"));
+                info!("Recording segment {path:?}");
+                entry.insert(Segment {
+                    writer: ClipWriter::create(&path)?,
+                    started_at: frame.timestamp,
+                    last_frame_at: frame.timestamp,
+                })
+            }
+        };
+
+        if frame.image.width() > config.max_width {
+            let height = frame.image.height() * config.max_width / frame.image.width();
+            let image = resize(&frame.image, config.max_width, height, FilterType::Triangle);
+            segment.writer.write_frame(&image, frame.timestamp)?;
+        } else {
+            segment.writer.write_frame(&frame.image, frame.timestamp)?;
+        }
+        segment.last_frame_at = frame.timestamp;
+        Ok(())
+    }
+
+    fn bookmark(&mut self, bookmark: Bookmark) -> Result<()> {
+        self.bookmarks.push(bookmark);
+        fs::create_dir_all(&self.dir)?;
+        let f = fs::File::create(self.dir.join(BOOKMARKS_FILE))?;
+        serde_json::to_writer_pretty(BufWriter::new(f), &self.bookmarks)?;
+        Ok(())
+    }
+
+    fn finish(self) {
+        for (stream, segment) in self.segments {
+            if let Err(e) = segment.writer.finish() {
+                error!("failed to finish recording of '{stream}': {e:?}");
+            }
+        }
+    }
+}
+
+/// Lane and camera names may contain characters not allowed in file names
+fn stream_dir_name(stream: &str) -> String {
+    stream
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Segments of stream in any session overlapping `from..=to`, oldest first
+fn find_segments(stream: &str, from: DateTime<Local>, to: DateTime<Local>) -> Result<Vec<PathBuf>> {
+    let base = Path::new(RECORDINGS_PATH);
+    if !base.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut segments: Vec<(DateTime<Local>, PathBuf)> = Vec::new();
+    for session in fs::read_dir(base)? {
+        let dir = session?.path().join(stream_dir_name(stream));
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let started_at = path
+                .file_stem()
+                .and_then(|name| {
+                    NaiveDateTime::parse_from_str(&name.to_string_lossy(), SEGMENT_NAME_FORMAT).ok()
+                })
+                .and_then(|naive| Local.from_local_datetime(&naive).single());
+            if let Some(started_at) = started_at {
+                segments.push((started_at, path));
+            }
+        }
+    }
+    segments.sort_by_key(|(started_at, _)| *started_at);
+
+    // Segment lasts until next one starts
+    let ends: Vec<Option<DateTime<Local>>> = segments
+        .iter()
+        .skip(1)
+        .map(|(started_at, _)| Some(*started_at))
+        .chain([None])
+        .collect();
+    Ok(segments
+        .into_iter()
+        .zip(ends)
+        .filter(|((started_at, _), end)| *started_at <= to && end.is_none_or(|end| end > from))
+        .map(|((_, path), _)| path)
+        .collect())
+}
+
+fn load_review(stream: &str, timestamp: DateTime<Local>) -> Result<Clip> {
+    let (from, to) = (timestamp - REVIEW_BEFORE, timestamp + REVIEW_AFTER);
+    let segments = find_segments(stream, from, to)?;
+    if segments.is_empty() {
+        bail!("no recording of '{stream}' at {timestamp}");
+    }
+
+    let mut clip = Clip::default();
+    for path in segments {
+        let part = load_range(&path, from, to)?;
+        clip.frames.extend(part.frames);
+        clip.timestamps.extend(part.timestamps);
+    }
+    Ok(clip)
+}
+
+pub fn start_session_recorder(
+    bus_tx: Sender<Event>,
+    config: RecordingConfig,
+) -> Sender<SessionRecorderCommand> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut recording: Option<Recording> = None;
+        for msg in rx {
+            match msg {
+                SessionRecorderCommand::Start { session_id } => {
+                    if let Some(recording) = recording.take() {
+                        recording.finish();
+                    }
+                    if config.enabled {
+                        info!("Recording session {session_id}");
+                        recording = Some(Recording::start(&session_id));
+                    }
+                }
+                SessionRecorderCommand::Stop => {
+                    if let Some(recording) = recording.take() {
+                        recording.finish();
+                    }
+                }
+                SessionRecorderCommand::Frame { stream, frame } => {
+                    let Some(recording) = &mut recording else {
+                        continue;
+                    };
+                    if let Err(e) = recording.write_frame(&config, &stream, &frame) {
+                        error!("failed to record frame of '{stream}': {e:?}");
+                        // Start new segment with next frame
+                        if let Some(segment) = recording.segments.remove(&stream) {
+                            let _ = segment.writer.finish();
+                        }
+                    }
+                }
+                SessionRecorderCommand::Bookmark(bookmark) => {
+                    let Some(recording) = &mut recording else {
+                        continue;
+                    };
+                    if let Err(e) = recording.bookmark(bookmark) {
+                        error!("failed to save recording bookmark: {e:?}");
+                    }
+                }
+                SessionRecorderCommand::Review { timestamp, stream } => {
+                    let clip = match load_review(&stream, timestamp) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("failed to load recording for review: {e:?}");
+                            continue;
+                        }
+                    };
+                    bus_tx
+                        .send(Event::RecordingReview { timestamp, clip })
+                        .expect("failed to send recording review");
+                }
+            }
+        }
+
+        if let Some(recording) = recording.take() {
+            recording.finish();
+        }
+    });
+    tx
+}
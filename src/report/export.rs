@@ -0,0 +1,168 @@
+use std::{collections::HashMap, io::Write};
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::hits::storage::{HitData, HitQuery, HitStorage};
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// One exported hit, field order is the CSV column order and must stay stable
+#[derive(Serialize)]
+pub struct HitRow {
+    pub timestamp: DateTime<Local>,
+    pub session: Option<String>,
+    pub shooter: Option<String>,
+    pub target_id: Option<String>,
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    /// Position relative to clip frame size (0..1)
+    pub x_norm: Option<f32>,
+    pub y_norm: Option<f32>,
+    pub zone: Option<u8>,
+    pub score: Option<f32>,
+    pub processor_version: Option<u32>,
+}
+
+const CSV_HEADER: [&str; 11] = [
+    "timestamp",
+    "session",
+    "shooter",
+    "target_id",
+    "x",
+    "y",
+    "x_norm",
+    "y_norm",
+    "zone",
+    "score",
+    "processor_version",
+];
+
+impl HitRow {
+    pub fn new(timestamp: DateTime<Local>, hit: &HitData, shooter: Option<String>) -> Self {
+        let processed = hit.processed.as_ref();
+        let pos = processed.and_then(|p| p.hit_pos.as_ref());
+        let pos_norm = processed.and_then(|p| p.hit_pos_norm.as_ref());
+        Self {
+            timestamp,
+            session: hit.session.clone(),
+            shooter,
+            target_id: hit.target_id.clone(),
+            x: pos.map(|p| p.x),
+            y: pos.map(|p| p.y),
+            x_norm: pos_norm.map(|p| p.x),
+            y_norm: pos_norm.map(|p| p.y),
+            zone: processed.and_then(|p| p.zone),
+            score: processed.map(|p| p.score),
+            processor_version: processed.map(|p| p.processor_version),
+        }
+    }
+
+    fn csv_fields(&self) -> [String; 11] {
+        fn opt<T: ToString>(v: &Option<T>) -> String {
+            v.as_ref().map(T::to_string).unwrap_or_default()
+        }
+        [
+            self.timestamp.to_rfc3339(),
+            opt(&self.session),
+            opt(&self.shooter),
+            opt(&self.target_id),
+            opt(&self.x),
+            opt(&self.y),
+            opt(&self.x_norm),
+            opt(&self.y_norm),
+            opt(&self.zone),
+            opt(&self.score),
+            opt(&self.processor_version),
+        ]
+    }
+}
+
+/// Rows of hits matching any of the queries, oldest first
+pub fn collect_rows(storage: &mut dyn HitStorage, queries: &[HitQuery]) -> Result<Vec<HitRow>> {
+    let shooters: HashMap<String, String> = storage
+        .get_all_sessions()?
+        .into_iter()
+        .map(|s| (s.id, s.shooter))
+        .collect();
+
+    let mut hits = HashMap::new();
+    for query in queries {
+        hits.extend(storage.query_hits(query)?);
+    }
+    let mut hits: Vec<_> = hits.into_iter().collect();
+    hits.sort_by_key(|(timestamp, _)| *timestamp);
+
+    Ok(hits
+        .into_iter()
+        .map(|(timestamp, hit)| {
+            let shooter = hit
+                .session
+                .as_ref()
+                .and_then(|id| shooters.get(id))
+                .cloned();
+            HitRow::new(timestamp, &hit, shooter)
+        })
+        .collect())
+}
+
+pub fn write_rows(rows: &[HitRow], format: ExportFormat, mut w: impl Write) -> Result<()> {
+    match format {
+        ExportFormat::Json => serde_json::to_writer_pretty(&mut w, rows)?,
+        ExportFormat::Csv => {
+            writeln!(w, "{}", CSV_HEADER.join(","))?;
+            for row in rows {
+                let fields = row.csv_fields().map(|v| csv_escape(&v));
+                writeln!(w, "{}", fields.join(","))?;
+            }
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Quote field if it contains separator, quote or line break
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_escape_leaves_plain_values() {
+        assert_eq!(csv_escape("Lane 1"), "Lane 1");
+        assert_eq!(csv_escape(""), "");
+    }
+
+    #[test]
+    fn csv_escape_quotes_separators_and_line_breaks() {
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_escape("cr\r"), "\"cr\r\"");
+    }
+
+    #[test]
+    fn csv_escape_doubles_quotes() {
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
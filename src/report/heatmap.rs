@@ -0,0 +1,138 @@
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use image::{Rgb, RgbImage};
+use imageproc::{drawing::draw_filled_circle_mut, point::Point};
+
+use crate::hits::storage::{HitQuery, HitStorage};
+
+/// Heat spot radius relative to the smaller image side
+const HEAT_RADIUS: f32 = 0.06;
+/// Heat values below this are not drawn at all
+const HEAT_CUTOFF: f32 = 0.02;
+const HEAT_OPACITY: f32 = 0.75;
+/// Scatter dot radius relative to the smaller image side
+const SCATTER_RADIUS: f32 = 0.01;
+
+#[derive(Clone)]
+pub struct HeatmapRequest {
+    pub target_id: String,
+    /// Only hits of session, all sessions if none
+    pub session: Option<String>,
+    pub from: Option<DateTime<Local>>,
+    pub to: Option<DateTime<Local>>,
+    pub heat: bool,
+    pub scatter: bool,
+}
+
+/// Collect normalized positions of processed hits matching request
+pub fn collect_points(
+    storage: &mut dyn HitStorage,
+    request: &HeatmapRequest,
+) -> Result<Vec<Point<f32>>> {
+    let hits = storage.query_hits(&HitQuery {
+        from: request.from,
+        to: request.to,
+        // Hits without known target can't be placed on its face
+        target_id: Some(request.target_id.clone()),
+        session: request.session.clone(),
+        processed: Some(true),
+        ..Default::default()
+    })?;
+    let points = hits
+        .into_iter()
+        .filter_map(|(_, hit)| hit.processed?.hit_pos_norm)
+        .map(|p| *p)
+        .collect();
+    Ok(points)
+}
+
+/// Render hits of request over target preview image
+pub fn render_heatmap(
+    storage: &mut dyn HitStorage,
+    request: &HeatmapRequest,
+    preview_path: &Path,
+) -> Result<RgbImage> {
+    let preview = image::open(preview_path)?.to_rgb8();
+    let points = collect_points(storage, request)?;
+    Ok(render(&preview, &points, request.heat, request.scatter))
+}
+
+/// Draw heatmap and/or scatter plot of normalized points over image
+pub fn render(preview: &RgbImage, points: &[Point<f32>], heat: bool, scatter: bool) -> RgbImage {
+    let mut out = preview.clone();
+    let (width, height) = out.dimensions();
+    let side = width.min(height) as f32;
+
+    if heat && !points.is_empty() {
+        draw_heat(&mut out, points, HEAT_RADIUS * side);
+    }
+
+    if scatter {
+        let radius = ((SCATTER_RADIUS * side).round() as i32).max(2);
+        for p in points {
+            let center = ((p.x * width as f32) as i32, (p.y * height as f32) as i32);
+            draw_filled_circle_mut(&mut out, center, radius + 1, Rgb([255, 255, 255]));
+            draw_filled_circle_mut(&mut out, center, radius, Rgb([0, 0, 0]));
+        }
+    }
+
+    out
+}
+
+fn draw_heat(img: &mut RgbImage, points: &[Point<f32>], radius: f32) {
+    let (width, height) = img.dimensions();
+    let mut density = vec![0.0f32; (width * height) as usize];
+
+    // Gaussian falloff, cut at radius
+    let sigma = radius / 2.0;
+    let two_sigma_sq = 2.0 * sigma * sigma;
+    for p in points {
+        let (cx, cy) = (p.x * width as f32, p.y * height as f32);
+        let x0 = ((cx - radius).floor() as i32).max(0);
+        let x1 = ((cx + radius).ceil() as i32).min(width as i32 - 1);
+        let y0 = ((cy - radius).floor() as i32).max(0);
+        let y1 = ((cy + radius).ceil() as i32).min(height as i32 - 1);
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let d_sq = (x as f32 - cx).powi(2) + (y as f32 - cy).powi(2);
+                if d_sq <= radius * radius {
+                    density[(y as u32 * width + x as u32) as usize] += (-d_sq / two_sigma_sq).exp();
+                }
+            }
+        }
+    }
+
+    let max = density.iter().copied().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return;
+    }
+
+    for (pixel, value) in img.pixels_mut().zip(density) {
+        let v = value / max;
+        if v < HEAT_CUTOFF {
+            continue;
+        }
+        let color = heat_color(v);
+        let alpha = HEAT_OPACITY * v.sqrt();
+        for c in 0..3 {
+            pixel[c] = (pixel[c] as f32 * (1.0 - alpha) + color[c] * alpha) as u8;
+        }
+    }
+}
+
+/// Map 0..1 to blue -> green -> yellow -> red
+fn heat_color(v: f32) -> [f32; 3] {
+    let v = v.clamp(0.0, 1.0);
+    if v < 0.33 {
+        let t = v / 0.33;
+        [0.0, 255.0 * t, 255.0 * (1.0 - t)]
+    } else if v < 0.66 {
+        let t = (v - 0.33) / 0.33;
+        [255.0 * t, 255.0, 0.0]
+    } else {
+        let t = (v - 0.66) / 0.34;
+        [255.0, 255.0 * (1.0 - t), 0.0]
+    }
+}
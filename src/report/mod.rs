@@ -0,0 +1,7 @@
+pub mod export;
+pub mod heatmap;
+pub mod pdf;
+pub mod sheet;
+pub mod svg;
+
+pub const EXPORTS_PATH: &str = "data/exports";
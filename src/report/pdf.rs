@@ -0,0 +1,224 @@
+use std::{fmt::Write as _, io::Write};
+
+use anyhow::Result;
+
+use crate::report::sheet::{Canvas, PAGE_HEIGHT, PAGE_WIDTH};
+
+/// Control point distance for drawing quarter circle as bezier curve
+const BEZIER_CIRCLE: f32 = 0.552_284_8;
+
+/// Minimal PDF writer with built in Helvetica and JPEG images
+#[derive(Default)]
+pub struct PdfCanvas {
+    /// Content stream of every page
+    pages: Vec<String>,
+    /// JPEG data and pixel size
+    images: Vec<(Vec<u8>, (u32, u32))>,
+}
+
+impl PdfCanvas {
+    fn page(&mut self) -> &mut String {
+        if self.pages.is_empty() {
+            self.pages.push(String::new());
+        }
+        self.pages.last_mut().unwrap()
+    }
+}
+
+/// PDF y axis points up from bottom of page
+fn flip(y: f32) -> f32 {
+    PAGE_HEIGHT - y
+}
+
+fn color([r, g, b]: [u8; 3]) -> String {
+    format!(
+        "{:.3} {:.3} {:.3}",
+        r as f32 / 255.0,
+        g as f32 / 255.0,
+        b as f32 / 255.0
+    )
+}
+
+impl Canvas for PdfCanvas {
+    fn new_page(&mut self) {
+        self.pages.push(String::new());
+    }
+
+    fn text(&mut self, x: f32, y: f32, size: f32, text: &str) {
+        let text = escape(text);
+        writeln!(
+            self.page(),
+            "0 g BT /F1 {size} Tf {x:.2} {:.2} Td ({text}) Tj ET",
+            flip(y)
+        )
+        .unwrap();
+    }
+
+    fn line(&mut self, from: (f32, f32), to: (f32, f32), stroke: [u8; 3]) {
+        writeln!(
+            self.page(),
+            "{} RG {:.2} {:.2} m {:.2} {:.2} l S",
+            color(stroke),
+            from.0,
+            flip(from.1),
+            to.0,
+            flip(to.1)
+        )
+        .unwrap();
+    }
+
+    fn circle(&mut self, center: (f32, f32), radius: f32, fill: [u8; 3]) {
+        let (cx, cy) = (center.0, flip(center.1));
+        let (r, k) = (radius, radius * BEZIER_CIRCLE);
+        let page = self.page();
+        writeln!(page, "{} rg 1 1 1 RG {:.2} {cy:.2} m", color(fill), cx + r).unwrap();
+        for (x0, y0, x1, y1, x, y) in [
+            (r, k, k, r, 0.0, r),
+            (-k, r, -r, k, -r, 0.0),
+            (-r, -k, -k, -r, 0.0, -r),
+            (k, -r, r, -k, r, 0.0),
+        ] {
+            writeln!(
+                page,
+                "{:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c",
+                cx + x0,
+                cy + y0,
+                cx + x1,
+                cy + y1,
+                cx + x,
+                cy + y
+            )
+            .unwrap();
+        }
+        writeln!(page, "B").unwrap();
+    }
+
+    fn image(
+        &mut self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        jpeg: Vec<u8>,
+        pixels: (u32, u32),
+    ) {
+        self.images.push((jpeg, pixels));
+        let name = self.images.len();
+        writeln!(
+            self.page(),
+            "q {width:.2} 0 0 {height:.2} {x:.2} {:.2} cm /Im{name} Do Q",
+            flip(y + height)
+        )
+        .unwrap();
+    }
+
+    fn finish(mut self, w: &mut dyn Write) -> Result<()> {
+        if self.pages.is_empty() {
+            self.pages.push(String::new());
+        }
+
+        // Objects: catalog, page tree, font, images, then page and its content per page
+        let first_image = 4;
+        let first_page = first_image + self.images.len();
+        let page_ids: Vec<_> = (0..self.pages.len()).map(|i| first_page + 2 * i).collect();
+
+        let mut out = Vec::new();
+        let mut offsets = Vec::new();
+        out.extend_from_slice(b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n");
+
+        let mut object = |out: &mut Vec<u8>, dict: String, stream: Option<&[u8]>| {
+            offsets.push(out.len());
+            let id = offsets.len();
+            out.extend_from_slice(format!("{id} 0 obj\n{dict}\n").as_bytes());
+            if let Some(stream) = stream {
+                out.extend_from_slice(b"stream\n");
+                out.extend_from_slice(stream);
+                out.extend_from_slice(b"\nendstream\n");
+            }
+            out.extend_from_slice(b"endobj\n");
+        };
+
+        object(&mut out, "<< /Type /Catalog /Pages 2 0 R >>".into(), None);
+        let kids: Vec<_> = page_ids.iter().map(|id| format!("{id} 0 R")).collect();
+        object(
+            &mut out,
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids.join(" "),
+                page_ids.len()
+            ),
+            None,
+        );
+        object(
+            &mut out,
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+                .into(),
+            None,
+        );
+        for (jpeg, (width, height)) in &self.images {
+            object(
+                &mut out,
+                format!(
+                    "<< /Type /XObject /Subtype /Image /Width {width} /Height {height} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode /Length {} >>",
+                    jpeg.len()
+                ),
+                Some(jpeg),
+            );
+        }
+
+        let images: Vec<_> = (0..self.images.len())
+            .map(|i| format!("/Im{} {} 0 R", i + 1, first_image + i))
+            .collect();
+        let resources = format!(
+            "<< /Font << /F1 3 0 R >> /XObject << {} >> >>",
+            images.join(" ")
+        );
+        for (page, id) in self.pages.iter().zip(&page_ids) {
+            object(
+                &mut out,
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {PAGE_WIDTH} {PAGE_HEIGHT}] /Resources {resources} /Contents {} 0 R >>",
+                    id + 1
+                ),
+                None,
+            );
+            object(
+                &mut out,
+                format!("<< /Length {} >>", page.len()),
+                Some(page.as_bytes()),
+            );
+        }
+
+        let xref = out.len();
+        writeln!(out, "xref\n0 {}", offsets.len() + 1)?;
+        writeln!(out, "0000000000 65535 f ")?;
+        for offset in &offsets {
+            writeln!(out, "{offset:010} 00000 n ")?;
+        }
+        writeln!(
+            out,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF",
+            offsets.len() + 1
+        )?;
+
+        w.write_all(&out)?;
+        Ok(())
+    }
+}
+
+/// Escape PDF string, characters outside Latin-1 become '?'
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            '\u{a0}'..='\u{ff}' => write!(out, "\\{:03o}", c as u32).unwrap(),
+            _ => out.push('?'),
+        }
+    }
+    out
+}
@@ -0,0 +1,413 @@
+use std::{collections::HashMap, io::Write};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use image::{RgbImage, codecs::jpeg::JpegEncoder};
+use imageproc::point::Point;
+use tracing::error;
+
+use crate::{
+    hits::storage::{HitQuery, HitStorage},
+    report::{pdf::PdfCanvas, svg::SvgCanvas},
+    targets::settings::Target,
+};
+
+/// A4 in points, also used as SVG user units
+pub const PAGE_WIDTH: f32 = 595.0;
+pub const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 40.0;
+const PLOT_SIZE: f32 = 240.0;
+const HIT_RADIUS: f32 = 3.5;
+const ROW_HEIGHT: f32 = 14.0;
+/// Longer preview side is scaled down to this before embedding
+const PREVIEW_MAX_SIZE: u32 = 800;
+
+const HIT_COLOR: [u8; 3] = [220, 0, 0];
+const FLYER_COLOR: [u8; 3] = [140, 140, 140];
+const CENTER_COLOR: [u8; 3] = [0, 90, 220];
+const TEXT_COLOR: [u8; 3] = [0, 0, 0];
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ReportFormat {
+    Svg,
+    /// Uses built-in Helvetica, characters outside Latin-1 such as Cyrillic or CJK names print
+    /// as '?', SVG shows them
+    Pdf,
+}
+
+impl ReportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Svg => "svg",
+            ReportFormat::Pdf => "pdf",
+        }
+    }
+}
+
+/// One shot in report table
+pub struct ShotRow {
+    pub timestamp: DateTime<Local>,
+    pub target: Option<String>,
+    pub zone: Option<u8>,
+    pub score: Option<f32>,
+    /// Seconds since previous shot, from shot timer if it ran
+    pub split: Option<f32>,
+    pub flyer: bool,
+}
+
+/// Spread of hit group, distances relative to target width
+pub struct GroupStats {
+    pub count: usize,
+    pub center: Point<f32>,
+    pub extreme_spread: f32,
+    pub mean_radius: f32,
+}
+
+/// Hits of session on one target face
+pub struct TargetPlot {
+    pub name: String,
+    pub preview: Option<RgbImage>,
+    /// Normalized hit positions and whether shot was called flyer
+    pub hits: Vec<(Point<f32>, bool)>,
+    /// Group statistics without flyers
+    pub stats: Option<GroupStats>,
+}
+
+pub struct SessionReport {
+    pub session: String,
+    pub shooter: String,
+    pub started_at: DateTime<Local>,
+    pub shots: Vec<ShotRow>,
+    pub total_score: f32,
+    pub plots: Vec<TargetPlot>,
+}
+
+/// Drawing operations the report layout needs, origin top left of current page
+pub trait Canvas {
+    fn new_page(&mut self);
+    /// Text with baseline at y
+    fn text(&mut self, x: f32, y: f32, size: f32, text: &str);
+    fn line(&mut self, from: (f32, f32), to: (f32, f32), color: [u8; 3]);
+    fn circle(&mut self, center: (f32, f32), radius: f32, fill: [u8; 3]);
+    /// JPEG image of `pixels` size stretched over rectangle
+    fn image(&mut self, x: f32, y: f32, width: f32, height: f32, jpeg: Vec<u8>, pixels: (u32, u32));
+    fn finish(self, w: &mut dyn Write) -> Result<()>;
+}
+
+/// Collect hits of session with shooter, target previews and group statistics
+pub fn build_report(
+    storage: &mut dyn HitStorage,
+    targets: &HashMap<String, Target>,
+    session_id: &str,
+) -> Result<SessionReport> {
+    let session = storage
+        .load_session(session_id)?
+        .with_context(|| format!("unknown session '{session_id}'"))?;
+    let hits = storage.query_hits(&HitQuery {
+        session: Some(session_id.to_string()),
+        ..Default::default()
+    })?;
+
+    let target_name = |id: &Option<String>| match id {
+        Some(id) => targets.get(id).map_or(id.clone(), |t| t.name().clone()),
+        None => "Unknown target".to_string(),
+    };
+
+    let mut shots = Vec::new();
+    let mut plots: Vec<(Option<String>, TargetPlot)> = Vec::new();
+    let mut previous: Option<DateTime<Local>> = None;
+    for (timestamp, hit) in &hits {
+        let processed = hit.processed.as_ref();
+        let split = match &hit.shot_timing {
+            Some(timing) => timing.split,
+            None => previous.map(|p| (*timestamp - p).as_seconds_f32()),
+        };
+        previous = Some(*timestamp);
+        shots.push(ShotRow {
+            timestamp: *timestamp,
+            target: hit.target_id.as_ref().map(|_| target_name(&hit.target_id)),
+            zone: processed.and_then(|p| p.zone),
+            score: processed.map(|p| p.score),
+            split,
+            flyer: hit.flyer,
+        });
+
+        let Some(pos) = processed.and_then(|p| p.hit_pos_norm.as_ref()) else {
+            continue;
+        };
+        let index = match plots.iter().position(|(id, _)| *id == hit.target_id) {
+            Some(index) => index,
+            None => {
+                let preview = hit
+                    .target_id
+                    .as_ref()
+                    .and_then(|id| targets.get(id))
+                    .and_then(|t| {
+                        image::open(t.preview_path())
+                            .inspect_err(|e| {
+                                error!("failed to load preview of '{}': {e:?}", t.id())
+                            })
+                            .ok()
+                    })
+                    .map(|img| img.to_rgb8());
+                plots.push((
+                    hit.target_id.clone(),
+                    TargetPlot {
+                        name: target_name(&hit.target_id),
+                        preview,
+                        hits: Vec::new(),
+                        stats: None,
+                    },
+                ));
+                plots.len() - 1
+            }
+        };
+        plots[index].1.hits.push((**pos, hit.flyer));
+    }
+
+    let plots = plots
+        .into_iter()
+        .map(|(_, mut plot)| {
+            let aspect = plot
+                .preview
+                .as_ref()
+                .map_or(1.0, |p| p.height() as f32 / p.width() as f32);
+            let counted: Vec<_> = plot
+                .hits
+                .iter()
+                .filter(|(_, flyer)| !flyer)
+                .map(|(p, _)| *p)
+                .collect();
+            plot.stats = group_stats(&counted, aspect);
+            plot
+        })
+        .collect();
+
+    Ok(SessionReport {
+        session: session.id,
+        shooter: session.shooter,
+        started_at: session.started_at,
+        total_score: shots.iter().filter_map(|s| s.score).sum(),
+        shots,
+        plots,
+    })
+}
+
+/// Center, extreme spread and mean radius of normalized points on target of height/width `aspect`
+pub fn group_stats(points: &[Point<f32>], aspect: f32) -> Option<GroupStats> {
+    if points.is_empty() {
+        return None;
+    }
+    // Same unit on both axes, so distances are fractions of target width
+    let scaled: Vec<_> = points
+        .iter()
+        .map(|p| Point::new(p.x, p.y * aspect))
+        .collect();
+    let dist = |a: &Point<f32>, b: &Point<f32>| (a.x - b.x).hypot(a.y - b.y);
+
+    let n = scaled.len() as f32;
+    let center = Point::new(
+        scaled.iter().map(|p| p.x).sum::<f32>() / n,
+        scaled.iter().map(|p| p.y).sum::<f32>() / n,
+    );
+    let mut extreme_spread = 0.0f32;
+    for (i, a) in scaled.iter().enumerate() {
+        for b in &scaled[i + 1..] {
+            extreme_spread = extreme_spread.max(dist(a, b));
+        }
+    }
+    let mean_radius = scaled.iter().map(|p| dist(p, &center)).sum::<f32>() / n;
+
+    Some(GroupStats {
+        count: points.len(),
+        center: Point::new(center.x, center.y / aspect),
+        extreme_spread,
+        mean_radius,
+    })
+}
+
+pub fn write_report(report: &SessionReport, format: ReportFormat, mut w: impl Write) -> Result<()> {
+    match format {
+        ReportFormat::Svg => {
+            let mut canvas = SvgCanvas::default();
+            layout(report, &mut canvas)?;
+            canvas.finish(&mut w)?;
+        }
+        ReportFormat::Pdf => {
+            let mut canvas = PdfCanvas::default();
+            layout(report, &mut canvas)?;
+            canvas.finish(&mut w)?;
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Header, one plot with statistics per target, then table of every shot
+fn layout(report: &SessionReport, canvas: &mut impl Canvas) -> Result<()> {
+    canvas.new_page();
+    let mut y = MARGIN + 18.0;
+    canvas.text(MARGIN, y, 18.0, &format!("Session {}", report.session));
+    y += 20.0;
+    canvas.text(
+        MARGIN,
+        y,
+        11.0,
+        &format!(
+            "{}, {}",
+            report.shooter,
+            report.started_at.format("%Y-%m-%d %H:%M")
+        ),
+    );
+    y += 15.0;
+    canvas.text(
+        MARGIN,
+        y,
+        11.0,
+        &format!(
+            "{} shots, total score {}",
+            report.shots.len(),
+            report.total_score
+        ),
+    );
+    y += 20.0;
+
+    for plot in &report.plots {
+        if y + PLOT_SIZE + 20.0 > PAGE_HEIGHT - MARGIN {
+            canvas.new_page();
+            y = MARGIN;
+        }
+        canvas.text(MARGIN, y + 10.0, 12.0, &plot.name);
+        y += 16.0;
+        draw_plot(canvas, plot, MARGIN, y)?;
+        draw_stats(canvas, plot, MARGIN + PLOT_SIZE + 20.0, y + 12.0);
+        y += PLOT_SIZE + 20.0;
+    }
+
+    let header = TABLE_COLUMNS.map(|(title, _)| title.to_string());
+    y += 10.0;
+    draw_row(canvas, y, &header);
+    canvas.line(
+        (MARGIN, y + 4.0),
+        (PAGE_WIDTH - MARGIN, y + 4.0),
+        TEXT_COLOR,
+    );
+    y += ROW_HEIGHT + 4.0;
+    for (i, shot) in report.shots.iter().enumerate() {
+        if y > PAGE_HEIGHT - MARGIN {
+            canvas.new_page();
+            y = MARGIN + ROW_HEIGHT;
+            draw_row(canvas, y, &header);
+            canvas.line(
+                (MARGIN, y + 4.0),
+                (PAGE_WIDTH - MARGIN, y + 4.0),
+                TEXT_COLOR,
+            );
+            y += ROW_HEIGHT + 4.0;
+        }
+        let fields = [
+            (i + 1).to_string(),
+            shot.timestamp.format("%H:%M:%S%.3f").to_string(),
+            shot.target.clone().unwrap_or_default(),
+            opt(shot.zone),
+            opt(shot.score),
+            opt(shot.split.map(|s| format!("{s:.2}"))),
+            if shot.flyer { "flyer" } else { "" }.to_string(),
+        ];
+        draw_row(canvas, y, &fields);
+        y += ROW_HEIGHT;
+    }
+    Ok(())
+}
+
+/// Column titles and x offsets from margin
+const TABLE_COLUMNS: [(&str, f32); 7] = [
+    ("#", 0.0),
+    ("Time", 30.0),
+    ("Target", 110.0),
+    ("Zone", 290.0),
+    ("Score", 330.0),
+    ("Split (s)", 380.0),
+    ("", 450.0),
+];
+
+fn draw_row(canvas: &mut impl Canvas, y: f32, fields: &[String; 7]) {
+    for ((_, x), field) in TABLE_COLUMNS.iter().zip(fields) {
+        canvas.text(MARGIN + x, y, 9.0, field);
+    }
+}
+
+fn opt<T: ToString>(v: Option<T>) -> String {
+    v.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Target preview fitted into plot square with hits and group center on top
+fn draw_plot(canvas: &mut impl Canvas, plot: &TargetPlot, x: f32, y: f32) -> Result<()> {
+    let (width, height) = match &plot.preview {
+        Some(preview) => {
+            let scale = PLOT_SIZE / preview.width().max(preview.height()) as f32;
+            let (width, height) = (
+                preview.width() as f32 * scale,
+                preview.height() as f32 * scale,
+            );
+            let preview = image::imageops::thumbnail(
+                preview,
+                (PREVIEW_MAX_SIZE as f32 * width / PLOT_SIZE) as u32,
+                (PREVIEW_MAX_SIZE as f32 * height / PLOT_SIZE) as u32,
+            );
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, 85).encode_image(&preview)?;
+            canvas.image(x, y, width, height, jpeg, preview.dimensions());
+            (width, height)
+        }
+        None => {
+            let corners = [
+                (x, y),
+                (x + PLOT_SIZE, y),
+                (x + PLOT_SIZE, y + PLOT_SIZE),
+                (x, y + PLOT_SIZE),
+            ];
+            for i in 0..4 {
+                canvas.line(corners[i], corners[(i + 1) % 4], TEXT_COLOR);
+            }
+            (PLOT_SIZE, PLOT_SIZE)
+        }
+    };
+
+    let at = |p: &Point<f32>| (x + p.x * width, y + p.y * height);
+    for (pos, flyer) in &plot.hits {
+        let color = if *flyer { FLYER_COLOR } else { HIT_COLOR };
+        canvas.circle(at(pos), HIT_RADIUS, color);
+    }
+    if let Some(stats) = &plot.stats {
+        let (cx, cy) = at(&stats.center);
+        canvas.line((cx - 6.0, cy), (cx + 6.0, cy), CENTER_COLOR);
+        canvas.line((cx, cy - 6.0), (cx, cy + 6.0), CENTER_COLOR);
+    }
+    Ok(())
+}
+
+fn draw_stats(canvas: &mut impl Canvas, plot: &TargetPlot, x: f32, mut y: f32) {
+    let flyers = plot.hits.iter().filter(|(_, flyer)| *flyer).count();
+    let mut lines = vec![format!("{} hits, {flyers} flyers", plot.hits.len())];
+    match &plot.stats {
+        Some(stats) => lines.extend([
+            format!("Group of {} hits", stats.count),
+            format!(
+                "Extreme spread {:.1}% of target width",
+                stats.extreme_spread * 100.0
+            ),
+            format!("Mean radius {:.1}%", stats.mean_radius * 100.0),
+            format!(
+                "Center {:.1}% / {:.1}% from top left",
+                stats.center.x * 100.0,
+                stats.center.y * 100.0
+            ),
+        ]),
+        None => lines.push("No group, every hit was a flyer".to_string()),
+    }
+    for line in lines {
+        canvas.text(x, y, 10.0, &line);
+        y += 14.0;
+    }
+}
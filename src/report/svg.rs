@@ -0,0 +1,121 @@
+use std::{fmt::Write as _, io::Write};
+
+use anyhow::Result;
+
+use crate::report::sheet::{Canvas, PAGE_HEIGHT, PAGE_WIDTH};
+
+/// Pages are stacked top to bottom in one document
+#[derive(Default)]
+pub struct SvgCanvas {
+    body: String,
+    pages: usize,
+}
+
+impl SvgCanvas {
+    /// Y offset of current page
+    fn top(&self) -> f32 {
+        self.pages.saturating_sub(1) as f32 * PAGE_HEIGHT
+    }
+}
+
+impl Canvas for SvgCanvas {
+    fn new_page(&mut self) {
+        self.pages += 1;
+        let top = self.top();
+        if self.pages > 1 {
+            writeln!(
+                self.body,
+                r##"<line x1="0" y1="{top}" x2="{PAGE_WIDTH}" y2="{top}" stroke="#ccc" stroke-dasharray="4"/>"##
+            )
+            .unwrap();
+        }
+    }
+
+    fn text(&mut self, x: f32, y: f32, size: f32, text: &str) {
+        writeln!(
+            self.body,
+            r#"<text x="{x}" y="{}" font-size="{size}">{}</text>"#,
+            self.top() + y,
+            escape(text)
+        )
+        .unwrap();
+    }
+
+    fn line(&mut self, from: (f32, f32), to: (f32, f32), color: [u8; 3]) {
+        let top = self.top();
+        writeln!(
+            self.body,
+            r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}"/>"#,
+            from.0,
+            top + from.1,
+            to.0,
+            top + to.1,
+            rgb(color)
+        )
+        .unwrap();
+    }
+
+    fn circle(&mut self, center: (f32, f32), radius: f32, fill: [u8; 3]) {
+        writeln!(
+            self.body,
+            r#"<circle cx="{}" cy="{}" r="{radius}" fill="{}" stroke="white"/>"#,
+            center.0,
+            self.top() + center.1,
+            rgb(fill)
+        )
+        .unwrap();
+    }
+
+    fn image(&mut self, x: f32, y: f32, width: f32, height: f32, jpeg: Vec<u8>, _: (u32, u32)) {
+        writeln!(
+            self.body,
+            r#"<image x="{x}" y="{}" width="{width}" height="{height}" preserveAspectRatio="none" href="data:image/jpeg;base64,{}"/>"#,
+            self.top() + y,
+            base64(&jpeg)
+        )
+        .unwrap();
+    }
+
+    fn finish(self, w: &mut dyn Write) -> Result<()> {
+        let height = self.pages.max(1) as f32 * PAGE_HEIGHT;
+        writeln!(
+            w,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{PAGE_WIDTH}pt" height="{height}pt" viewBox="0 0 {PAGE_WIDTH} {height}" font-family="Helvetica, Arial, sans-serif">"#
+        )?;
+        writeln!(w, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+        w.write_all(self.body.as_bytes())?;
+        writeln!(w, "</svg>")?;
+        Ok(())
+    }
+}
+
+fn rgb([r, g, b]: [u8; 3]) -> String {
+    format!("rgb({r},{g},{b})")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
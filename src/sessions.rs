@@ -0,0 +1,31 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::drills::DrillOutcome;
+
+/// Milliseconds keep sessions started in quick succession apart
+const SESSION_ID_FORMAT: &str = "%Y-%m-%d_%H-%M-%S-%3f";
+
+/// Training session of single shooter
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Session {
+    pub id: String,
+    pub shooter: String,
+    pub started_at: DateTime<Local>,
+    pub ended_at: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub drills: Vec<DrillOutcome>,
+}
+
+impl Session {
+    pub fn start(shooter: String) -> Self {
+        let started_at = Local::now();
+        Self {
+            id: started_at.format(SESSION_ID_FORMAT).to_string(),
+            shooter,
+            started_at,
+            ended_at: None,
+            drills: Vec::new(),
+        }
+    }
+}
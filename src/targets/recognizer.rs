@@ -2,24 +2,31 @@ use std::{
     ptr::addr_eq,
     sync::{
         Arc, RwLock,
-        mpsc::{self, Sender},
+        mpsc::{self, RecvTimeoutError, Sender},
     },
     time::{Duration, Instant},
 };
 
-use image::buffer::ConvertBuffer;
+use image::{GrayImage, RgbImage, buffer::ConvertBuffer};
 use imageproc::edges::canny;
+use serde_json::json;
 use tracing::info;
 
 use crate::{
+    bus::{CommandReply, Event, send_reply},
     capturer::CapturedFrame,
     targets::TargetInfo,
     vision::{frame::find_rectangle_vertices, zones::ZoneMap},
 };
 
-pub enum TargetRecognizerCommand {}
+pub enum TargetRecognizerCommand {
+    /// Recognize without waiting for interval, even if frame did not change
+    RecognizeNow { reply: Option<CommandReply> },
+}
 
 pub fn start_target_recognizer(
+    bus_tx: Sender<Event>,
+    lane: String,
     target_info_share: Arc<RwLock<Option<TargetInfo>>>,
     zone_map: Arc<RwLock<Option<ZoneMap>>>,
     last_camera_frame: Arc<RwLock<Option<Arc<CapturedFrame>>>>,
@@ -30,39 +37,69 @@ pub fn start_target_recognizer(
         let recognition_interval = Duration::from_secs(5);
         let mut processed_frame: Option<Arc<CapturedFrame>> = None;
         loop {
-            while last_recognition_at.elapsed() < recognition_interval {
-                if rx
-                    .recv_timeout(recognition_interval - last_recognition_at.elapsed())
-                    .is_ok()
-                {
-                    info!("Command received");
+            let mut forced = false;
+            let mut reply = None;
+            while !forced && last_recognition_at.elapsed() < recognition_interval {
+                match rx.recv_timeout(
+                    recognition_interval.saturating_sub(last_recognition_at.elapsed()),
+                ) {
+                    Ok(TargetRecognizerCommand::RecognizeNow { reply: asked }) => {
+                        info!("Recognizing target of lane '{lane}' on request");
+                        forced = true;
+                        reply = asked;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // Owner is gone
+                    Err(RecvTimeoutError::Disconnected) => return,
                 }
             }
 
             let frame = match last_camera_frame.read().unwrap().clone() {
                 Some(v) => v,
-                None => continue,
+                None => {
+                    send_reply(reply, Err("no camera frame yet".to_string()));
+                    continue;
+                }
             };
 
-            if let Some(processed_frame) = &mut processed_frame
+            if !forced
+                && let Some(processed_frame) = &mut processed_frame
                 && addr_eq(&*processed_frame, &*frame)
             {
                 continue;
             }
             processed_frame = Some(frame.clone());
 
-            let gray = frame.image.convert();
-            let edges = canny(&gray, 50.0, 100.0);
-            let contours = imageproc::contours::find_contours::<u32>(&edges);
-
-            if let Some(rect) = find_rectangle_vertices(&contours) {
-                *target_info_share.write().unwrap() = Some(TargetInfo { rect });
+            let recognized = recognize_target(&frame.image);
+            send_reply(
+                reply,
+                match &recognized {
+                    Some(target_info) => Ok(json!({ "lane": lane, "target": target_info })),
+                    None => Err("no target found on frame".to_string()),
+                },
+            );
+            if let Some(target_info) = recognized {
+                *target_info_share.write().unwrap() = Some(target_info.clone());
+                bus_tx
+                    .send(Event::TargetRecognized {
+                        lane: lane.clone(),
+                        target_info,
+                    })
+                    .expect("failed to send recognized target");
             }
 
-            // *zone_map.write().unwrap() = Some(ZoneMap::recognize(&gray));
+            // *zone_map.write().unwrap() = Some(ZoneMap::recognize(&frame.image.convert()));
 
             last_recognition_at = Instant::now();
         }
     });
     tx
 }
+
+/// Find target outline on image, none if no rectangle is seen
+pub fn recognize_target(image: &RgbImage) -> Option<TargetInfo> {
+    let gray: GrayImage = image.convert();
+    let edges = canny(&gray, 50.0, 100.0);
+    let contours = imageproc::contours::find_contours::<u32>(&edges);
+    find_rectangle_vertices(&contours).map(|rect| TargetInfo { rect })
+}
@@ -8,12 +8,13 @@ use std::{
 
 use anyhow::Result;
 use image::{GrayImage, ImageReader, RgbImage};
+use imageproc::point::Point;
 use serde::Deserialize;
 use tracing::error;
 
 use crate::vision::zones::ZoneMap;
 
-const TARGETS_PATH: &str = "targets";
+pub const TARGETS_PATH: &str = "targets";
 const TARGET_PREVIEW_PATH: &str = "preview.png";
 const TARGET_ZONEMAP_PATH: &str = "zonemap.png";
 const TARGET_DATA_PATH: &str = "data.json";
@@ -55,8 +56,9 @@ impl Target {
             return Ok(loaded_zonemap);
         }
 
-        let dir = self.dir();
-        let zonemap_img: GrayImage = ImageReader::open(dir)?.decode()?.into();
+        let zonemap_img: GrayImage = ImageReader::open(self.dir().join(TARGET_ZONEMAP_PATH))?
+            .decode()?
+            .into();
         let zonemap = Arc::new(ZoneMap::load(zonemap_img));
 
         *loaded_zonemap = Arc::<ZoneMap>::downgrade(&zonemap);
@@ -76,6 +78,26 @@ impl Target {
         &self.id
     }
 
+    /// Score of zone, zero for unknown zones
+    pub fn zone_score(&self, zone: u8) -> u32 {
+        self.zone_scores.get(&zone).copied().unwrap_or(0)
+    }
+
+    /// Zone and its score at normalized position on target
+    pub fn score_at(&self, pos: Point<f32>) -> Result<(u8, u32)> {
+        if !(0.0..1.0).contains(&pos.x) || !(0.0..1.0).contains(&pos.y) {
+            return Ok((0, 0));
+        }
+
+        let zonemap = self.load_zonemap()?;
+        let (width, height) = zonemap.map().dimensions();
+        let zone = zonemap.at(Point::new(
+            (pos.x * width as f32) as u32,
+            (pos.y * height as f32) as u32,
+        ));
+        Ok((zone, self.zone_score(zone)))
+    }
+
     pub fn preview_path(&self) -> PathBuf {
         Path::new(TARGETS_PATH)
             .join(&self.id)
@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use rodio::{OutputStream, Sink, Source, source::SineWave};
+use tracing::error;
+
+const BEEP_FREQUENCY: f32 = 2000.0;
+const BEEP_DURATION: Duration = Duration::from_millis(400);
+const BEEP_VOLUME: f32 = 0.5;
+
+fn play_beep() -> Result<()> {
+    let (_stream, handle) = OutputStream::try_default()?;
+    let sink = Sink::try_new(&handle)?;
+    sink.append(
+        SineWave::new(BEEP_FREQUENCY)
+            .take_duration(BEEP_DURATION)
+            .amplify(BEEP_VOLUME),
+    );
+    sink.sleep_until_end();
+    Ok(())
+}
+
+/// Play start signal beep without blocking caller
+pub fn play_start_signal() {
+    std::thread::spawn(|| {
+        if let Err(e) = play_beep() {
+            error!("failed to play start signal: {e:?}");
+        }
+    });
+}
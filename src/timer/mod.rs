@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+pub mod cue;
+pub mod runner;
+
+/// String ends once no shot came for this long after start signal, par or last shot
+const STRING_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct ShotTimerConfig {
+    /// Random start delay bounds
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// Shots slower than par (since start signal) are flagged late
+    pub par: Option<Duration>,
+}
+
+impl ShotTimerConfig {
+    /// Pick random start delay within configured bounds
+    pub fn random_delay(&self) -> Duration {
+        let min = self.min_delay.as_millis() as u64;
+        let max = (self.max_delay.as_millis() as u64).max(min);
+        Duration::from_millis(rand::random_range(min..=max))
+    }
+}
+
+/// Timing of single shot relative to start signal, in seconds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShotTiming {
+    /// 1-based shot number in string
+    pub number: u32,
+    /// Time from start signal
+    pub since_start: f32,
+    /// Time from previous shot, none for first shot
+    pub split: Option<f32>,
+    /// Shot came after par time
+    pub late: bool,
+}
+
+/// Running shot timer string
+pub struct ShotTimer {
+    started_at: DateTime<Local>,
+    par: Option<TimeDelta>,
+    shots: Vec<(DateTime<Local>, ShotTiming)>,
+}
+
+impl ShotTimer {
+    pub fn start(started_at: DateTime<Local>, par: Option<Duration>) -> Self {
+        Self {
+            started_at,
+            par: par.and_then(|v| TimeDelta::from_std(v).ok()),
+            shots: Vec::new(),
+        }
+    }
+
+    pub fn started_at(&self) -> DateTime<Local> {
+        self.started_at
+    }
+
+    /// Record shot at frame timestamp, none if shot came before start signal
+    pub fn record(&mut self, shot_at: DateTime<Local>) -> Option<ShotTiming> {
+        if shot_at < self.started_at {
+            return None;
+        }
+
+        let since_start = shot_at - self.started_at;
+        let split = self
+            .shots
+            .last()
+            .map(|(last_at, _)| seconds(shot_at - *last_at));
+        let timing = ShotTiming {
+            number: self.shots.len() as u32 + 1,
+            since_start: seconds(since_start),
+            split,
+            late: self.par.is_some_and(|par| since_start > par),
+        };
+        self.shots.push((shot_at, timing.clone()));
+        Some(timing)
+    }
+
+    /// When string is over if no further shot comes
+    pub fn ends_at(&self) -> DateTime<Local> {
+        let par_end = self.started_at + self.par.unwrap_or_default();
+        let last_shot = self.shots.last().map_or(self.started_at, |(at, _)| *at);
+        par_end.max(last_shot) + TimeDelta::from_std(STRING_TIMEOUT).unwrap()
+    }
+}
+
+fn seconds(delta: TimeDelta) -> f32 {
+    delta.num_microseconds().unwrap_or(i64::MAX) as f32 / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(start: DateTime<Local>, millis: i64) -> DateTime<Local> {
+        start + TimeDelta::milliseconds(millis)
+    }
+
+    #[test]
+    fn random_delay_stays_within_bounds() {
+        let config = ShotTimerConfig {
+            min_delay: Duration::from_millis(1500),
+            max_delay: Duration::from_millis(1600),
+            par: None,
+        };
+        for _ in 0..200 {
+            let delay = config.random_delay();
+            assert!(delay >= config.min_delay && delay <= config.max_delay);
+        }
+    }
+
+    #[test]
+    fn random_delay_below_min_uses_min() {
+        let config = ShotTimerConfig {
+            min_delay: Duration::from_secs(3),
+            max_delay: Duration::from_secs(1),
+            par: None,
+        };
+        assert_eq!(config.random_delay(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn first_shot_has_time_and_later_ones_splits() {
+        let start = Local::now();
+        let mut timer = ShotTimer::start(start, None);
+        assert!(timer.record(at(start, -100)).is_none());
+
+        let first = timer.record(at(start, 1200)).unwrap();
+        assert_eq!(first.number, 1);
+        assert_eq!(first.since_start, 1.2);
+        assert_eq!(first.split, None);
+
+        let second = timer.record(at(start, 1450)).unwrap();
+        assert_eq!(second.number, 2);
+        assert_eq!(second.since_start, 1.45);
+        assert_eq!(second.split, Some(0.25));
+    }
+
+    #[test]
+    fn shots_after_par_are_late() {
+        let start = Local::now();
+        let mut timer = ShotTimer::start(start, Some(Duration::from_secs(2)));
+        assert!(!timer.record(at(start, 2000)).unwrap().late);
+        assert!(timer.record(at(start, 2001)).unwrap().late);
+        assert!(
+            !ShotTimer::start(start, None)
+                .record(at(start, 60_000))
+                .unwrap()
+                .late
+        );
+    }
+
+    #[test]
+    fn string_ends_after_latest_of_start_par_and_last_shot() {
+        let start = Local::now();
+        let timeout = TimeDelta::from_std(STRING_TIMEOUT).unwrap();
+
+        let mut timer = ShotTimer::start(start, None);
+        assert_eq!(timer.ends_at(), start + timeout);
+        timer.record(at(start, 3000));
+        assert_eq!(timer.ends_at(), at(start, 3000) + timeout);
+
+        let mut timer = ShotTimer::start(start, Some(Duration::from_secs(5)));
+        timer.record(at(start, 3000));
+        assert_eq!(timer.ends_at(), at(start, 5000) + timeout);
+        timer.record(at(start, 7000));
+        assert_eq!(timer.ends_at(), at(start, 7000) + timeout);
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    time::Instant,
+};
+
+use chrono::{DateTime, Local};
+use serde_json::json;
+use tracing::info;
+
+use crate::{
+    bus::{CommandReply, Event, send_reply},
+    timer::{ShotTimer, ShotTimerConfig, cue::play_start_signal},
+};
+
+pub enum ShotTimerCommand {
+    Start {
+        config: ShotTimerConfig,
+        reply: Option<CommandReply>,
+    },
+    Stop {
+        reply: Option<CommandReply>,
+    },
+    Shot {
+        timestamp: DateTime<Local>,
+    },
+}
+
+enum State {
+    Idle,
+    Waiting {
+        start_at: Instant,
+        config: ShotTimerConfig,
+    },
+    Running(ShotTimer),
+}
+
+pub fn start_shot_timer(bus_tx: Sender<Event>) -> Sender<ShotTimerCommand> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut state = State::Idle;
+        loop {
+            let msg = match &state {
+                State::Waiting { start_at, .. } => {
+                    match rx.recv_timeout(start_at.saturating_duration_since(Instant::now())) {
+                        Ok(v) => Some(v),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                State::Running(timer) => {
+                    let left = (timer.ends_at() - Local::now())
+                        .to_std()
+                        .unwrap_or_default();
+                    match rx.recv_timeout(left) {
+                        Ok(v) => Some(v),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                State::Idle => match rx.recv() {
+                    Ok(v) => Some(v),
+                    Err(_) => break,
+                },
+            };
+
+            match msg {
+                None if matches!(state, State::Running(_)) => {
+                    info!("Shot timer string ended");
+                    state = State::Idle;
+                    bus_tx
+                        .send(Event::ShotTimerEnded)
+                        .expect("failed to send shot timer end");
+                }
+                None => {
+                    // Start delay elapsed
+                    let State::Waiting { config, .. } = state else {
+                        unreachable!()
+                    };
+                    let timer = ShotTimer::start(Local::now(), config.par);
+                    play_start_signal();
+                    info!("Shot timer started at {}", timer.started_at());
+                    bus_tx
+                        .send(Event::ShotTimerStarted {
+                            at: timer.started_at(),
+                        })
+                        .expect("failed to send shot timer start");
+                    state = State::Running(timer);
+                }
+                Some(ShotTimerCommand::Start { config, reply }) => {
+                    let delay = config.random_delay();
+                    info!("Shot timer armed, start in {delay:?}");
+                    send_reply(reply, Ok(json!({ "delay": delay.as_secs_f32() })));
+                    state = State::Waiting {
+                        start_at: Instant::now() + delay,
+                        config,
+                    };
+                }
+                Some(ShotTimerCommand::Stop { reply }) => {
+                    if let State::Idle = state {
+                        send_reply(reply, Err("shot timer is not running".to_string()));
+                        continue;
+                    }
+                    send_reply(reply, Ok(serde_json::Value::Null));
+                    state = State::Idle;
+                }
+                Some(ShotTimerCommand::Shot { timestamp }) => {
+                    let State::Running(timer) = &mut state else {
+                        continue;
+                    };
+                    if let Some(timing) = timer.record(timestamp) {
+                        bus_tx
+                            .send(Event::ShotTimed { timestamp, timing })
+                            .expect("failed to send shot timing");
+                    }
+                }
+            }
+        }
+    });
+    tx
+}
@@ -0,0 +1,15 @@
+use imageproc::point::Point;
+
+/// Check if point lies inside polygon (even-odd rule)
+pub fn polygon_contains(polygon: &[Point<f32>], p: Point<f32>) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len().wrapping_sub(1);
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[j]);
+        if (a.y > p.y) != (b.y > p.y) && p.x < (b.x - a.x) * (p.y - a.y) / (b.y - a.y) + a.x {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
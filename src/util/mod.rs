@@ -1 +1,2 @@
+pub mod geometry;
 pub mod point;
@@ -1,6 +1,7 @@
 use image::{GenericImageView, RgbImage, SubImage, math::Rect};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Stencil {
     start_x: f32,
     start_y: f32,
@@ -24,6 +25,11 @@ impl Stencil {
         img.view(r.x, r.y, r.width, r.height)
     }
 
+    /// Stencil bounds as (start_x, start_y, end_x, end_y) fractions of frame size
+    pub fn bounds(&self) -> (f32, f32, f32, f32) {
+        (self.start_x, self.start_y, self.end_x, self.end_y)
+    }
+
     pub fn rect(&self, width: u32, height: u32) -> Rect {
         let (width, height) = (width as f32, height as f32);
         Rect {